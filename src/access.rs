@@ -0,0 +1,221 @@
+use crate::proxy::Proxy;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+
+/// An address range, `10.0.0.0/8`, `fd00::/8` or a single address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl TryFrom<&str> for Cidr {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid cidr {}", value);
+        let (addr, prefix) = match value.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// ::ffff:10.0.0.1 is 10.0.0.1
+fn canonical(addr: &IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(*v6),
+        },
+        addr => *addr,
+    }
+}
+
+fn is_internal_v4(addr: &Ipv4Addr) -> bool {
+    let [a, b, ..] = addr.octets();
+    addr.is_loopback()
+        || addr.is_private()
+        // 169.254.0.0/16, with the cloud metadata 169.254.169.254
+        || addr.is_link_local()
+        || addr.is_unspecified()
+        || addr.is_broadcast()
+        // 224.0.0.0/4
+        || addr.is_multicast()
+        // 0.0.0.0/8, "this network"
+        || a == 0
+        // 100.64.0.0/10, carrier grade nat
+        || (a == 100 && (64..128).contains(&b))
+        // 198.18.0.0/15, benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+}
+
+// the ipv4 address a translated or tunneled ipv6 one reaches
+fn embedded_v4(addr: &Ipv6Addr) -> Option<Ipv4Addr> {
+    let s = addr.segments();
+    let octets = addr.octets();
+    let tail = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+    match s {
+        // 64:ff9b::/96, nat64
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(tail),
+        // 2002::/16, 6to4 with the address in the next 32 bits
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        // ::a.b.c.d, deprecated ipv4 compatible, not :: and ::1
+        [0, 0, 0, 0, 0, 0, ..] if !addr.is_loopback() && !addr.is_unspecified() => Some(tail),
+        _ => None,
+    }
+}
+
+fn is_internal_v6(addr: &Ipv6Addr) -> bool {
+    let first = addr.segments()[0];
+    addr.is_loopback()
+        || addr.is_unspecified()
+        // ff00::/8
+        || addr.is_multicast()
+        || embedded_v4(addr).is_some_and(|v4| is_internal_v4(&v4))
+        // fc00::/7 unique local, with the aws metadata fd00:ec2::254
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10 link local
+        || (first & 0xffc0) == 0xfe80
+        // fec0::/10 deprecated site local
+        || (first & 0xffc0) == 0xfec0
+}
+
+/// Loopback, private, link local and other non public addresses
+pub fn is_internal(addr: &IpAddr) -> bool {
+    match canonical(addr) {
+        IpAddr::V4(v4) => is_internal_v4(&v4),
+        IpAddr::V6(v6) => is_internal_v6(&v6),
+    }
+}
+
+/// Which addresses the browser may reach: the deny list always wins, the allow
+/// list opens internal ranges, anything else internal is denied unless allow_private
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    pub allow_private: bool,
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl AccessPolicy {
+    pub fn check_ip(&self, addr: &IpAddr) -> Result<(), String> {
+        let embedded = match addr {
+            IpAddr::V6(v6) => embedded_v4(v6).map(IpAddr::V4),
+            IpAddr::V4(_) => None,
+        };
+        let denied = |addr: &IpAddr| self.deny.iter().any(|c| c.contains(addr));
+        if denied(addr) || embedded.as_ref().is_some_and(denied) {
+            return Err(format!("address {} is denied", addr));
+        }
+        if self.allow_private || self.allow.iter().any(|c| c.contains(addr)) {
+            return Ok(());
+        }
+        match is_internal(addr) {
+            true => Err(format!("internal address {} is not allowed", addr)),
+            false => Ok(()),
+        }
+    }
+
+    /// Nothing to check, every address is allowed
+    pub fn is_open(&self) -> bool {
+        self.allow_private && self.deny.is_empty()
+    }
+
+    /// Check the host of the url and every address it resolves to, urls
+    /// without a network host (data:, blob:, about:) are allowed
+    pub async fn check_url(&self, u: &url::Url) -> Result<(), String> {
+        if self.is_open() {
+            return Ok(());
+        }
+        let host = match u.host() {
+            Some(url::Host::Ipv4(addr)) => return self.check_ip(&IpAddr::V4(addr)),
+            Some(url::Host::Ipv6(addr)) => return self.check_ip(&IpAddr::V6(addr)),
+            Some(url::Host::Domain(host)) => host.trim_end_matches('.').to_lowercase(),
+            None => return Ok(()),
+        };
+        if !self.allow_private && (host == "localhost" || host.ends_with(".localhost")) {
+            return Err(format!("host {} is not allowed", host));
+        }
+
+        let port = u.port_or_known_default().unwrap_or(80);
+        // unresolvable hosts fail in the browser as well
+        let addrs = match tokio::net::lookup_host((host.as_str(), port)).await {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                log::debug!("resolve {} error: {}", host, e);
+                return Ok(());
+            }
+        };
+        self.check_resolved(&host, &addrs.collect::<Vec<_>>())
+    }
+}
+
+impl AccessPolicy {
+    /// Check the addresses a host resolved to, the caller connects to one of
+    /// them so the name can't resolve somewhere else in between
+    pub fn check_resolved(&self, host: &str, addrs: &[SocketAddr]) -> Result<(), String> {
+        if self.is_open() {
+            return Ok(());
+        }
+        if !self.allow_private && (host == "localhost" || host.ends_with(".localhost")) {
+            return Err(format!("host {} is not allowed", host));
+        }
+        for addr in addrs {
+            self.check_ip(&addr.ip())
+                .map_err(|e| format!("host {} resolves to {}", host, e))?;
+        }
+        Ok(())
+    }
+
+    /// A proxy of the client sees every request of the browser, its host must be reachable
+    pub async fn check_proxy(&self, proxy: &Proxy) -> Result<(), String> {
+        match proxy.url() {
+            Some(u) => self.check_url(&u).await.map_err(|e| format!("proxy {}", e)),
+            None => Err("invalid proxy host".to_string()),
+        }
+    }
+}
+
+/// Caches the verdict of each host for the lifetime of a page
+#[derive(Debug, Default)]
+pub struct HostCache(Mutex<HashMap<String, Result<(), String>>>);
+
+impl HostCache {
+    pub async fn check(&self, policy: &AccessPolicy, u: &url::Url) -> Result<(), String> {
+        let key = format!("{}:{:?}", u.host_str().unwrap_or_default(), u.port());
+        if let Some(r) = self.0.lock().unwrap().get(&key) {
+            return r.clone();
+        }
+        let r = policy.check_url(u).await;
+        self.0.lock().unwrap().insert(key, r.clone());
+        r
+    }
+}
@@ -0,0 +1,236 @@
+use chromiumoxide::{Element, Page};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+use tokio::time;
+
+const DEFAULT_TIMEOUT: u64 = 10 * 1000;
+const SLEEP_INTERVAL: u64 = 10;
+
+/// One interaction step run before capture, e.g.
+/// `{"type": "click", "selector": "#accept", "timeout": 3000}`
+#[derive(Debug, Deserialize)]
+pub struct ActionStep {
+    #[serde(flatten)]
+    action: Action,
+    // milliseconds, the step fails when exceeded
+    timeout: Option<u64>,
+    // a failed optional step is logged and skipped
+    optional: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Click {
+        selector: String,
+    },
+    Type {
+        selector: String,
+        text: String,
+    },
+    Press {
+        key: String,
+        selector: Option<String>,
+    },
+    Hover {
+        selector: String,
+    },
+    Select {
+        selector: String,
+        values: Vec<String>,
+    },
+    ScrollTo {
+        selector: Option<String>,
+        x: Option<f64>,
+        y: Option<f64>,
+    },
+    WaitForSelector {
+        selector: String,
+    },
+    WaitForNavigation,
+    WaitMs {
+        ms: u64,
+    },
+    Evaluate {
+        script: String,
+    },
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Click { .. } => "click",
+            Action::Type { .. } => "type",
+            Action::Press { .. } => "press",
+            Action::Hover { .. } => "hover",
+            Action::Select { .. } => "select",
+            Action::ScrollTo { .. } => "scroll_to",
+            Action::WaitForSelector { .. } => "wait_for_selector",
+            Action::WaitForNavigation => "wait_for_navigation",
+            Action::WaitMs { .. } => "wait_ms",
+            Action::Evaluate { .. } => "evaluate",
+        }
+    }
+}
+
+async fn find(page: &Page, selector: &str) -> Result<Element, String> {
+    page.find_element(selector)
+        .await
+        .map_err(|e| format!("{} not found: {}", selector, e))
+}
+
+async fn wait_for_selector(page: &Page, selector: &str) {
+    while page.find_element(selector).await.is_err() {
+        time::sleep(Duration::from_millis(SLEEP_INTERVAL)).await;
+    }
+}
+
+async fn run_action(page: &Page, action: &Action) -> Result<(), String> {
+    match action {
+        Action::Click { selector } => {
+            find(page, selector)
+                .await?
+                .click()
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Action::Type { selector, text } => {
+            find(page, selector)
+                .await?
+                .click()
+                .await
+                .map_err(|e| e.to_string())?
+                .type_str(text)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Action::Press { key, selector } => {
+            let element = match selector {
+                Some(selector) => {
+                    let element = find(page, selector).await?;
+                    element.focus().await.map_err(|e| e.to_string())?;
+                    element
+                }
+                // the key goes to whatever has the focus
+                None => find(page, "html").await?,
+            };
+            element.press_key(key).await.map_err(|e| e.to_string())?;
+        }
+        Action::Hover { selector } => {
+            find(page, selector)
+                .await?
+                .hover()
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Action::Select { selector, values } => {
+            let values = serde_json::to_string(values).map_err(|e| e.to_string())?;
+            let resp = find(page, selector)
+                .await?
+                .call_js_fn(
+                    format!(
+                        "function() {{
+                        if (!(this instanceof HTMLSelectElement)) return 'not a select element';
+                        const values = {values};
+                        for (const option of this.options) option.selected = values.includes(option.value);
+                        this.dispatchEvent(new Event('input', {{ bubbles: true }}));
+                        this.dispatchEvent(new Event('change', {{ bubbles: true }}));
+                        return '';
+                    }}"
+                    ),
+                    false,
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            match resp.result.value.as_ref().and_then(|v| v.as_str()) {
+                Some(err) if !err.is_empty() => return Err(err.to_string()),
+                _ => {}
+            }
+        }
+        Action::ScrollTo { selector, x, y } => match selector {
+            Some(selector) => {
+                find(page, selector)
+                    .await?
+                    .scroll_into_view()
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            None => {
+                page.evaluate(format!(
+                    "window.scrollTo({}, {})",
+                    x.unwrap_or_default(),
+                    y.unwrap_or_default()
+                ))
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+        },
+        Action::WaitForSelector { selector } => wait_for_selector(page, selector).await,
+        Action::WaitForNavigation => {
+            page.wait_for_navigation()
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Action::WaitMs { ms } => time::sleep(Duration::from_millis(*ms)).await,
+        Action::Evaluate { script } => {
+            page.evaluate(script.as_str())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the steps in order, the first failed step aborts with its index and type
+pub async fn run_actions(
+    cmd: &str,
+    url: &str,
+    page: &Page,
+    steps: &[ActionStep],
+) -> Result<(), String> {
+    for (index, step) in steps.iter().enumerate() {
+        let st = SystemTime::now();
+        let timeout = step.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let r = match time::timeout(
+            Duration::from_millis(timeout),
+            run_action(page, &step.action),
+        )
+        .await
+        {
+            Ok(r) => r,
+            Err(_) => Err(format!("timeout after {}ms", timeout)),
+        };
+
+        match r {
+            Ok(_) => {
+                log::info!(
+                    "{} {} action #{} {} done usage: {:?}",
+                    cmd,
+                    url,
+                    index,
+                    step.action.name(),
+                    st.elapsed().unwrap_or_default()
+                );
+            }
+            Err(e) if step.optional.unwrap_or_default() => {
+                log::warn!(
+                    "{} {} action #{} {} skipped: {}",
+                    cmd,
+                    url,
+                    index,
+                    step.action.name(),
+                    e
+                );
+            }
+            Err(e) => {
+                return Err(format!(
+                    "action #{} {} failed: {}",
+                    index,
+                    step.action.name(),
+                    e
+                ));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,435 @@
+use chromiumoxide::cdp::browser_protocol::network::ResourceType;
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+
+// request types of the filter options, as a bit mask
+const TYPE_SCRIPT: u16 = 1;
+const TYPE_IMAGE: u16 = 1 << 1;
+const TYPE_STYLESHEET: u16 = 1 << 2;
+const TYPE_XHR: u16 = 1 << 3;
+const TYPE_SUBDOCUMENT: u16 = 1 << 4;
+const TYPE_FONT: u16 = 1 << 5;
+const TYPE_MEDIA: u16 = 1 << 6;
+const TYPE_WEBSOCKET: u16 = 1 << 7;
+const TYPE_PING: u16 = 1 << 8;
+const TYPE_OTHER: u16 = 1 << 9;
+const TYPE_ALL: u16 = (1 << 10) - 1;
+
+fn type_mask(resource_type: &ResourceType) -> u16 {
+    match resource_type {
+        ResourceType::Script => TYPE_SCRIPT,
+        ResourceType::Image => TYPE_IMAGE,
+        ResourceType::Stylesheet => TYPE_STYLESHEET,
+        ResourceType::Xhr | ResourceType::Fetch | ResourceType::EventSource => TYPE_XHR,
+        ResourceType::Document => TYPE_SUBDOCUMENT,
+        ResourceType::Font => TYPE_FONT,
+        ResourceType::Media | ResourceType::TextTrack => TYPE_MEDIA,
+        ResourceType::WebSocket => TYPE_WEBSOCKET,
+        ResourceType::Ping | ResourceType::CspViolationReport => TYPE_PING,
+        _ => TYPE_OTHER,
+    }
+}
+
+fn option_mask(name: &str) -> Option<u16> {
+    match name {
+        "script" => Some(TYPE_SCRIPT),
+        "image" => Some(TYPE_IMAGE),
+        "stylesheet" => Some(TYPE_STYLESHEET),
+        "xmlhttprequest" | "xhr" => Some(TYPE_XHR),
+        "subdocument" | "frame" => Some(TYPE_SUBDOCUMENT),
+        "font" => Some(TYPE_FONT),
+        "media" => Some(TYPE_MEDIA),
+        "websocket" => Some(TYPE_WEBSOCKET),
+        "ping" => Some(TYPE_PING),
+        "object" | "other" => Some(TYPE_OTHER),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Literal(String),
+    Wildcard,
+    // `^`, anything but a letter, a digit or one of `_-.%`, or the end of the url
+    Separator,
+}
+
+#[derive(Debug, PartialEq)]
+enum Anchor {
+    None,
+    // `|` at the start of the url
+    Start,
+    // `||` at the start of the host or one of its labels
+    Domain,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Tokens {
+        tokens: Vec<Token>,
+        anchor: Anchor,
+        end_anchor: bool,
+    },
+    Regex(Regex),
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    types: u16,
+    third_party: Option<bool>,
+    include_domains: Vec<String>,
+    exclude_domains: Vec<String>,
+    match_case: bool,
+}
+
+/// The request checked against the filters
+pub struct Request<'a> {
+    pub url: &'a str,
+    pub resource_type: &'a ResourceType,
+    // host of the page issuing the request
+    pub source_host: &'a str,
+}
+
+/// EasyList/Adblock Plus network filters, element hiding rules are ignored
+#[derive(Debug, Default)]
+pub struct FilterList {
+    // `||host^` rules indexed by host
+    domain_rules: HashMap<String, Vec<Rule>>,
+    rules: Vec<Rule>,
+    exceptions: Vec<Rule>,
+}
+
+fn is_separator(c: u8) -> bool {
+    !(c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'.' | b'%'))
+}
+
+fn match_tokens(tokens: &[Token], text: &[u8], end_anchor: bool) -> bool {
+    match tokens.first() {
+        None => !end_anchor || text.is_empty(),
+        Some(Token::Literal(literal)) => {
+            text.starts_with(literal.as_bytes())
+                && match_tokens(&tokens[1..], &text[literal.len()..], end_anchor)
+        }
+        Some(Token::Separator) => match text.first() {
+            None => match_tokens(&tokens[1..], text, end_anchor),
+            Some(c) if is_separator(*c) => match_tokens(&tokens[1..], &text[1..], end_anchor),
+            Some(_) => false,
+        },
+        Some(Token::Wildcard) => {
+            (0..=text.len()).any(|i| match_tokens(&tokens[1..], &text[i..], end_anchor))
+        }
+    }
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    for c in pattern.chars() {
+        let token = match c {
+            '*' => Token::Wildcard,
+            '^' => Token::Separator,
+            _ => {
+                literal.push(c);
+                continue;
+            }
+        };
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+        }
+        // `**` is the same as `*`
+        if !(token == Token::Wildcard && tokens.last() == Some(&Token::Wildcard)) {
+            tokens.push(token);
+        }
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    tokens
+}
+
+fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain) && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+// the last two labels, good enough to tell first from third party
+fn base_domain(host: &str) -> &str {
+    match host.rmatch_indices('.').nth(1) {
+        Some((pos, _)) => &host[pos + 1..],
+        None => host,
+    }
+}
+
+// the position and length of the host in the url
+fn host_range(url: &str) -> Option<(usize, usize)> {
+    let start = url.find("://")? + 3;
+    let authority_end = url[start..]
+        .find(['/', '?', '#'])
+        .map(|pos| start + pos)
+        .unwrap_or(url.len());
+    let host_start = url[start..authority_end]
+        .rfind('@')
+        .map(|pos| start + pos + 1)
+        .unwrap_or(start);
+    let host_end = url[host_start..authority_end]
+        .find(':')
+        .map(|pos| host_start + pos)
+        .unwrap_or(authority_end);
+    Some((host_start, host_end - host_start))
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<(Rule, bool)> {
+        let (line, exception) = match line.strip_prefix("@@") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+
+        let (pattern, options) = match line.rfind('$') {
+            // a `$` inside a regex rule is an end anchor
+            Some(pos) if !(line.starts_with('/') && line.ends_with('/')) => {
+                (&line[..pos], Some(&line[pos + 1..]))
+            }
+            _ => (line, None),
+        };
+
+        let mut rule = Rule {
+            pattern: Pattern::Tokens {
+                tokens: Vec::new(),
+                anchor: Anchor::None,
+                end_anchor: false,
+            },
+            types: TYPE_ALL,
+            third_party: None,
+            include_domains: Vec::new(),
+            exclude_domains: Vec::new(),
+            match_case: false,
+        };
+
+        let mut included = 0;
+        let mut excluded = 0;
+        for option in options.into_iter().flat_map(|o| o.split(',')) {
+            let (negated, name) = match option.strip_prefix('~') {
+                Some(name) => (true, name),
+                None => (false, option),
+            };
+            match name {
+                "third-party" | "3p" => rule.third_party = Some(!negated),
+                "first-party" | "1p" => rule.third_party = Some(negated),
+                "match-case" => rule.match_case = true,
+                _ if name.starts_with("domain=") => {
+                    for domain in name["domain=".len()..].split('|') {
+                        match domain.strip_prefix('~') {
+                            Some(domain) => rule.exclude_domains.push(domain.to_lowercase()),
+                            None => rule.include_domains.push(domain.to_lowercase()),
+                        }
+                    }
+                }
+                _ => match option_mask(name) {
+                    Some(mask) if negated => excluded |= mask,
+                    Some(mask) => included |= mask,
+                    // popup, csp, redirect... are beyond blocking requests
+                    None => return None,
+                },
+            }
+        }
+        if included != 0 {
+            rule.types = included;
+        }
+        rule.types &= !excluded;
+
+        if pattern.len() > 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+            let regex = RegexBuilder::new(&pattern[1..pattern.len() - 1])
+                .case_insensitive(!rule.match_case)
+                .build()
+                .ok()?;
+            rule.pattern = Pattern::Regex(regex);
+            return Some((rule, exception));
+        }
+
+        let pattern = match rule.match_case {
+            true => pattern.to_string(),
+            false => pattern.to_lowercase(),
+        };
+        let (pattern, anchor) = if let Some(pattern) = pattern.strip_prefix("||") {
+            (pattern, Anchor::Domain)
+        } else if let Some(pattern) = pattern.strip_prefix('|') {
+            (pattern, Anchor::Start)
+        } else {
+            (pattern.as_str(), Anchor::None)
+        };
+        let (pattern, end_anchor) = match pattern.strip_suffix('|') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+        let tokens = tokenize(pattern);
+        if tokens.is_empty() && anchor == Anchor::None {
+            // an empty pattern would block everything
+            return None;
+        }
+        rule.pattern = Pattern::Tokens {
+            tokens,
+            anchor,
+            end_anchor,
+        };
+        Some((rule, exception))
+    }
+
+    // the host a `||host^` rule applies to
+    fn index_host(&self) -> Option<String> {
+        let Pattern::Tokens {
+            tokens,
+            anchor: Anchor::Domain,
+            ..
+        } = &self.pattern
+        else {
+            return None;
+        };
+        let Some(Token::Literal(literal)) = tokens.first() else {
+            return None;
+        };
+        match literal.find(['/', ':', '?']) {
+            Some(pos) => Some(literal[..pos].to_string()),
+            None if tokens.get(1) == Some(&Token::Separator) => Some(literal.clone()),
+            None => None,
+        }
+        .filter(|host| !host.is_empty() && !self.match_case)
+    }
+
+    fn matches(&self, url: &str, lower_url: &str, request: &Request, mask: u16) -> bool {
+        if self.types & mask == 0 {
+            return false;
+        }
+
+        if !self.include_domains.is_empty()
+            && !self
+                .include_domains
+                .iter()
+                .any(|d| host_matches(request.source_host, d))
+        {
+            return false;
+        }
+        if self
+            .exclude_domains
+            .iter()
+            .any(|d| host_matches(request.source_host, d))
+        {
+            return false;
+        }
+
+        if let Some(third_party) = self.third_party {
+            let host = host_range(lower_url)
+                .map(|(start, len)| &lower_url[start..start + len])
+                .unwrap_or_default();
+            if third_party != (base_domain(host) != base_domain(request.source_host)) {
+                return false;
+            }
+        }
+
+        let text = match self.match_case {
+            true => url,
+            false => lower_url,
+        };
+        match &self.pattern {
+            Pattern::Regex(regex) => regex.is_match(url),
+            Pattern::Tokens {
+                tokens,
+                anchor: Anchor::Start,
+                end_anchor,
+            } => match_tokens(tokens, text.as_bytes(), *end_anchor),
+            Pattern::Tokens {
+                tokens,
+                anchor: Anchor::Domain,
+                end_anchor,
+            } => {
+                let Some((start, len)) = host_range(text) else {
+                    return false;
+                };
+                let host = &text[start..start + len];
+                std::iter::once(0)
+                    .chain(host.match_indices('.').map(|(pos, _)| pos + 1))
+                    .any(|pos| match_tokens(tokens, &text.as_bytes()[start + pos..], *end_anchor))
+            }
+            Pattern::Tokens {
+                tokens,
+                anchor: Anchor::None,
+                end_anchor,
+            } => match tokens.first() {
+                // only try the positions where the first literal occurs
+                Some(Token::Literal(literal)) => text
+                    .match_indices(literal.as_str())
+                    .any(|(pos, _)| match_tokens(tokens, &text.as_bytes()[pos..], *end_anchor)),
+                _ => (0..=text.len())
+                    .any(|pos| match_tokens(tokens, &text.as_bytes()[pos..], *end_anchor)),
+            },
+        }
+    }
+}
+
+impl FilterList {
+    pub fn parse(content: &str) -> Self {
+        let mut list = FilterList::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty()
+                || line.starts_with('!')
+                || line.starts_with('[')
+                || line.contains("##")
+                || line.contains("#@#")
+                || line.contains("#?#")
+                || line.contains("#$#")
+            {
+                continue;
+            }
+            match Rule::parse(line) {
+                Some((rule, true)) => list.exceptions.push(rule),
+                Some((rule, false)) => match rule.index_host() {
+                    Some(host) => list.domain_rules.entry(host).or_default().push(rule),
+                    None => list.rules.push(rule),
+                },
+                None => {}
+            }
+        }
+        list
+    }
+
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn extend(&mut self, other: FilterList) {
+        for (host, rules) in other.domain_rules {
+            self.domain_rules.entry(host).or_default().extend(rules);
+        }
+        self.rules.extend(other.rules);
+        self.exceptions.extend(other.exceptions);
+    }
+
+    pub fn len(&self) -> usize {
+        self.domain_rules.values().map(Vec::len).sum::<usize>()
+            + self.rules.len()
+            + self.exceptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the request should be blocked
+    pub fn check(&self, request: &Request) -> bool {
+        let lower_url = request.url.to_lowercase();
+        let mask = type_mask(request.resource_type);
+        let matches = |rule: &Rule| rule.matches(request.url, &lower_url, request, mask);
+
+        let host = host_range(&lower_url)
+            .map(|(start, len)| &lower_url[start..start + len])
+            .unwrap_or_default();
+        let by_host = std::iter::once(host)
+            .chain(host.match_indices('.').map(|(pos, _)| &host[pos + 1..]))
+            .filter_map(|h| self.domain_rules.get(h))
+            .flatten()
+            .any(matches);
+
+        (by_host || self.rules.iter().any(matches)) && !self.exceptions.iter().any(matches)
+    }
+}
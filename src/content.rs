@@ -1,30 +1,64 @@
-use crate::headless::create_headless_browser_session;
-use crate::Error;
+use crate::actions::{run_actions, ActionStep};
+use crate::download::{Download, DownloadWatcher};
+use crate::egress::{start_egress, EgressPolicy};
+use crate::headless::{start_headless_browser_session, SessionBrowser};
+use crate::intercept::{
+    parse_resource_type, InterceptGuard, Interceptor, Mock, MockResponse, Rewrite, RewriteRule,
+    UrlPattern,
+};
+use crate::overlays;
+use crate::pdf;
+use crate::policy::UrlPolicy;
+use crate::pool::Lease;
+use crate::proxy::{select_proxy, Proxy};
+use crate::queue::Priority;
+use crate::throttle::{Throttle, ThrottleOptions};
+use crate::upstream::{NavigationFailure, ResponseRecorder, UpstreamResponse};
+use crate::validate::FieldErrors;
+use crate::wait::{event_recorder_js, wait_condition, WaitCondition};
 use crate::{
     devices::get_device,
     session::{SessionGuard, SessionOption},
     StateRef,
 };
+use crate::{Error, ErrorCode};
 use axum::Json;
 use axum::{
     body::Body,
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Query, State,
+    },
+    http::{HeaderName, StatusCode},
     response::Response,
 };
+use chromiumoxide::cdp::browser_protocol::browser::{GrantPermissionsParams, PermissionType};
 use chromiumoxide::cdp::browser_protocol::network::{
-    EventLoadingFailed, EventLoadingFinished, EventRequestWillBeSent,
+    CookieParam, EventLoadingFailed, EventLoadingFinished, EventRequestWillBeSent, Headers,
+    SetCookiesParams, SetExtraHttpHeadersParams, SetUserAgentOverrideParams, TimeSinceEpoch,
 };
 use chromiumoxide::{
+    cdp::browser_protocol::emulation::{
+        MediaFeature, SetDeviceMetricsOverrideParams, SetEmulatedMediaParams,
+        SetGeolocationOverrideParams, SetLocaleOverrideParams, SetScrollbarsHiddenParams,
+    },
     cdp::browser_protocol::page::{CaptureScreenshotFormat, PrintToPdfParams, Viewport},
+    error::CdpError,
     page::ScreenshotParams,
-    Browser, Page,
+    Page,
 };
 use futures::{Future, StreamExt};
 use serde::Deserialize;
+use serde_json::json;
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tokio::{select, sync::oneshot, time};
+use tokio_util::io::ReaderStream;
+
+// how long a download may take to start once the actions are done
+const DOWNLOAD_START_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Deserialize)]
 pub struct RenderParams {
@@ -48,6 +82,16 @@ pub struct RenderParams {
 
     #[serde(rename = "page_ready")]
     wait_page_ready: Option<bool>,
+
+    // javascript expression polled until truthy, a promise is awaited
+    wait_function: Option<String>,
+    wait_text: Option<String>,
+    wait_visible: Option<String>,
+    wait_hidden: Option<String>,
+    // javascript regex on location.href
+    wait_url: Option<String>,
+    // custom event dispatched on window
+    wait_event: Option<String>,
     scroll_bottom: Option<u64>, // scroll to bottom before render, in seconds
     scroll_interval: Option<u64>,
 
@@ -91,6 +135,199 @@ pub struct RenderParams {
     full_page: Option<bool>,
 
     author: Option<String>,
+
+    // pdf: shrink the output, images above `image_dpi` are resampled
+    optimize: Option<bool>,
+    image_dpi: Option<u32>,
+    jpeg_quality: Option<u8>,
+
+    // pdf: turn html form controls into fillable fields
+    acroform: Option<bool>,
+    // pdf: fast web view, the first page displays before the download completes
+    linearize: Option<bool>,
+
+    // pdf: embed the page html, a json blob or uploaded files as attachments
+    attach_html: Option<bool>,
+    attach_json: Option<serde_json::Value>,
+    attachments: Option<Vec<pdf::Attachment>>,
+
+    // sent with every request of the page, for rendering behind a login
+    headers: Option<HashMap<String, String>>,
+    cookies: Option<Vec<RenderCookie>>,
+    user_agent: Option<String>,
+    // user:password, answered to http auth challenges
+    basic_auth: Option<String>,
+
+    // runs in every frame before the page scripts
+    script_before: Option<String>,
+    // runs after the wait phase, a returned promise is awaited
+    script_after: Option<String>,
+    // extra css injected before capture
+    style: Option<String>,
+
+    // interaction steps run in order after the wait phase, POST only
+    actions: Option<Vec<ActionStep>>,
+
+    // comma separated: image, font, media, stylesheet, script, xhr
+    block_resources: Option<String>,
+    // glob or /regex/ matched against the whole url, POST only
+    block_urls: Option<Vec<String>>,
+    // block with the filter lists given by --adblock-list
+    block_ads: Option<bool>,
+
+    // canned responses and origin rewrites for deterministic renders, POST only
+    mocks: Option<Vec<MockResponse>>,
+    rewrite: Option<Vec<RewriteRule>>,
+
+    // screen or print, a pdf with screen uses the screen css
+    media: Option<String>,
+    // light or dark
+    color_scheme: Option<String>,
+    reduced_motion: Option<bool>,
+    forced_colors: Option<bool>,
+    // more, less, custom or no-preference
+    contrast: Option<String>,
+
+    // IANA id, e.g. Europe/Berlin
+    timezone: Option<String>,
+    // e.g. de-DE, also the browser ui language
+    locale: Option<String>,
+    // latitude,longitude[,accuracy in meters], the permission is granted
+    geolocation: Option<String>,
+    // e.g. de-DE,de;q=0.9
+    accept_language: Option<String>,
+
+    // offline, slow-3g, fast-3g, 4g or custom with download_kbps, upload_kbps and latency
+    throttle: Option<String>,
+    download_kbps: Option<f64>,
+    upload_kbps: Option<f64>,
+    latency: Option<f64>,
+    cpu_slowdown: Option<f64>,
+
+    // close known consent banners, rejecting unless accept_cookies
+    dismiss_cookie_banners: Option<bool>,
+    accept_cookies: Option<bool>,
+    // css selector list of site specific overlays
+    remove_selectors: Option<String>,
+    // fixed and sticky elements scroll with the page
+    unfix_position: Option<bool>,
+
+    // http(s)://user:password@host:port, socks5://host:port or direct to skip the proxy pool
+    proxy: Option<String>,
+    // the same proxy of the pool for the same proxy_session
+    proxy_session: Option<String>,
+
+    // selects the --url-policy of the key
+    api_key: Option<String>,
+
+    // return the file downloaded by the url or the actions instead of the render
+    download: Option<bool>,
+
+    // answer 502 when the page itself is a 4xx or 5xx
+    fail_on_status: Option<bool>,
+    // comma separated response headers of the page, returned as X-Upstream-<name>
+    upstream_headers: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RenderCookie {
+    name: String,
+    value: String,
+    domain: Option<String>,
+    path: Option<String>,
+    // unix time in seconds, session cookie if omitted
+    expires: Option<f64>,
+    secure: Option<bool>,
+    http_only: Option<bool>,
+}
+
+impl RenderCookie {
+    // without a domain the cookie belongs to the rendered url
+    fn to_cookie_param(&self, url: &str) -> CookieParam {
+        let mut cookie = CookieParam::new(self.name.clone(), self.value.clone());
+        match &self.domain {
+            Some(domain) => cookie.domain = Some(domain.clone()),
+            None => cookie.url = Some(url.to_string()),
+        }
+        cookie.path = self.path.clone();
+        cookie.expires = self.expires.map(TimeSinceEpoch::new);
+        cookie.secure = self.secure;
+        cookie.http_only = self.http_only;
+        cookie
+    }
+}
+
+/// The rendered body handed back to `extrace_page` by each api
+pub struct RenderOutput {
+    content: Body,
+    content_type: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RenderOutput {
+    pub fn new(content: Vec<u8>, content_type: &str) -> Self {
+        Self::stream(Body::from(content), content_type)
+    }
+
+    /// A body sent as it is read, e.g. a downloaded file
+    pub fn stream(content: Body, content_type: &str) -> Self {
+        Self {
+            content,
+            content_type: content_type.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+// why a render failed, anything but the page itself means the service is unavailable
+enum RenderError {
+    Navigation(NavigationFailure, String),
+    Upstream(UpstreamResponse),
+    Timeout(u64),
+    NoDownload,
+    BrowserClosed,
+    Canceled,
+    Other(String),
+}
+
+impl From<String> for RenderError {
+    fn from(e: String) -> Self {
+        RenderError::Other(e)
+    }
+}
+
+impl From<RenderError> for Error {
+    fn from(e: RenderError) -> Self {
+        match e {
+            RenderError::Navigation(failure, text) => {
+                Error::from_code(failure.code(), &format!("navigation failed: {}", text))
+                    .with_details(json!({ "net_error": text }))
+            }
+            RenderError::Upstream(response) => Error::from_code(
+                ErrorCode::UpstreamStatus,
+                &format!("{} answered {}", response.url, response.status),
+            )
+            .with_details(json!({ "status": response.status, "url": response.url })),
+            RenderError::Timeout(timeout) => Error::from_code(
+                ErrorCode::RenderTimeout,
+                &format!("render not finished in {}ms", timeout),
+            )
+            .with_details(json!({ "timeout": timeout })),
+            RenderError::NoDownload => {
+                Error::from_code(ErrorCode::NoDownload, "no download was triggered")
+            }
+            RenderError::BrowserClosed => {
+                Error::from_code(ErrorCode::BrowserClosed, "browser exited")
+            }
+            RenderError::Canceled => Error::from_code(ErrorCode::SessionCanceled, "session killed"),
+            RenderError::Other(e) => Error::new(StatusCode::SERVICE_UNAVAILABLE, &e),
+        }
+    }
 }
 
 impl From<&RenderParams> for SessionOption {
@@ -101,6 +338,11 @@ impl From<&RenderParams> for SessionOption {
             enable_cache: params.expired.is_some(),
             landscape: params.landscape.unwrap_or_default(),
             userdatadir_expire: params.expired,
+            lang: params.locale.clone(),
+            // picked from the proxy pool by PageSetup
+            proxy: None,
+            max_lifetime: None,
+            idle_timeout: None,
         }
     }
 }
@@ -143,8 +385,8 @@ impl Into<PrintToPdfParams> for RenderParams {
         params.margin_right = self.margin_right;
 
         params.scale = self.scale;
-        params.paper_width = self.paper_width;
-        params.paper_height = self.paper_height;
+        params.paper_width = self.paper_width.or(params.paper_width);
+        params.paper_height = self.paper_height.or(params.paper_height);
 
         if self.header_template.is_some() {
             params.display_header_footer = Some(true);
@@ -168,66 +410,132 @@ impl Into<ScreenshotParams> for RenderParams {
             _ => CaptureScreenshotFormat::Png,
         };
 
-        if let Some(clip) = self.clip {
-            let mut parts = clip.split(',').map(str::parse::<f64>).map(Result::unwrap);
-            let x = parts.next().unwrap_or(0.0);
-            let y = parts.next().unwrap_or(0.0);
-            let width = parts.next().unwrap_or(0.0);
-            let height = parts.next().unwrap_or(0.0);
+        if let Some(clip) = self.clip.as_deref().and_then(|c| parse_clip(c).ok()) {
+            params = params.clip(clip);
+        }
+
+        params
+            .format(format)
+            .quality(self.quality.unwrap_or_default())
+            .full_page(self.full_page.unwrap_or_default())
+            .build()
+    }
+}
 
-            let viewport = Viewport::builder()
+// x,y,width,height in css pixels
+fn parse_clip(clip: &str) -> Result<Viewport, String> {
+    let invalid = || "must be x,y,width,height".to_string();
+    let parts = clip
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts[..] {
+        [x, y, width, height] if parts.iter().all(|v| v.is_finite()) => {
+            if x < 0.0 || y < 0.0 || width <= 0.0 || height <= 0.0 {
+                return Err("must have a positive size inside the page".to_string());
+            }
+            Viewport::builder()
                 .x(x)
                 .y(y)
                 .width(width)
                 .height(height)
+                .scale(1.0)
                 .build()
-                .unwrap();
-            params = params.clip(viewport);
         }
+        _ => Err(invalid()),
+    }
+}
 
-        params
-            .format(format)
-            .quality(self.quality.unwrap_or_default())
-            .full_page(self.full_page.unwrap_or_default())
-            .build()
+impl RenderParams {
+    /// Reject malformed or conflicting parameters before a browser is launched
+    fn validate(&self) -> Result<(), Error> {
+        let mut errors = FieldErrors::default();
+        if let Some(clip) = &self.clip {
+            if let Err(e) = parse_clip(clip) {
+                errors.add("clip", e);
+            }
+        }
+        if let Some(device) = &self.emulating_device {
+            errors.check("device", get_device(device).is_some(), "unknown device");
+        }
+        errors.one_of("format", self.format.as_deref(), &["png", "jpeg", "webp"]);
+        errors.one_of(
+            "paper",
+            self.paper_size.as_deref().map(str::to_lowercase).as_deref(),
+            &["a3", "a4", "a5", "legal", "letter"],
+        );
+        errors.range("quality", self.quality, 0, 100);
+        errors.range("scale", self.scale, 0.1, 2.0);
+        errors.range("jpeg_quality", self.jpeg_quality, 1, 100);
+        errors.range("image_dpi", self.image_dpi, 1, 2400);
+        for (field, value) in [("width", self.paper_width), ("height", self.paper_height)] {
+            errors.check(field, value.is_none_or(|v| v > 0.0), "must be positive");
+        }
+        for (field, value) in [
+            ("top", self.margin_top),
+            ("bottom", self.margin_bottom),
+            ("left", self.margin_left),
+            ("right", self.margin_right),
+        ] {
+            errors.check(
+                field,
+                value.is_none_or(|v| v >= 0.0),
+                "must not be negative",
+            );
+        }
+        for (field, value) in [
+            ("timeout", self.timeout),
+            ("wait_load", self.wait_load),
+            ("network_idle", self.wait_network_idle),
+            ("scroll_interval", self.scroll_interval),
+        ] {
+            errors.check(field, value != Some(0), "must be positive");
+        }
+
+        errors.conflicts(
+            "quality",
+            self.quality.is_some(),
+            "format png",
+            self.format.as_deref() == Some("png"),
+        );
+        errors.conflicts(
+            "clip",
+            self.clip.is_some(),
+            "full_page",
+            self.full_page.unwrap_or_default(),
+        );
+        errors.conflicts(
+            "paper",
+            self.paper_size.is_some(),
+            "width and height",
+            self.paper_width.is_some() || self.paper_height.is_some(),
+        );
+        errors.into_result()
     }
 }
 
-pub fn can_access(u: url::Url, state: StateRef) -> Result<url::Url, Error> {
+pub async fn can_access(
+    u: url::Url,
+    state: &StateRef,
+    policy: Option<&UrlPolicy>,
+) -> Result<url::Url, Error> {
     if u.scheme() != "http" && u.scheme() != "https" {
         return Err(Error::new(
             StatusCode::BAD_REQUEST,
             "only support http/https",
         ));
     }
-
-    if state.enable_private_ip {
-        return Ok(u);
-    }
-    let host = u.host_str().map(str::to_lowercase).unwrap_or_default();
-    if host == "localhost" {
-        return Err(Error::new(StatusCode::BAD_REQUEST, "not allow localhost"));
-    }
-
-    match host.parse::<std::net::IpAddr>() {
-        Ok(addr) => {
-            if addr.is_loopback() {
-                return Err(Error::new(
-                    StatusCode::BAD_REQUEST,
-                    "not allow loopback address",
-                ));
-            }
-            match addr {
-                std::net::IpAddr::V4(v4_addr) => {
-                    if !state.enable_private_ip && v4_addr.is_private() {
-                        return Err(Error::new(StatusCode::BAD_REQUEST, "not allow private ip"));
-                    }
-                }
-                _ => {}
-            }
-        }
-        Err(_) => {}
+    if let Some(policy) = policy {
+        policy
+            .check(&u)
+            .map_err(|e| Error::new(StatusCode::FORBIDDEN, &e))?;
     }
+    // redirects and subresources are checked by the interceptor
+    state
+        .access
+        .check_url(&u)
+        .await
+        .map_err(|e| Error::new(StatusCode::FORBIDDEN, &e))?;
     Ok(u)
 }
 
@@ -370,6 +678,406 @@ async fn wait_page_ready(page: Page, check_interval: u64) {
     }
 }
 
+fn build_interceptor(
+    params: &RenderParams,
+    state: &StateRef,
+    host: &str,
+) -> Result<Interceptor, Error> {
+    let mut interceptor = Interceptor::default();
+    if let Some(basic_auth) = &params.basic_auth {
+        let (username, password) = basic_auth.split_once(':').ok_or_else(|| {
+            Error::new(StatusCode::BAD_REQUEST, "basic_auth must be user:password")
+        })?;
+        interceptor = interceptor.with_credentials(username, password);
+    }
+
+    if let Some(resources) = &params.block_resources {
+        let types = resources
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(parse_resource_type)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
+        interceptor = interceptor.block_types(types.concat());
+    }
+
+    if let Some(patterns) = &params.block_urls {
+        let patterns = patterns
+            .iter()
+            .map(|p| UrlPattern::try_from(p.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
+        interceptor = interceptor.block_urls(patterns);
+    }
+
+    if params.block_ads.unwrap_or_default() {
+        let filters = state.adblock.clone().ok_or_else(|| {
+            Error::new(
+                StatusCode::BAD_REQUEST,
+                "block_ads requires the server to start with --adblock-list",
+            )
+        })?;
+        interceptor = interceptor.with_adblock(filters, host);
+    }
+
+    if let Some(mocks) = &params.mocks {
+        let mocks = mocks
+            .iter()
+            .map(Mock::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
+        interceptor = interceptor.mocks(mocks);
+    }
+
+    if let Some(rules) = &params.rewrite {
+        let rewrites = rules
+            .iter()
+            .map(Rewrite::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
+        interceptor = interceptor.rewrites(rewrites);
+    }
+    Ok(interceptor)
+}
+
+fn emulated_media(params: &RenderParams) -> Result<Option<SetEmulatedMediaParams>, Error> {
+    fn check(name: &str, value: &str, allowed: &[&str]) -> Result<MediaFeature, Error> {
+        match allowed.contains(&value) {
+            true => Ok(MediaFeature::new(name, value)),
+            false => Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                &format!("{} must be one of {}", name, allowed.join(", ")),
+            )),
+        }
+    }
+
+    let mut features = Vec::new();
+    if let Some(scheme) = &params.color_scheme {
+        features.push(check("prefers-color-scheme", scheme, &["light", "dark"])?);
+    }
+    if let Some(reduce) = params.reduced_motion {
+        let value = if reduce { "reduce" } else { "no-preference" };
+        features.push(MediaFeature::new("prefers-reduced-motion", value));
+    }
+    if let Some(forced) = params.forced_colors {
+        let value = if forced { "active" } else { "none" };
+        features.push(MediaFeature::new("forced-colors", value));
+    }
+    if let Some(contrast) = &params.contrast {
+        features.push(check(
+            "prefers-contrast",
+            contrast,
+            &["more", "less", "custom", "no-preference"],
+        )?);
+    }
+    if let Some(media) = &params.media {
+        check("media", media, &["screen", "print"])?;
+    }
+
+    if params.media.is_none() && features.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(SetEmulatedMediaParams {
+        media: params.media.clone(),
+        features: Some(features),
+    }))
+}
+
+fn parse_geolocation(geolocation: &str) -> Result<SetGeolocationOverrideParams, Error> {
+    let invalid = || {
+        Error::new(
+            StatusCode::BAD_REQUEST,
+            "geolocation must be latitude,longitude[,accuracy]",
+        )
+    };
+    let parts = geolocation
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts[..] {
+        [latitude, longitude, ..]
+            if parts.len() <= 3
+                && (-90.0..=90.0).contains(&latitude)
+                && (-180.0..=180.0).contains(&longitude) =>
+        {
+            Ok(SetGeolocationOverrideParams {
+                latitude: Some(latitude),
+                longitude: Some(longitude),
+                accuracy: Some(parts.get(2).copied().unwrap_or(100.0)),
+            })
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// everything prepare_page applies, checked before the browser starts
+struct PageSetup {
+    interceptor: Interceptor,
+    media: Option<SetEmulatedMediaParams>,
+    geolocation: Option<SetGeolocationOverrideParams>,
+    origin: String,
+    throttle: Option<Throttle>,
+    proxy: Option<Proxy>,
+}
+
+impl PageSetup {
+    fn new(
+        params: &RenderParams,
+        state: &StateRef,
+        u: &url::Url,
+        policy: Option<Arc<UrlPolicy>>,
+    ) -> Result<Self, Error> {
+        let host = u.host_str().map(str::to_lowercase).unwrap_or_default();
+        let proxy = select_proxy(
+            state.proxy_pool.as_deref(),
+            params.proxy.as_deref(),
+            params.proxy_session.as_deref(),
+        )
+        .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
+        let mut interceptor =
+            build_interceptor(params, state, &host)?.with_access(state.access.clone());
+        if let Some((username, password)) = proxy.as_ref().and_then(|p| p.credentials()) {
+            interceptor = interceptor.with_proxy_credentials(username, password);
+        }
+        if let Some(policy) = policy {
+            interceptor = interceptor.with_url_policy(policy);
+        }
+        Ok(Self {
+            interceptor,
+            media: emulated_media(params)?,
+            geolocation: params
+                .geolocation
+                .as_deref()
+                .map(parse_geolocation)
+                .transpose()?,
+            origin: u.origin().ascii_serialization(),
+            throttle: ThrottleOptions {
+                profile: params.throttle.as_deref(),
+                download_kbps: params.download_kbps,
+                upload_kbps: params.upload_kbps,
+                latency: params.latency,
+                cpu_slowdown: params.cpu_slowdown,
+            }
+            .build()
+            .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?,
+            proxy,
+        })
+    }
+}
+
+// apply the per-request settings before the page navigates
+async fn prepare_page(
+    browser: &SessionBrowser,
+    page: &Page,
+    params: &RenderParams,
+    setup: PageSetup,
+) -> Result<Option<InterceptGuard>, Error> {
+    if let Some(throttle) = &setup.throttle {
+        throttle.apply(page).await?;
+    }
+
+    if let Some(media) = setup.media {
+        page.execute(media).await?;
+    }
+
+    if let Some(timezone) = &params.timezone {
+        page.emulate_timezone(timezone.as_str()).await?;
+    }
+
+    if let Some(locale) = &params.locale {
+        page.execute(SetLocaleOverrideParams {
+            locale: Some(locale.clone()),
+        })
+        .await?;
+    }
+
+    if let Some(geolocation) = setup.geolocation {
+        browser
+            .browser()
+            .execute(GrantPermissionsParams {
+                permissions: vec![PermissionType::Geolocation],
+                origin: Some(setup.origin.clone()),
+                browser_context_id: browser.context(),
+            })
+            .await?;
+        page.execute(geolocation).await?;
+    }
+
+    if params.user_agent.is_some() || params.accept_language.is_some() {
+        let user_agent = match &params.user_agent {
+            Some(user_agent) => user_agent.clone(),
+            None => browser.browser().user_agent().await?,
+        };
+        page.set_user_agent(SetUserAgentOverrideParams {
+            user_agent,
+            accept_language: params.accept_language.clone(),
+            platform: None,
+            user_agent_metadata: None,
+        })
+        .await?;
+    }
+
+    if let Some(headers) = &params.headers {
+        page.execute(SetExtraHttpHeadersParams::new(Headers::new(
+            serde_json::to_value(headers)?,
+        )))
+        .await?;
+    }
+
+    if let Some(script) = &params.script_before {
+        page.evaluate_on_new_document(script.as_str()).await?;
+    }
+
+    if let Some(name) = &params.wait_event {
+        page.evaluate_on_new_document(event_recorder_js(name))
+            .await?;
+    }
+
+    if let Some(cookies) = &params.cookies {
+        let cookies = cookies
+            .iter()
+            .map(|c| c.to_cookie_param(&params.url))
+            .collect::<Vec<_>>();
+        page.execute(SetCookiesParams::new(cookies)).await?;
+    }
+
+    match setup.interceptor.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(setup.interceptor.start(page.clone()).await?)),
+    }
+}
+
+// close consent banners and overlays before capture, returns the dismissed banners
+async fn clean_page(page: &Page, params: &RenderParams) -> Result<Vec<String>, String> {
+    let mut dismissed = Vec::new();
+    let mut removed = 0;
+    if params.dismiss_cookie_banners.unwrap_or_default() {
+        dismissed = page
+            .evaluate(overlays::dismiss_banners_js(
+                params.accept_cookies.unwrap_or_default(),
+            ))
+            .await
+            .map_err(|e| format!("dismiss_cookie_banners error: {}", e))?
+            .into_value::<Vec<String>>()
+            .map_err(|e| e.to_string())?;
+    }
+
+    if let Some(selectors) = &params.remove_selectors {
+        removed = page
+            .evaluate(overlays::remove_selectors_js(selectors))
+            .await
+            .map_err(|e| format!("remove_selectors error: {}", e))?
+            .into_value::<usize>()
+            .map_err(|e| e.to_string())?;
+    }
+
+    if !dismissed.is_empty() || removed > 0 {
+        page.evaluate(overlays::UNLOCK_SCROLL_JS)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    if params.unfix_position.unwrap_or_default() {
+        page.evaluate(overlays::UNFIX_POSITION_JS)
+            .await
+            .map_err(|e| format!("unfix_position error: {}", e))?;
+    }
+
+    if !dismissed.is_empty() || removed > 0 {
+        log::info!(
+            "{} dismissed banners: {:?}, removed elements: {}",
+            params.url,
+            dismissed,
+            removed
+        );
+    }
+    Ok(dismissed)
+}
+
+// run the user script and inject the user style once the page is loaded
+async fn finish_page(page: &Page, params: &RenderParams) -> Result<(), String> {
+    if let Some(script) = &params.script_after {
+        page.evaluate(script.as_str())
+            .await
+            .map_err(|e| format!("script_after error: {}", e))?;
+    }
+
+    if let Some(style) = &params.style {
+        let css = serde_json::to_string(style).map_err(|e| e.to_string())?;
+        page.evaluate(format!(
+            "(() => {{ const el = document.createElement('style'); el.textContent = {}; (document.head || document.documentElement).appendChild(el); }})()",
+            css
+        ))
+        .await
+        .map_err(|e| format!("style error: {}", e))?;
+    }
+    Ok(())
+}
+
+async fn download_output(
+    download: Download,
+    params: &RenderParams,
+) -> Result<RenderOutput, String> {
+    let file = tokio::fs::File::open(&download.path)
+        .await
+        .map_err(|e| format!("open download {} error: {}", download.url, e))?;
+    let len = file
+        .metadata()
+        .await
+        .map_err(|e| format!("read download {} error: {}", download.url, e))?
+        .len();
+    log::info!(
+        "{} downloaded {} bytes from {}",
+        params.url,
+        len,
+        download.url
+    );
+    let content_type = download.content_type();
+    let disposition = download.content_disposition();
+    let url = download.url.clone();
+    // the directory goes with the body, once it is sent or dropped
+    let dir = download.dir;
+    let stream = ReaderStream::new(file).map(move |chunk| {
+        let _dir = &dir;
+        chunk
+    });
+    let output = RenderOutput::stream(Body::from_stream(stream), &content_type)
+        .header("Content-Length", len)
+        .header("X-Download-Url", &url);
+    // file_name takes precedence over the suggested one
+    match params.file_name {
+        Some(_) => Ok(output),
+        None => Ok(output.header("Content-Disposition", disposition)),
+    }
+}
+
+// the status, the final url and the selected headers of the page
+fn upstream_output(
+    mut output: RenderOutput,
+    response: Option<UpstreamResponse>,
+    headers: Option<&str>,
+) -> RenderOutput {
+    let response = match response {
+        Some(response) => response,
+        None => return output,
+    };
+    output = output
+        .header("X-Upstream-Status", response.status)
+        .header("X-Final-Url", &response.url);
+    let names = headers.unwrap_or_default().split(',').map(str::trim);
+    for name in names.filter(|name| !name.is_empty()) {
+        let header = format!("X-Upstream-{}", name);
+        if HeaderName::try_from(header.as_str()).is_err() {
+            continue;
+        }
+        for value in response.header_values(name) {
+            output = output.header(&header, value);
+        }
+    }
+    output
+}
+
 pub async fn extrace_page<C, Fut>(
     cmd: &str,
     params: RenderParams,
@@ -378,43 +1086,101 @@ pub async fn extrace_page<C, Fut>(
 ) -> Result<Response, Error>
 where
     C: FnOnce(String, RenderParams, StateRef, Page) -> Fut + Send + 'static,
-    Fut: Future<Output = Result<(Vec<u8>, String), String>> + Send + 'static,
+    Fut: Future<Output = Result<RenderOutput, String>> + Send + 'static,
 {
+    params.validate()?;
     let u = url::Url::parse(params.url.as_str())
-        .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e.to_string()))
-        .and_then(|u| can_access(u, state.clone()))?;
+        .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e.to_string()))?;
+    let policy = state.url_policy(params.api_key.as_deref())?;
+    let u = can_access(u, &state, policy.as_deref()).await?;
 
     let host = u.host_str().map(str::to_lowercase).unwrap_or_default();
+    let setup = PageSetup::new(&params, &state, &u, policy.clone())?;
+    // the proxies of --proxy-pool are trusted, the ones of the client are not
+    if let (Some(_), Some(proxy)) = (&params.proxy, &setup.proxy) {
+        state
+            .access
+            .check_proxy(proxy)
+            .await
+            .map_err(|e| Error::new(StatusCode::FORBIDDEN, &e))?;
+    }
+    let stats = setup.interceptor.stats();
+    let is_blocking = setup.interceptor.is_blocking();
+    let block_ads = params.block_ads.unwrap_or_default();
     let st = SystemTime::now();
 
     let device = get_device(&params.emulating_device.clone().unwrap_or_default());
-    let opt = SessionOption::from(&params);
+    let mut opt = SessionOption::from(&params);
+    opt.proxy = setup.proxy.clone();
+    // the browser resolves nothing itself, names are pinned to the checked addresses
+    let egress = start_egress(
+        &mut opt,
+        EgressPolicy {
+            access: Some(state.access.clone()),
+            url_policy: policy,
+        },
+    )
+    .await?;
 
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
-    let session =
-        create_headless_browser_session(opt, device, state.clone(), Some(shutdown_tx)).await?;
-    let mut browser: Browser = session.browser.take().ok_or_else(|| "window is None")?;
-    let mut handler = session
-        .headless_handler
-        .take()
-        .ok_or_else(|| "handler is None")?;
+    // a render runs in its own browser context of a pooled browser, a download owns the browser
+    let download = params.download.unwrap_or_default();
+    let lease = match download {
+        true => Lease::Exclusive,
+        false => Lease::Context,
+    };
+    let (mut session, browser, mut exit) = start_headless_browser_session(
+        opt,
+        device,
+        state.clone(),
+        Some(shutdown_tx),
+        lease,
+        Priority::Batch,
+    )
+    .await?;
+    // a render has no websocket traffic, its timeout bounds it
+    session.expiry.idle_timeout = None;
     let launch_usage = st.elapsed().unwrap_or_default();
     let st = SystemTime::now();
 
     let timeout = params
         .timeout
         .unwrap_or(state.max_timeout)
-        .max(state.max_timeout);
+        .min(state.max_timeout);
 
     const SLEEP_INTERVAL: u64 = 10;
     let file_name = params.file_name.clone();
 
+    let session_id = session.id.clone();
     let _guard = SessionGuard::new(state.clone(), session);
     let render_loop = async {
-        let page = browser
-            .new_page(params.url.as_str())
+        let page = browser.new_page().await.map_err(|e| e.to_string())?;
+        let _intercept = prepare_page(&browser, &page, &params, setup)
             .await
             .map_err(|e| e.to_string())?;
+        let recorder = ResponseRecorder::start(&page)
+            .await
+            .map_err(|e| e.to_string())?;
+        let downloads = match download {
+            true => Some(
+                DownloadWatcher::start(browser.browser(), &page, Path::new(&state.data_root))
+                    .await
+                    .map_err(|e| e.to_string())?,
+            ),
+            false => None,
+        };
+        match page.goto(params.url.as_str()).await {
+            Ok(_) => {}
+            // a download aborts the navigation
+            Err(e) if downloads.is_some() => {
+                log::debug!("{} {} goto error: {}", cmd, params.url, e);
+            }
+            Err(CdpError::ChromeMessage(text)) if text.starts_with("net::") => {
+                let failure = NavigationFailure::from_error_text(&text);
+                return Err(RenderError::Navigation(failure, text));
+            }
+            Err(e) => return Err(e.to_string().into()),
+        }
 
         let wait_something = async {
             // 1. wait for network idle
@@ -457,21 +1223,17 @@ where
                 }
             }
 
-            if let Some(selector) = &params.wait_selector {
-                let st = SystemTime::now();
-                loop {
-                    match page.find_element(selector.as_str()).await {
-                        Ok(_) => break,
-                        Err(_) => {}
-                    }
-                    time::sleep(time::Duration::from_millis(SLEEP_INTERVAL)).await;
-                }
-                log::info!(
-                    "{} {} wait {selector} done usage: {:?}",
-                    cmd,
-                    params.url,
-                    st.elapsed().unwrap_or_default()
-                );
+            let conditions = [
+                params.wait_selector.as_deref().map(WaitCondition::Selector),
+                params.wait_url.as_deref().map(WaitCondition::Url),
+                params.wait_visible.as_deref().map(WaitCondition::Visible),
+                params.wait_hidden.as_deref().map(WaitCondition::Hidden),
+                params.wait_text.as_deref().map(WaitCondition::Text),
+                params.wait_event.as_deref().map(WaitCondition::Event),
+                params.wait_function.as_deref().map(WaitCondition::Function),
+            ];
+            for condition in conditions.iter().flatten() {
+                wait_condition(cmd, &params.url, &page, condition).await;
             }
 
             if params.wait_images.unwrap_or_default() {
@@ -480,40 +1242,101 @@ where
         };
 
         let wait_timeout = params.wait_load.unwrap_or(15 * 1000).min(state.max_timeout);
-        select! {
-            _ = time::sleep(time::Duration::from_millis(wait_timeout)) => {
-                log::warn!("{} {} wait load timeout:{} wait_load:{:?} selector:{:?} images:{:?} network_idle:{:?} page_ready:{:?}", cmd,
-                params.url, wait_timeout, params.wait_load, params.wait_selector,
-                params.wait_images, params.wait_network_idle, params.wait_page_ready);
+        let interact = async {
+            select! {
+                _ = time::sleep(time::Duration::from_millis(wait_timeout)) => {
+                    log::warn!("{} {} wait load timeout:{} wait_load:{:?} selector:{:?} images:{:?} network_idle:{:?} page_ready:{:?}", cmd,
+                    params.url, wait_timeout, params.wait_load, params.wait_selector,
+                    params.wait_images, params.wait_network_idle, params.wait_page_ready);
+                }
+                _ = wait_something => {
+                    log::info!("{} {} done usage:{:?}", cmd, params.url, st.elapsed().unwrap_or_default());
+                }
+            }
+            if let Some(actions) = &params.actions {
+                run_actions(cmd, &params.url, &page, actions).await?;
             }
-            _ = wait_something => {
-                log::info!("{} {} done usage:{:?}", cmd, params.url, st.elapsed().unwrap_or_default());
+            Ok::<(), String>(())
+        };
+
+        if let Some(mut downloads) = downloads {
+            // the download starts with the navigation or from the actions
+            let begun = select! {
+                r = downloads.begin() => Some(r?),
+                r = interact => {
+                    r?;
+                    None
+                }
+            };
+            let begun = match begun {
+                Some(begun) => begun,
+                // the actions are done, what they triggered gets a moment to start
+                None => time::timeout(DOWNLOAD_START_TIMEOUT, downloads.begin())
+                    .await
+                    .map_err(|_| RenderError::NoDownload)??,
+            };
+            let download = downloads.complete(&begun).await?;
+            let output = download_output(download, &params).await?;
+            let headers = params.upstream_headers.as_deref();
+            return Ok(upstream_output(output, recorder.response(), headers));
+        }
+        interact.await?;
+
+        let response = recorder.response();
+        if let Some(response) = &response {
+            if params.fail_on_status.unwrap_or_default() && response.is_error() {
+                return Err(RenderError::Upstream(response.clone()));
             }
         }
-        callback(host.to_string(), params, state, page).await
+        let dismissed = clean_page(&page, &params).await?;
+        finish_page(&page, &params).await?;
+        let upstream_headers = params.upstream_headers.clone();
+        let mut output = callback(host.to_string(), params, state, page).await?;
+        if !dismissed.is_empty() {
+            output = output.header("X-Dismissed-Banners", dismissed.join(","));
+        }
+        Ok(upstream_output(
+            output,
+            response,
+            upstream_headers.as_deref(),
+        ))
     };
 
     let r = select! {
         r = render_loop => {
             r
         },
-        _ = async {
-            while let Some(_) = handler.next().await {}
-        } => { Err("handler exit".to_string()) }
+        _ = exit.wait() => { Err(RenderError::BrowserClosed) }
         _ = shutdown_rx => {
-            Err("session cancel".to_string())
+            Err(RenderError::Canceled)
         },
-        _ = async { time::sleep(time::Duration::from_secs(timeout)).await } => {
-            Err("timeout".to_string())
+        _ = async { time::sleep(time::Duration::from_millis(timeout)).await } => {
+            Err(RenderError::Timeout(timeout))
         },
     };
 
-    browser.kill().await;
+    browser.finish().await;
 
-    let (content, content_type) = r.map_err(|e| Error::new(StatusCode::SERVICE_UNAVAILABLE, &e))?;
-    let resp = Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", content_type);
+    if let Some(e) = stats.denied_document() {
+        return Err(Error::new(StatusCode::FORBIDDEN, &e).with_session(&session_id));
+    }
+    let mut output = r.map_err(|e| Error::from(e).with_session(&session_id))?;
+    if is_blocking {
+        output = output.header("X-Blocked-Requests", stats.blocked());
+        if block_ads {
+            output = output.header("X-Blocked-Ads", stats.blocked_ads());
+        }
+    }
+    let denied = stats.denied() + egress.as_ref().map_or(0, |egress| egress.denied());
+    if denied > 0 {
+        output = output.header("X-Denied-Requests", denied);
+    }
+    let resp = output.headers.iter().fold(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", output.content_type),
+        |resp, (name, value)| resp.header(name, value),
+    );
     let extract_usage = st.elapsed().unwrap_or_default();
 
     log::info!(
@@ -531,24 +1354,76 @@ where
         ),
         None => resp,
     }
-    .body(Body::from(content))
+    .body(output.content)
     .map_err(|e| Error::new(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))
 }
 
 pub async fn render_pdf_get(
-    Query(params): Query<RenderParams>,
+    params: Result<Query<RenderParams>, QueryRejection>,
     State(state): State<StateRef>,
 ) -> Result<Response, Error> {
+    let Query(params) = params?;
     render_pdf(params, state).await
 }
 
 pub async fn render_pdf_post(
     State(state): State<StateRef>,
-    Json(body): Json<RenderParams>,
+    body: Result<Json<RenderParams>, JsonRejection>,
 ) -> Result<Response, Error> {
+    let Json(body) = body?;
     render_pdf(body, state).await
 }
 
+// where chrome prints the page content, with the defaults of Page.printToPDF
+fn print_layout(params: &PrintToPdfParams) -> pdf::PrintLayout {
+    let width = params.paper_width.unwrap_or(8.5);
+    let height = params.paper_height.unwrap_or(11.0);
+    let (width, height) = match params.landscape.unwrap_or_default() {
+        true => (height, width),
+        false => (width, height),
+    };
+    let margin = |margin: Option<f64>| margin.unwrap_or(0.4);
+    pdf::PrintLayout {
+        width,
+        height,
+        margin_top: margin(params.margin_top),
+        margin_bottom: margin(params.margin_bottom),
+        margin_left: margin(params.margin_left),
+        margin_right: margin(params.margin_right),
+        scale: params.scale.unwrap_or(1.0),
+        page_ranges: params.page_ranges.clone(),
+    }
+}
+
+// lay the page out as wide as a printed page and measure its form controls,
+// the viewport stays for the print that follows
+async fn collect_form_fields(
+    page: &Page,
+    layout: &pdf::PrintLayout,
+    media: Option<SetEmulatedMediaParams>,
+) -> Result<Vec<pdf::FormField>, String> {
+    let (width, height) = layout.viewport();
+    page.execute(SetDeviceMetricsOverrideParams::new(
+        width.round() as i64,
+        height.round() as i64,
+        0.0,
+        false,
+    ))
+    .await
+    .map_err(|e| e.to_string())?;
+    page.execute(SetScrollbarsHiddenParams::new(true))
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(media) = media {
+        page.execute(media).await.map_err(|e| e.to_string())?;
+    }
+    page.evaluate(pdf::collect_fields_js())
+        .await
+        .map_err(|e| e.to_string())?
+        .into_value::<Vec<pdf::FormField>>()
+        .map_err(|e| e.to_string())
+}
+
 async fn render_pdf(params: RenderParams, state: StateRef) -> Result<Response, Error> {
     let author = match &params.author {
         Some(author) => author.clone(),
@@ -558,7 +1433,33 @@ async fn render_pdf(params: RenderParams, state: StateRef) -> Result<Response, E
         },
     };
 
+    let mut files = params
+        .attachments
+        .iter()
+        .flatten()
+        .map(pdf::EmbeddedFile::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
+    if let Some(value) = &params.attach_json {
+        let mut file = pdf::EmbeddedFile::new(
+            "request.json",
+            serde_json::to_vec_pretty(value)?,
+            "application/json",
+        );
+        file.relationship = "Data".to_string();
+        files.push(file);
+    }
+
     extrace_page("pdf", params, state, |_, params, _, page| async move {
+        if params.attach_html.unwrap_or_default() {
+            let html = page.content_bytes().await.map_err(|e| e.to_string())?;
+            files.push(pdf::EmbeddedFile::new(
+                "source.html",
+                html.to_vec(),
+                "text/html",
+            ));
+        }
+
         if params.disable_link.unwrap_or_default() {
             page.evaluate(
                 "document.querySelectorAll('a').forEach((el) => el.setAttribute('href', '#'))",
@@ -567,44 +1468,117 @@ async fn render_pdf(params: RenderParams, state: StateRef) -> Result<Response, E
             .map_err(|e| e.to_string())?;
         }
 
-        let content = page.pdf(params.into()).await.map_err(|e| e.to_string())?;
+        let optimize = match params.optimize.unwrap_or_default() {
+            true => {
+                let mut opt = pdf::OptimizeOption::default();
+                if let Some(dpi) = params.image_dpi {
+                    opt.image_dpi = dpi;
+                }
+                if let Some(quality) = params.jpeg_quality {
+                    opt.jpeg_quality = quality.clamp(1, 100);
+                }
+                Some(opt)
+            }
+            false => None,
+        };
+
+        let linearize = params.linearize.unwrap_or_default();
+        let mode = match (linearize, optimize.is_some()) {
+            (true, _) => pdf::SaveMode::Linearized,
+            (false, true) => pdf::SaveMode::ObjectStreams,
+            (false, false) => pdf::SaveMode::Plain,
+        };
+
+        // the controls are measured with the screen css when the pdf uses it
+        let measure_media = match params.media.as_deref() {
+            Some("screen") => None,
+            _ => Some(SetEmulatedMediaParams {
+                media: Some("print".to_string()),
+                features: emulated_media(&params)
+                    .ok()
+                    .flatten()
+                    .and_then(|m| m.features),
+            }),
+        };
+        let acroform = params.acroform.unwrap_or_default();
+        let print: PrintToPdfParams = params.into();
+        let layout = print_layout(&print);
+        let fields = match acroform {
+            true => collect_form_fields(&page, &layout, measure_media).await?,
+            false => Vec::new(),
+        };
+
+        let content = page.pdf(print).await.map_err(|e| e.to_string())?;
+        let original_size = content.len();
+        let mut report = None;
+        // the widgets of the form controls, the attachments and an asked
+        // linearization need the rewrite, a 200 without them would hide the failure
+        let required = !fields.is_empty() || !files.is_empty() || linearize;
         let content = match lopdf::Document::load_mem(&content) {
             Ok(mut doc) => {
-                let mut info = lopdf::Dictionary::new();
-                info.set(
-                    "Author",
-                    lopdf::Object::String(author.into(), lopdf::StringFormat::Literal),
-                );
-                let value = doc.add_object(lopdf::Object::Dictionary(info));
-                doc.trailer.set("Info", value);
-
-                let mut new_content = Vec::new();
-                match doc.save_to(&mut new_content) {
-                    Ok(_) => new_content,
+                pdf::set_author(&mut doc, &author);
+                if !files.is_empty() {
+                    pdf::embed_files(&mut doc, &files);
+                }
+                if !fields.is_empty() {
+                    let count = pdf::build_acroform(&mut doc, &fields, &layout);
+                    log::info!(
+                        "pdf acroform {} fields from {} controls",
+                        count,
+                        fields.len()
+                    );
+                }
+                let optimized = optimize.as_ref().map(|opt| pdf::optimize(&mut doc, opt));
+                match pdf::save(&mut doc, mode) {
+                    // only a saved document is reported as optimized
+                    Ok(new_content) => {
+                        report = optimized;
+                        new_content
+                    }
+                    Err(e) if required => return Err(format!("pdf save error: {}", e)),
                     Err(e) => {
                         log::error!("pdf save error: {}", e);
                         content
                     }
                 }
             }
+            Err(e) if required => return Err(format!("pdf load error: {}", e)),
             Err(_) => content,
         };
-        Ok((content, "application/pdf".to_string()))
+
+        let output = match report {
+            Some(report) => {
+                log::info!(
+                    "pdf optimized {} -> {} bytes, {:?}",
+                    original_size,
+                    content.len(),
+                    report
+                );
+                let optimized_size = content.len();
+                RenderOutput::new(content, "application/pdf")
+                    .header("X-Pdf-Original-Size", original_size)
+                    .header("X-Pdf-Optimized-Size", optimized_size)
+            }
+            None => RenderOutput::new(content, "application/pdf"),
+        };
+        Ok(output)
     })
     .await
 }
 
 pub async fn render_screenshot_get(
-    Query(params): Query<RenderParams>,
+    params: Result<Query<RenderParams>, QueryRejection>,
     State(state): State<StateRef>,
 ) -> Result<Response, Error> {
+    let Query(params) = params?;
     render_screenshot(params, state).await
 }
 
 pub async fn render_screenshot_post(
     State(state): State<StateRef>,
-    Json(body): Json<RenderParams>,
+    body: Result<Json<RenderParams>, JsonRejection>,
 ) -> Result<Response, Error> {
+    let Json(body) = body?;
     render_screenshot(body, state).await
 }
 
@@ -620,22 +1594,24 @@ async fn render_screenshot(params: RenderParams, state: StateRef) -> Result<Resp
             };
             let params: ScreenshotParams = params.into();
             let content = page.screenshot(params).await.map_err(|e| e.to_string())?;
-            Ok((content, format!("image/{file_ext}")))
+            Ok(RenderOutput::new(content, &format!("image/{file_ext}")))
         },
     )
     .await
 }
 
 pub async fn dump_text_get(
-    Query(params): Query<RenderParams>,
+    params: Result<Query<RenderParams>, QueryRejection>,
     State(state): State<StateRef>,
 ) -> Result<Response, Error> {
+    let Query(params) = params?;
     dump_text(params, state).await
 }
 pub async fn dump_text_post(
     State(state): State<StateRef>,
-    Json(body): Json<RenderParams>,
+    body: Result<Json<RenderParams>, JsonRejection>,
 ) -> Result<Response, Error> {
+    let Json(body) = body?;
     dump_text(body, state).await
 }
 
@@ -647,29 +1623,54 @@ async fn dump_text(params: RenderParams, state: StateRef) -> Result<Response, Er
             .map_err(|e| e.to_string())?
             .into_value()
             .map_err(|e| e.to_string())?;
-        Ok((content.into(), format!("plain/text")))
+        Ok(RenderOutput::new(content.into(), "plain/text"))
     })
     .await
 }
 
 pub async fn dump_html_get(
-    Query(params): Query<RenderParams>,
+    params: Result<Query<RenderParams>, QueryRejection>,
     State(state): State<StateRef>,
 ) -> Result<Response, Error> {
+    let Query(params) = params?;
     dump_html(params, state).await
 }
 
 pub async fn dump_html_post(
     State(state): State<StateRef>,
-    Json(body): Json<RenderParams>,
+    body: Result<Json<RenderParams>, JsonRejection>,
 ) -> Result<Response, Error> {
+    let Json(body) = body?;
     dump_html(body, state).await
 }
 
 async fn dump_html(params: RenderParams, state: StateRef) -> Result<Response, Error> {
     extrace_page("html", params, state, |_, _, _, page| async move {
         let content = page.content_bytes().await.map_err(|e| e.to_string())?;
-        Ok((content.into(), format!("text/html")))
+        Ok(RenderOutput::new(content.into(), "text/html"))
+    })
+    .await
+}
+
+pub async fn download_get(
+    params: Result<Query<RenderParams>, QueryRejection>,
+    State(state): State<StateRef>,
+) -> Result<Response, Error> {
+    let Query(params) = params?;
+    download(params, state).await
+}
+pub async fn download_post(
+    State(state): State<StateRef>,
+    body: Result<Json<RenderParams>, JsonRejection>,
+) -> Result<Response, Error> {
+    let Json(body) = body?;
+    download(body, state).await
+}
+async fn download(mut params: RenderParams, state: StateRef) -> Result<Response, Error> {
+    params.download = Some(true);
+    // the download is returned before the callback
+    extrace_page("download", params, state, |_, params, _, _| async move {
+        Err(format!("{} didn't download anything", params.url))
     })
     .await
 }
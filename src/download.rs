@@ -0,0 +1,165 @@
+use chromiumoxide::cdp::browser_protocol::browser::{
+    DownloadProgressState, EventDownloadProgress, EventDownloadWillBegin,
+    SetDownloadBehaviorBehavior, SetDownloadBehaviorParams,
+};
+use chromiumoxide::cdp::browser_protocol::network::EventResponseReceived;
+use chromiumoxide::error::CdpError;
+use chromiumoxide::listeners::EventStream;
+use chromiumoxide::{Browser, Page};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+
+/// A finished download, saved under its guid in a directory removed with it
+#[derive(Debug)]
+pub(crate) struct Download {
+    pub path: PathBuf,
+    pub url: String,
+    pub suggested_filename: String,
+    // what chrome got from the server, none when it wasn't seen
+    pub mime_type: Option<String>,
+    pub dir: TempDir,
+}
+
+impl Download {
+    /// The type the server sent, guessed from the file name when it is unknown or generic
+    pub fn content_type(&self) -> String {
+        match &self.mime_type {
+            Some(mime_type) if !mime_type.is_empty() && mime_type != "application/octet-stream" => {
+                mime_type.clone()
+            }
+            _ => mime_guess::from_path(&self.suggested_filename)
+                .first_or_octet_stream()
+                .to_string(),
+        }
+    }
+
+    /// `attachment` with an ascii fallback and the utf-8 name
+    pub fn content_disposition(&self) -> String {
+        let fallback: String = self
+            .suggested_filename
+            .chars()
+            .map(|c| match c {
+                ' '..='~' if c != '"' && c != '\\' => c,
+                _ => '_',
+            })
+            .collect();
+        format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            fallback,
+            urlencoding::encode(&self.suggested_filename)
+        )
+    }
+}
+
+// the mime types of the responses of the page by url, until dropped
+struct MimeTypes {
+    types: Arc<Mutex<HashMap<String, String>>>,
+    handle: JoinHandle<()>,
+}
+
+impl MimeTypes {
+    async fn start(page: &Page) -> Result<Self, CdpError> {
+        let mut events = page.event_listener::<EventResponseReceived>().await?;
+        let types = Arc::new(Mutex::new(HashMap::new()));
+        let recorded = types.clone();
+        let handle = tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .insert(event.response.url.clone(), event.response.mime_type.clone());
+            }
+        });
+        Ok(Self { types, handle })
+    }
+
+    fn get(&self, url: &str) -> Option<String> {
+        self.types.lock().unwrap().get(url).cloned()
+    }
+}
+
+impl Drop for MimeTypes {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Saves the downloads of the browser into a temporary directory and reports the first one
+pub(crate) struct DownloadWatcher {
+    dir: TempDir,
+    will_begin: EventStream<EventDownloadWillBegin>,
+    progress: EventStream<EventDownloadProgress>,
+    mime_types: MimeTypes,
+}
+
+impl DownloadWatcher {
+    pub async fn start(browser: &Browser, page: &Page, root: &Path) -> Result<Self, CdpError> {
+        std::fs::create_dir_all(root)?;
+        let dir = tempfile::Builder::new()
+            .prefix("download-")
+            .tempdir_in(root)?;
+        let will_begin = browser.event_listener::<EventDownloadWillBegin>().await?;
+        let progress = browser.event_listener::<EventDownloadProgress>().await?;
+        let mime_types = MimeTypes::start(page).await?;
+        browser
+            .execute(
+                SetDownloadBehaviorParams::builder()
+                    .behavior(SetDownloadBehaviorBehavior::AllowAndName)
+                    .download_path(dir.path().to_string_lossy())
+                    .events_enabled(true)
+                    .build()
+                    .map_err(CdpError::msg)?,
+            )
+            .await?;
+        Ok(Self {
+            dir,
+            will_begin,
+            progress,
+            mime_types,
+        })
+    }
+
+    /// The first download that begins, the caller bounds the wait
+    pub async fn begin(&mut self) -> Result<Arc<EventDownloadWillBegin>, String> {
+        let begin = self
+            .will_begin
+            .next()
+            .await
+            .ok_or_else(|| "browser closed before the download began".to_string())?;
+        log::info!(
+            "download begin {} -> {}",
+            begin.url,
+            begin.suggested_filename
+        );
+        Ok(begin)
+    }
+
+    /// Wait until the download is completed, the caller bounds it with a timeout
+    pub async fn complete(mut self, begin: &EventDownloadWillBegin) -> Result<Download, String> {
+        while let Some(event) = self.progress.next().await {
+            if event.guid != begin.guid {
+                continue;
+            }
+            match event.state {
+                DownloadProgressState::InProgress => {}
+                DownloadProgressState::Completed => {
+                    return Ok(Download {
+                        path: self.dir.path().join(&begin.guid),
+                        url: begin.url.clone(),
+                        suggested_filename: begin.suggested_filename.clone(),
+                        mime_type: self.mime_types.get(&begin.url),
+                        dir: self.dir,
+                    });
+                }
+                DownloadProgressState::Canceled => {
+                    return Err(format!("download {} canceled", begin.url));
+                }
+            }
+        }
+        Err("browser closed before the download completed".to_string())
+    }
+}
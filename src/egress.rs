@@ -0,0 +1,287 @@
+use crate::access::AccessPolicy;
+use crate::policy::UrlPolicy;
+use crate::proxy::Proxy;
+use crate::session::SessionOption;
+use crate::Error;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+
+// the request line and the headers of a proxied request
+const MAX_HEAD: usize = 64 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// not forwarded to the server, the connection is closed after one request
+const HOP_BY_HOP: [&str; 4] = [
+    "connection",
+    "proxy-connection",
+    "proxy-authorization",
+    "keep-alive",
+];
+
+/// Where the browser may connect to, checked on every connection of the egress proxy
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EgressPolicy {
+    pub access: Option<Arc<AccessPolicy>>,
+    pub url_policy: Option<Arc<UrlPolicy>>,
+}
+
+impl EgressPolicy {
+    /// Nothing to check, the browser can go direct
+    pub fn is_open(&self) -> bool {
+        self.access.iter().all(|access| access.is_open()) && self.url_policy.is_none()
+    }
+
+    fn check(&self, host: &str, port: u16, addrs: &[SocketAddr]) -> Result<(), String> {
+        if let Some(policy) = &self.url_policy {
+            policy.check_host(host, port)?;
+        }
+        match &self.access {
+            Some(access) => access.check_resolved(host, addrs),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Send the browser through an egress proxy, unless it has a proxy of its own,
+/// which resolves the names itself, or there is nothing to enforce
+pub(crate) async fn start_egress(
+    opt: &mut SessionOption,
+    policy: EgressPolicy,
+) -> Result<Option<EgressProxy>, Error> {
+    if opt.proxy.is_some() || policy.is_open() {
+        return Ok(None);
+    }
+    let egress = EgressProxy::start(policy).await?;
+    opt.proxy = Some(egress.proxy());
+    Ok(Some(egress))
+}
+
+/// A forward proxy on loopback in front of one browser. Names are resolved and
+/// checked here and the connection goes to the checked address, so a name can't
+/// rebind to an internal one, and workers, websockets and the targets attached
+/// after the page can't go around the policy. Stopped when dropped
+pub(crate) struct EgressProxy {
+    addr: SocketAddr,
+    #[cfg(feature = "content")]
+    denied: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for EgressProxy {
+    fn drop(&mut self) {
+        // the connections are aborted with their JoinSet
+        self.task.abort();
+    }
+}
+
+impl EgressProxy {
+    pub async fn start(policy: EgressPolicy) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let policy = Arc::new(policy);
+        let denied = Arc::new(AtomicUsize::new(0));
+        let task = tokio::spawn({
+            let denied = denied.clone();
+            async move {
+                let mut connections = JoinSet::new();
+                loop {
+                    let stream = match listener.accept().await {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            log::warn!("egress {} accept error: {}", addr, e);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    };
+                    while connections.try_join_next().is_some() {}
+                    let policy = policy.clone();
+                    let denied = denied.clone();
+                    connections.spawn(async move {
+                        if let Err(e) = serve(stream, &policy, &denied).await {
+                            log::debug!("egress {} error: {}", addr, e);
+                        }
+                    });
+                }
+            }
+        });
+        log::debug!("egress proxy started on {}", addr);
+        Ok(Self {
+            addr,
+            #[cfg(feature = "content")]
+            denied,
+            task,
+        })
+    }
+
+    /// The `--proxy-server` of the browser
+    pub fn proxy(&self) -> Proxy {
+        Proxy::try_from(format!("http://{}", self.addr).as_str()).expect("loopback proxy")
+    }
+
+    #[cfg(feature = "content")]
+    /// Connections refused by the policy
+    pub fn denied(&self) -> usize {
+        self.denied.load(Ordering::Relaxed)
+    }
+}
+
+// where a proxied request goes, the head is rewritten for plain http
+struct Target {
+    host: String,
+    port: u16,
+    tunnel: bool,
+    head: Vec<u8>,
+}
+
+fn split_host_port(value: &str) -> Option<(String, u16)> {
+    let (host, port) = value.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some((
+        host.trim_end_matches('.').to_lowercase(),
+        port.parse().ok()?,
+    ))
+}
+
+fn parse_head(head: &str) -> Option<Target> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let (method, target, version) = (
+        request_line.next()?,
+        request_line.next()?,
+        request_line.next()?,
+    );
+    if method == "CONNECT" {
+        let (host, port) = split_host_port(target)?;
+        return Some(Target {
+            host,
+            port,
+            tunnel: true,
+            head: Vec::new(),
+        });
+    }
+
+    // absolute-form to origin-form, one request per connection
+    let u = url::Url::parse(target).ok()?;
+    if u.scheme() != "http" {
+        return None;
+    }
+    let path = &u[url::Position::BeforePath..url::Position::AfterQuery];
+    let mut rewritten = format!("{} {} {}\r\n", method, path, version);
+    for line in lines.filter(|line| !line.is_empty()) {
+        let name = line.split(':').next().unwrap_or_default().trim();
+        if !HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            rewritten.push_str(line);
+            rewritten.push_str("\r\n");
+        }
+    }
+    rewritten.push_str("Connection: close\r\n\r\n");
+    Some(Target {
+        host: u
+            .host_str()?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_lowercase(),
+        port: u.port_or_known_default()?,
+        tunnel: false,
+        head: rewritten.into_bytes(),
+    })
+}
+
+// the head and the bytes read after it, None when the client went away first
+async fn read_head(stream: &mut TcpStream) -> io::Result<Option<(String, Vec<u8>)>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            buf.truncate(end);
+            return Ok(Some((String::from_utf8_lossy(&buf).into_owned(), rest)));
+        }
+        if buf.len() > MAX_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+        match stream.read(&mut chunk).await? {
+            0 => return Ok(None),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, reason: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason.len(),
+        reason
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    match host.parse::<IpAddr>() {
+        Ok(addr) => Ok(vec![SocketAddr::new(addr, port)]),
+        Err(_) => Ok(tokio::net::lookup_host((host, port)).await?.collect()),
+    }
+}
+
+async fn serve(
+    mut client: TcpStream,
+    policy: &EgressPolicy,
+    denied: &AtomicUsize,
+) -> io::Result<()> {
+    let Some((head, rest)) = read_head(&mut client).await? else {
+        return Ok(());
+    };
+    let Some(target) = parse_head(&head) else {
+        return respond(&mut client, "400 Bad Request", "unsupported proxy request").await;
+    };
+    let addrs = match resolve(&target.host, target.port).await {
+        Ok(addrs) if !addrs.is_empty() => addrs,
+        Ok(_) | Err(_) => {
+            let reason = format!("resolve {} failed", target.host);
+            return respond(&mut client, "502 Bad Gateway", &reason).await;
+        }
+    };
+    if let Err(e) = policy.check(&target.host, target.port, &addrs) {
+        log::warn!("egress denied {}:{}: {}", target.host, target.port, e);
+        denied.fetch_add(1, Ordering::Relaxed);
+        return respond(&mut client, "403 Forbidden", &e).await;
+    }
+
+    // only the checked addresses, never a second lookup
+    let mut upstream =
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&addrs[..])).await {
+            Ok(Ok(upstream)) => upstream,
+            Ok(Err(e)) => {
+                let reason = format!("connect {} error: {}", target.host, e);
+                return respond(&mut client, "502 Bad Gateway", &reason).await;
+            }
+            Err(_) => {
+                let reason = format!("connect {} timeout", target.host);
+                return respond(&mut client, "504 Gateway Timeout", &reason).await;
+            }
+        };
+    match target.tunnel {
+        true => {
+            client
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await?
+        }
+        false => upstream.write_all(&target.head).await?,
+    }
+    upstream.write_all(&rest).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
@@ -1,26 +1,157 @@
-use axum::http::StatusCode;
+use axum::extract::Request;
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use chromiumoxide::error::CdpError;
+use serde::Serialize;
 use std::fmt;
 
+/// Seconds a client should wait before retrying when the server is at capacity
+const RETRY_AFTER_SECS: u64 = 5;
+
+tokio::task_local! {
+    // the X-Request-Id of the request being served
+    static REQUEST_ID: String;
+}
+
+/// The stable error codes of the api, clients match on these instead of the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InvalidParams,
+    AccessDenied,
+    NotFound,
+    SessionNotFound,
+    NoDownload,
+    TooManySessions,
+    InternalError,
+    BrowserError,
+    UpstreamError,
+    UpstreamStatus,
+    DnsError,
+    TlsError,
+    ConnectionError,
+    NavigationError,
+    NavigationBlocked,
+    ServiceUnavailable,
+    BrowserLaunchFailed,
+    BrowserClosed,
+    SessionCanceled,
+    Timeout,
+    NavigationTimeout,
+    RenderTimeout,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidParams => StatusCode::BAD_REQUEST,
+            Self::AccessDenied | Self::NavigationBlocked => StatusCode::FORBIDDEN,
+            Self::NotFound | Self::SessionNotFound => StatusCode::NOT_FOUND,
+            Self::NoDownload => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManySessions => StatusCode::TOO_MANY_REQUESTS,
+            Self::InternalError | Self::BrowserError => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::UpstreamError
+            | Self::UpstreamStatus
+            | Self::DnsError
+            | Self::TlsError
+            | Self::ConnectionError
+            | Self::NavigationError => StatusCode::BAD_GATEWAY,
+            Self::ServiceUnavailable
+            | Self::BrowserLaunchFailed
+            | Self::BrowserClosed
+            | Self::SessionCanceled => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Timeout | Self::NavigationTimeout | Self::RenderTimeout => {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+        }
+    }
+
+    // the code of an error only known by its status
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => Self::InvalidParams,
+            StatusCode::FORBIDDEN => Self::AccessDenied,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => Self::TooManySessions,
+            StatusCode::BAD_GATEWAY => Self::UpstreamError,
+            StatusCode::SERVICE_UNAVAILABLE => Self::ServiceUnavailable,
+            StatusCode::GATEWAY_TIMEOUT => Self::Timeout,
+            _ => Self::InternalError,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = serde_json::to_value(self).unwrap_or_default();
+        write!(f, "{}", code.as_str().unwrap_or_default())
+    }
+}
+
 #[derive(Debug)]
 pub struct Error {
     status_code: StatusCode,
+    code: ErrorCode,
     message: String,
+    session_id: Option<String>,
+    details: Option<serde_json::Value>,
+    retry_after: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: ErrorCode,
+    message: &'a str,
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a serde_json::Value>,
 }
 
 impl Error {
     pub fn new(status_code: StatusCode, message: &str) -> Self {
         Self {
             status_code,
+            code: ErrorCode::from_status(status_code),
             message: message.to_string(),
+            session_id: None,
+            details: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn from_code(code: ErrorCode, message: &str) -> Self {
+        let error = Self::new(code.status(), message);
+        match code {
+            ErrorCode::TooManySessions | ErrorCode::BrowserLaunchFailed => Self {
+                code,
+                retry_after: Some(RETRY_AFTER_SECS),
+                ..error
+            },
+            _ => Self { code, ..error },
         }
     }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn with_session(mut self, session_id: &str) -> Self {
+        self.session_id = Some(session_id.to_string());
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} {}", self.status_code, self.message)
+        write!(f, "{} {} {}", self.status_code, self.code, self.message)
     }
 }
 
@@ -28,24 +159,57 @@ impl std::error::Error for Error {}
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let builder = axum::http::Response::builder().status(self.status_code.as_u16());
-        if self.message.is_empty() {
-            builder.body(
-                self.status_code
-                    .canonical_reason()
-                    .unwrap_or_default()
-                    .into(),
-            )
-        } else {
-            builder.body(self.message.into())
+        let message = match self.message.is_empty() {
+            true => self.status_code.canonical_reason().unwrap_or_default(),
+            false => self.message.as_str(),
+        };
+        let body = ErrorBody {
+            code: self.code,
+            message,
+            request_id: REQUEST_ID.try_with(|id| id.clone()).ok(),
+            session_id: self.session_id.as_deref(),
+            details: self.details.as_ref(),
+        };
+        let builder = axum::http::Response::builder()
+            .status(self.status_code.as_u16())
+            .header("Content-Type", "application/json");
+        match self.retry_after {
+            Some(secs) => builder.header("Retry-After", secs),
+            None => builder,
         }
+        .body(serde_json::to_string(&body).unwrap_or_default().into())
         .unwrap()
     }
 }
 
+/// Tag the request with its X-Request-Id, or a new one, for the error bodies and the logs
+pub async fn with_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("X-Request-Id", value);
+    }
+    response
+}
+
 impl From<CdpError> for Error {
     fn from(err: CdpError) -> Self {
-        Self::new(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string())
+        let code = match &err {
+            CdpError::Timeout => ErrorCode::Timeout,
+            CdpError::LaunchExit(..) | CdpError::LaunchTimeout(..) | CdpError::LaunchIo(..) => {
+                ErrorCode::BrowserLaunchFailed
+            }
+            _ => ErrorCode::BrowserError,
+        };
+        Self::from_code(code, &err.to_string())
     }
 }
 
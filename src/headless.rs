@@ -2,26 +2,49 @@ use std::{cell::RefCell, time::SystemTime};
 
 use axum::{
     body::Body,
-    extract::{ws, Query, State, WebSocketUpgrade},
+    extract::{rejection::QueryRejection, ws, Query, State, WebSocketUpgrade},
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+#[cfg(feature = "content")]
+use chromiumoxide::cdp::browser_protocol::target::CreateTargetParams;
 use chromiumoxide::{
-    browser::BrowserConfigBuilder, cdp::browser_protocol::page::CaptureScreenshotFormat,
-    page::ScreenshotParams, Browser,
+    browser::{BrowserConfig, BrowserConfigBuilder},
+    cdp::browser_protocol::{
+        browser::BrowserContextId,
+        page::CaptureScreenshotFormat,
+        target::{CreateBrowserContextParams, EventTargetCreated},
+    },
+    error::CdpError,
+    page::ScreenshotParams,
+    Browser, Handler, Page,
 };
 use futures::{SinkExt, StreamExt};
-use tokio::{select, sync::oneshot};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    select,
+    sync::{oneshot, watch},
+    time,
+};
 use tokio_tungstenite::tungstenite;
 
 use crate::{
     devices::{get_device, Device},
+    egress::{start_egress, EgressPolicy},
+    intercept::{InterceptGuard, Interceptor},
+    pool::{is_poolable, BrowserPool, Lease, PooledBrowser},
+    proxy::select_proxy,
+    queue::Priority,
     session::{
         handle_index_page, CreateSessionParams, Session, SessionGuard, SessionOption, SessionType,
     },
-    Error, StateRef,
+    throttle::{Throttle, ThrottleOptions},
+    Error, ErrorCode, StateRef,
 };
 
+// how long a page fetched for a screenshot may take to attach
+const PAGE_ATTACH_TIMEOUT: Duration = Duration::from_secs(5);
+
 fn from_ts_message(msg: tungstenite::Message) -> Option<ws::Message> {
     match msg {
         tungstenite::Message::Text(text) => Some(ws::Message::Text(text)),
@@ -54,21 +77,32 @@ fn to_ts_message(msg: ws::Message) -> tungstenite::Message {
         ws::Message::Close(None) => tungstenite::Message::Close(None),
     }
 }
-pub(crate) async fn create_headless_browser_session(
-    opt: SessionOption,
-    device: Option<Device>,
-    state: StateRef,
-    shutdown_tx: Option<oneshot::Sender<()>>,
-) -> Result<Session, Error> {
-    if state.is_full() {
-        return Err(Error::new(
-            StatusCode::SERVICE_UNAVAILABLE,
-            "too many sessions",
-        ));
-    }
-
-    let id = opt.uuid.unwrap_or(uuid::Uuid::new_v4().to_string());
-    let data_dir = format!("{}/{}", state.data_root.trim_end_matches("/"), id);
+/// The chrome flags of a session, its data_dir is the user data dir
+pub(crate) fn browser_config(
+    opt: &SessionOption,
+    device: Option<&Device>,
+    data_dir: &str,
+) -> Result<BrowserConfig, Error> {
+    let is_lang = |lang: &str| {
+        !lang.is_empty()
+            && lang
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    let lang_arg = match &opt.lang {
+        Some(lang) if !is_lang(lang) => {
+            return Err(Error::new(StatusCode::BAD_REQUEST, "invalid lang"));
+        }
+        Some(lang) => Some(format!("--lang={}", lang)),
+        None => None,
+    };
+    // chrome sends loopback requests direct unless told otherwise
+    let proxy_args = opt.proxy.as_ref().map(|proxy| {
+        vec![
+            format!("--proxy-server={}", proxy.server()),
+            "--proxy-bypass-list=<-loopback>".to_string(),
+        ]
+    });
 
     let config = BrowserConfigBuilder::default()
         .disable_default_args()
@@ -96,9 +130,36 @@ pub(crate) async fn create_headless_browser_session(
             "--password-store=basic",
             "--use-mock-keychain",
             "--enable-blink-features=IdleDetection",
-            "--lang=en_US",
         ])
-        .user_data_dir(&data_dir);
+        .args(lang_arg)
+        .args(proxy_args.into_iter().flatten())
+        .user_data_dir(data_dir);
+
+    let config = match opt.enable_cache {
+        true => config.enable_cache(),
+        false => config.disable_cache(),
+    };
+
+    match device {
+        Some(d) => {
+            let viewport = d.get_viewport(opt.landscape);
+            config.viewport(viewport)
+        }
+        None => config,
+    }
+    .build()
+    .map_err(|e| Error::new(StatusCode::SERVICE_UNAVAILABLE, &e))
+}
+
+pub(crate) async fn create_headless_browser_session(
+    opt: SessionOption,
+    device: Option<Device>,
+    state: StateRef,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+) -> Result<Session, Error> {
+    let id = opt.uuid.clone().unwrap_or(uuid::Uuid::new_v4().to_string());
+    let data_dir = format!("{}/{}", state.data_root.trim_end_matches("/"), id);
+    let config = browser_config(&opt, device.as_ref(), &data_dir)?;
 
     std::fs::create_dir_all(&data_dir)?;
 
@@ -119,37 +180,32 @@ pub(crate) async fn create_headless_browser_session(
         }
     });
 
-    let config = match opt.enable_cache {
-        true => config.enable_cache(),
-        false => config.disable_cache(),
-    };
-
-    let config = match device.as_ref() {
-        Some(d) => {
-            let viewport = d.get_viewport(opt.landscape);
-            config.viewport(viewport)
-        }
-        None => config,
-    }
-    .build()
-    .map_err(|e| Error::new(StatusCode::SERVICE_UNAVAILABLE, &e))?;
-
     let (browser, handler) = Browser::launch(config).await?;
 
     let ws_url = browser.websocket_address().to_string();
-    log::debug!("create session, id: {} dir: {} -> {}", id, data_dir, ws_url);
+    log::debug!(
+        "create session, id: {} dir: {} proxy: {:?} -> {}",
+        id,
+        data_dir,
+        opt.proxy,
+        ws_url
+    );
 
     Ok(Session {
         id,
         r#type: SessionType::Headless,
         data_dir,
         endpoint: ws_url,
+        context: None,
         device,
         cleanup: opt.cleanup,
         enable_cache: opt.enable_cache,
         shutdown_tx: RefCell::new(shutdown_tx),
         browser: RefCell::new(Some(browser)),
         headless_handler: RefCell::new(Some(handler)),
+        expiry: state.expiry.with_overrides(&opt),
+        expired: RefCell::new(None),
+        admission: None,
         created_at: SystemTime::now(),
         updated_at: RefCell::new(SystemTime::now()),
         #[cfg(feature = "remote")]
@@ -157,21 +213,252 @@ pub(crate) async fn create_headless_browser_session(
     })
 }
 
+/// How the browser of a session ends, its own handler or the pool task polling it
+pub(crate) enum BrowserExit {
+    Handler(Box<Handler>),
+    Pooled(watch::Receiver<bool>),
+}
+
+impl BrowserExit {
+    pub async fn wait(&mut self) {
+        match self {
+            Self::Handler(handler) => while handler.next().await.is_some() {},
+            Self::Pooled(exited) => {
+                exited.wait_for(|exited| *exited).await.ok();
+            }
+        }
+    }
+}
+
+enum BrowserHandle {
+    Launched(Browser),
+    Exclusive(Arc<BrowserPool>, PooledBrowser),
+    Shared(Arc<BrowserPool>, Arc<PooledBrowser>),
+}
+
+/// The browser of a session, taken from the warm pool when the options allow it.
+/// A shared browser only hands out pages of the session's own browser context
+pub(crate) struct SessionBrowser {
+    handle: BrowserHandle,
+    context: Option<BrowserContextId>,
+}
+
+impl SessionBrowser {
+    pub fn browser(&self) -> &Browser {
+        match &self.handle {
+            BrowserHandle::Launched(browser) => browser,
+            BrowserHandle::Exclusive(_, pooled) => &pooled.browser,
+            BrowserHandle::Shared(_, pooled) => &pooled.browser,
+        }
+    }
+
+    #[cfg(feature = "content")]
+    pub fn context(&self) -> Option<BrowserContextId> {
+        self.context.clone()
+    }
+
+    #[cfg(feature = "content")]
+    pub async fn new_page(&self) -> Result<Page, CdpError> {
+        let mut params = CreateTargetParams::new("about:blank");
+        params.browser_context_id = self.context.clone();
+        self.browser().new_page(params).await
+    }
+
+    /// Kill the browser, give it back or dispose the context
+    pub async fn finish(self) {
+        match self.handle {
+            BrowserHandle::Launched(mut browser) => {
+                browser.kill().await;
+            }
+            BrowserHandle::Exclusive(pool, pooled) => pool.release_exclusive(pooled).await,
+            BrowserHandle::Shared(pool, pooled) => {
+                if let Some(context) = self.context {
+                    if let Err(e) = pooled.browser.dispose_browser_context(context).await {
+                        log::warn!("dispose context of {} error: {}", pooled.id, e);
+                    }
+                }
+                pool.release_shared(pooled).await;
+            }
+        }
+    }
+}
+
+pub(crate) async fn start_headless_browser_session(
+    opt: SessionOption,
+    device: Option<Device>,
+    state: StateRef,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    lease: Lease,
+    priority: Priority,
+) -> Result<(Session, SessionBrowser, BrowserExit), Error> {
+    let admission = state.queue.admit(priority).await?;
+    if let Some(pool) = state
+        .pool
+        .as_ref()
+        .filter(|_| is_poolable(&opt, device.as_ref(), lease))
+    {
+        // a busy pool launches one more browser, up to --pool-max
+        let pooled = match lease {
+            Lease::Exclusive => {
+                let pooled = match pool.acquire_exclusive() {
+                    Some(pooled) => Some(pooled),
+                    None => pool.launch_exclusive().await,
+                };
+                pooled.map(|pooled| {
+                    let exit = BrowserExit::Pooled(pooled.exited());
+                    (
+                        pooled.id.clone(),
+                        BrowserHandle::Exclusive(pool.clone(), pooled),
+                        exit,
+                    )
+                })
+            }
+            Lease::Context => {
+                let pooled = match pool.acquire_shared() {
+                    Some(pooled) => Some(pooled),
+                    None => pool.launch_shared().await,
+                };
+                pooled.map(|pooled| {
+                    let exit = BrowserExit::Pooled(pooled.exited());
+                    let id = uuid::Uuid::new_v4().to_string();
+                    (id, BrowserHandle::Shared(pool.clone(), pooled), exit)
+                })
+            }
+        };
+        if let Some((id, handle, exit)) = pooled {
+            let (data_dir, endpoint) = match &handle {
+                BrowserHandle::Exclusive(_, pooled) => (&pooled.data_dir, &pooled.endpoint),
+                BrowserHandle::Shared(_, pooled) => (&pooled.data_dir, &pooled.endpoint),
+                BrowserHandle::Launched(_) => unreachable!(),
+            };
+            let mut session = Session {
+                id,
+                r#type: SessionType::Headless,
+                data_dir: data_dir.clone(),
+                endpoint: endpoint.clone(),
+                context: None,
+                device: None,
+                // the pool removes the data_dir with the browser
+                cleanup: false,
+                enable_cache: false,
+                shutdown_tx: RefCell::new(shutdown_tx),
+                browser: RefCell::new(None),
+                headless_handler: RefCell::new(None),
+                expiry: state.expiry.with_overrides(&opt),
+                expired: RefCell::new(None),
+                admission: Some(admission),
+                created_at: SystemTime::now(),
+                updated_at: RefCell::new(SystemTime::now()),
+                #[cfg(feature = "remote")]
+                remote_handler: None,
+            };
+            let mut browser = SessionBrowser {
+                handle,
+                context: None,
+            };
+            if lease == Lease::Context {
+                let params = CreateBrowserContextParams {
+                    proxy_server: opt.proxy.as_ref().map(|proxy| proxy.server()),
+                    proxy_bypass_list: opt.proxy.as_ref().map(|_| "<-loopback>".to_string()),
+                    ..Default::default()
+                };
+                match browser.browser().create_browser_context(params).await {
+                    Ok(context) => {
+                        session.context = Some(context.clone());
+                        browser.context = Some(context);
+                    }
+                    Err(e) => {
+                        browser.finish().await;
+                        return Err(e.into());
+                    }
+                }
+            }
+            return Ok((session, browser, exit));
+        }
+    }
+
+    let mut session = create_headless_browser_session(opt, device, state, shutdown_tx).await?;
+    session.admission = Some(admission);
+    let browser = session.browser.take().ok_or("browser is None")?;
+    let handler = session.headless_handler.take().ok_or("handler is None")?;
+    let browser = SessionBrowser {
+        handle: BrowserHandle::Launched(browser),
+        context: None,
+    };
+    Ok((session, browser, BrowserExit::Handler(Box::new(handler))))
+}
+
+async fn prepare_page(
+    page: Page,
+    throttle: Option<&Throttle>,
+    interceptor: Option<&Interceptor>,
+) -> Result<Option<InterceptGuard>, Error> {
+    if let Some(throttle) = throttle {
+        throttle.apply(&page).await?;
+    }
+    match interceptor {
+        Some(interceptor) => Ok(Some(interceptor.clone().start(page).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Throttle and intercept the open pages and every page created later,
+/// runs until the browser is gone
+async fn prepare_pages(
+    browser: &Browser,
+    throttle: Option<Throttle>,
+    interceptor: Option<Interceptor>,
+) -> Result<(), CdpError> {
+    if throttle.is_none() && interceptor.is_none() {
+        return Ok(());
+    }
+    let mut created = browser.event_listener::<EventTargetCreated>().await?;
+    let mut guards = Vec::new();
+    for page in browser.pages().await? {
+        match prepare_page(page, throttle.as_ref(), interceptor.as_ref()).await {
+            Ok(guard) => guards.extend(guard),
+            Err(e) => log::warn!("prepare page error: {}", e),
+        }
+    }
+
+    while let Some(event) = created.next().await {
+        if event.target_info.r#type != "page" {
+            continue;
+        }
+        // the handler attaches to the new target in the background
+        for _ in 0..50 {
+            if let Ok(page) = browser.get_page(event.target_info.target_id.clone()).await {
+                match prepare_page(page, throttle.as_ref(), interceptor.as_ref()).await {
+                    Ok(guard) => guards.extend(guard),
+                    Err(e) => log::warn!("prepare page {:?} error: {}", event.target_info.url, e),
+                }
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    Ok(())
+}
+
 /// Workflow
 /// 1. render index.html when GET / and request not websocket
 /// 2. create an chrome session when websocket request    
 ///     2.1 bridge websocket to chrome session
 pub(crate) async fn create_headless_session(
     ws: Option<WebSocketUpgrade>,
-    Query(params): Query<CreateSessionParams>,
+    params: Result<Query<CreateSessionParams>, QueryRejection>,
     State(state): State<StateRef>,
 ) -> Response {
     let ws = match ws {
         Some(ws) => ws,
         None => return handle_index_page().await,
     };
+    let params = match params {
+        Ok(params) => params,
+        Err(e) => return Error::from(e).into_response(),
+    };
 
-    match handle_headless_session(ws, Query(params), State(state)).await {
+    match handle_headless_session(ws, params, State(state)).await {
         Ok(r) => r,
         Err(e) => {
             log::error!("handle_session error: {}", e);
@@ -185,16 +472,64 @@ pub(crate) async fn handle_headless_session(
     Query(params): Query<CreateSessionParams>,
     State(state): State<StateRef>,
 ) -> Result<Response, Error> {
-    let opt = SessionOption::from(&params);
+    params.validate()?;
+    let mut opt = SessionOption::from(&params);
+    opt.proxy = select_proxy(
+        state.proxy_pool.as_deref(),
+        params.proxy.as_deref(),
+        params.proxy_session.as_deref(),
+    )
+    .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
+    if let (Some(_), Some(proxy)) = (&params.proxy, &opt.proxy) {
+        state
+            .access
+            .check_proxy(proxy)
+            .await
+            .map_err(|e| Error::new(StatusCode::FORBIDDEN, &e))?;
+    }
+    // proxy credentials are answered, and the access policy enforced, on every page of the session
+    let mut interceptor = Interceptor::default();
+    if let Some((username, password)) = opt.proxy.as_ref().and_then(|p| p.credentials()) {
+        interceptor = interceptor.with_proxy_credentials(username, password);
+    }
+    if state.session_access_policy {
+        interceptor = interceptor.with_access(state.access.clone());
+    }
+    let url_policy = state.url_policy(params.api_key.as_deref())?;
+    if let Some(policy) = &url_policy {
+        interceptor = interceptor.with_url_policy(policy.clone());
+    }
+    // what the interceptor can't see, workers, websockets and late targets, meets the egress proxy
+    let egress = start_egress(
+        &mut opt,
+        EgressPolicy {
+            access: state.session_access_policy.then(|| state.access.clone()),
+            url_policy,
+        },
+    )
+    .await?;
+    let interceptor = (!interceptor.is_empty()).then_some(interceptor);
     let device = get_device(&params.emulating_device.clone().unwrap_or_default());
+    let throttle = ThrottleOptions {
+        profile: params.throttle.as_deref(),
+        download_kbps: params.download_kbps,
+        upload_kbps: params.upload_kbps,
+        latency: params.latency,
+        cpu_slowdown: params.cpu_slowdown,
+    }
+    .build()
+    .map_err(|e| Error::new(StatusCode::BAD_REQUEST, &e))?;
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
-    let session =
-        create_headless_browser_session(opt, device, state.clone(), Some(shutdown_tx)).await?;
-    let mut browser: Browser = session.browser.take().ok_or_else(|| "browser is None")?;
-    let mut handler = session
-        .headless_handler
-        .take()
-        .ok_or_else(|| "handler is None")?;
+    // the client gets the whole browser
+    let (session, browser, mut exit) = start_headless_browser_session(
+        opt,
+        device,
+        state.clone(),
+        Some(shutdown_tx),
+        Lease::Exclusive,
+        Priority::Interactive,
+    )
+    .await?;
 
     let (upstream, _) = tokio_tungstenite::connect_async(&session.endpoint)
         .await
@@ -203,6 +538,7 @@ pub(crate) async fn handle_headless_session(
     let (mut server_ws_tx, mut server_ws_rx) = upstream.split();
 
     let r = ws.on_upgrade(|client_stream| async move {
+        let _egress = egress;
         let id = session.id.clone();
         let _guard = SessionGuard::new(state.clone(), session);
 
@@ -245,31 +581,59 @@ pub(crate) async fn handle_headless_session(
         select! {
             _ = server_to_client => {}
             _ = client_to_server => {}
+            _ = exit.wait() => { }
             _ = async {
-                while let Some(_) = handler.next().await {}
+                if let Err(e) = prepare_pages(browser.browser(), throttle, interceptor).await {
+                    log::warn!("prepare pages id: {} error: {}", id, e);
+                }
+                std::future::pending::<()>().await
             } => { }
             _  = shutdown_rx => {
                 log::info!("shutdown_rx shutdown id: {}", id);
             }
         }
-        browser.kill().await;
+        browser.finish().await;
     });
     Ok(r)
 }
 
+// the first page of a session, in its own context when it shares the browser
+async fn session_page(
+    browser: &mut Browser,
+    context: Option<&BrowserContextId>,
+) -> Result<Option<Page>, CdpError> {
+    let target = browser.fetch_targets().await?.into_iter().find(|target| {
+        target.r#type == "page"
+            && context.is_none_or(|context| target.browser_context_id.as_ref() == Some(context))
+    });
+    let Some(target) = target else {
+        return Ok(None);
+    };
+    // the page is there once the fetched target is attached
+    let deadline = time::Instant::now() + PAGE_ATTACH_TIMEOUT;
+    loop {
+        match browser.get_page(target.target_id.clone()).await {
+            Ok(page) => return Ok(Some(page)),
+            Err(CdpError::NotFound) if time::Instant::now() < deadline => {
+                time::sleep(Duration::from_millis(50)).await
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub(crate) async fn screen_headless_screen(
     session_id: String,
     state: StateRef,
 ) -> Result<Response, crate::Error> {
-    let endpoint_url = state
+    let (endpoint_url, context) = state
         .sessions
         .lock()
         .unwrap()
         .iter()
         .find(|s| s.id == session_id)
-        .ok_or_else(|| crate::Error::new(StatusCode::BAD_GATEWAY, "session not found"))?
-        .endpoint
-        .clone();
+        .map(|s| (s.endpoint.clone(), s.context.clone()))
+        .ok_or_else(|| crate::Error::from_code(ErrorCode::SessionNotFound, "session not found"))?;
 
     let (mut browser, mut handler) = Browser::connect(endpoint_url).await?;
     let handler_job = tokio::spawn(async move { while let Some(_) = handler.next().await {} });
@@ -278,21 +642,22 @@ pub(crate) async fn screen_headless_screen(
         .format(CaptureScreenshotFormat::Png)
         .build();
 
-    let screenshot = browser
-        .pages()
-        .await
-        .map(|pages| pages[0].clone())?
-        .screenshot(params)
-        .await?;
+    let screenshot = match session_page(&mut browser, context.as_ref()).await {
+        Ok(Some(page)) => page.screenshot(params).await.map_err(crate::Error::from),
+        Ok(None) => Err(
+            crate::Error::from_code(ErrorCode::NotFound, "session has no page")
+                .with_session(&session_id),
+        ),
+        Err(e) => Err(e.into()),
+    };
+    // only disconnect, closing would stop the browser of the session
+    drop(browser);
+    handler_job.abort();
+    let screenshot = screenshot?;
 
     let mut response = Response::new(Body::from(screenshot));
     response
         .headers_mut()
         .insert("Content-Type", "image/png".parse().unwrap());
-
-    browser.close().await.ok();
-    browser.wait().await.ok();
-    handler_job.abort();
-
     Ok(response.into_response())
 }
@@ -0,0 +1,454 @@
+use crate::access::{AccessPolicy, HostCache};
+use crate::adblock::{self, FilterList};
+use crate::policy::UrlPolicy;
+use crate::Error;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chromiumoxide::cdp::browser_protocol::fetch::{
+    AuthChallengeResponse, AuthChallengeResponseResponse, AuthChallengeSource,
+    ContinueRequestParams, ContinueWithAuthParams, EnableParams, EventAuthRequired,
+    EventRequestPaused, FailRequestParams, FulfillRequestParams, HeaderEntry, RequestPattern,
+};
+use chromiumoxide::cdp::browser_protocol::network::{ErrorReason, ResourceType};
+use chromiumoxide::cdp::browser_protocol::page::FrameId;
+use chromiumoxide::Page;
+use futures::StreamExt;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::{select, task::JoinHandle};
+
+/// `/regex/`, or a glob where `*` matches anything, against the whole url
+#[derive(Debug, Clone)]
+pub(crate) struct UrlPattern(Regex);
+
+impl TryFrom<&str> for UrlPattern {
+    type Error = String;
+
+    fn try_from(pattern: &str) -> Result<Self, Self::Error> {
+        let regex = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(regex) if !regex.is_empty() => regex.to_string(),
+            _ => format!(
+                "^{}$",
+                pattern
+                    .split('*')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(".*")
+            ),
+        };
+        Regex::new(&regex)
+            .map(UrlPattern)
+            .map_err(|e| format!("invalid url pattern {}: {}", pattern, e))
+    }
+}
+
+impl UrlPattern {
+    pub fn is_match(&self, url: &str) -> bool {
+        self.0.is_match(url)
+    }
+}
+
+#[cfg(feature = "content")]
+/// Resource types accepted by `block_resources`
+pub(crate) fn parse_resource_type(name: &str) -> Result<Vec<ResourceType>, String> {
+    match name {
+        "image" => Ok(vec![ResourceType::Image]),
+        "font" => Ok(vec![ResourceType::Font]),
+        "media" => Ok(vec![ResourceType::Media]),
+        "stylesheet" => Ok(vec![ResourceType::Stylesheet]),
+        "script" => Ok(vec![ResourceType::Script]),
+        "xhr" => Ok(vec![ResourceType::Xhr, ResourceType::Fetch]),
+        _ => Err(format!("unknown resource type {}", name)),
+    }
+}
+
+/// A canned response for the requests matching `url`
+#[derive(Debug, Deserialize)]
+pub struct MockResponse {
+    url: String,
+    status: Option<i64>,
+    headers: Option<HashMap<String, String>>,
+    body: Option<String>,
+    body_base64: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Mock {
+    pattern: UrlPattern,
+    status: i64,
+    headers: Vec<HeaderEntry>,
+    // base64, as Fetch.fulfillRequest wants it
+    body: String,
+}
+
+impl TryFrom<&MockResponse> for Mock {
+    type Error = String;
+
+    fn try_from(mock: &MockResponse) -> Result<Self, Self::Error> {
+        let body = match (&mock.body, &mock.body_base64) {
+            (Some(_), Some(_)) => {
+                return Err(format!("mock {} has both body and body_base64", mock.url))
+            }
+            (_, Some(body)) => {
+                STANDARD
+                    .decode(body.as_bytes())
+                    .map_err(|e| format!("mock {} body_base64 is not base64: {}", mock.url, e))?;
+                body.clone()
+            }
+            (body, None) => STANDARD.encode(body.as_deref().unwrap_or_default()),
+        };
+        let status = mock.status.unwrap_or(200);
+        if !(100..=599).contains(&status) {
+            return Err(format!("mock {} has invalid status {}", mock.url, status));
+        }
+        Ok(Self {
+            pattern: UrlPattern::try_from(mock.url.as_str())?,
+            status,
+            headers: mock
+                .headers
+                .iter()
+                .flatten()
+                .map(|(name, value)| HeaderEntry::new(name, value))
+                .collect(),
+            body,
+        })
+    }
+}
+
+/// Send the requests of one origin to another, e.g. prod api to staging
+#[derive(Debug, Deserialize)]
+pub struct RewriteRule {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Rewrite {
+    from: String,
+    to: String,
+}
+
+impl TryFrom<&RewriteRule> for Rewrite {
+    type Error = String;
+
+    fn try_from(rule: &RewriteRule) -> Result<Self, Self::Error> {
+        let origin = |s: &str| match url::Url::parse(s).map(|u| u.origin()) {
+            Ok(origin @ url::Origin::Tuple(..)) => Ok(origin.ascii_serialization()),
+            _ => Err(format!("rewrite {} is not an http origin", s)),
+        };
+        Ok(Self {
+            from: origin(&rule.from)?,
+            to: origin(&rule.to)?,
+        })
+    }
+}
+
+impl Rewrite {
+    pub fn apply(&self, url: &str) -> Option<String> {
+        let rest = url.strip_prefix(&self.from)?;
+        match rest.chars().next() {
+            None | Some('/') | Some('?') | Some('#') => Some(format!("{}{}", self.to, rest)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct InterceptStats {
+    blocked: AtomicUsize,
+    blocked_ads: AtomicUsize,
+    denied: AtomicUsize,
+    // the page itself, or a redirect of it, was denied by the access policy
+    denied_document: Mutex<Option<String>>,
+}
+
+impl InterceptStats {
+    #[cfg(feature = "content")]
+    /// Requests blocked by `block_resources`, `block_urls` or `block_ads`
+    pub fn blocked(&self) -> usize {
+        self.blocked.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "content")]
+    pub fn blocked_ads(&self) -> usize {
+        self.blocked_ads.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "content")]
+    /// Requests denied by the access policy
+    pub fn denied(&self) -> usize {
+        self.denied.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "content")]
+    pub fn denied_document(&self) -> Option<String> {
+        self.denied_document.lock().unwrap().clone()
+    }
+}
+
+/// Owns the Fetch domain of a page: every request is paused, checked
+/// against the rules and continued, auth challenges are answered.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interceptor {
+    credentials: Option<(String, String)>,
+    proxy_credentials: Option<(String, String)>,
+    block_types: Vec<ResourceType>,
+    block_urls: Vec<UrlPattern>,
+    // the filters and the host of the rendered page
+    adblock: Option<(Arc<FilterList>, String)>,
+    mocks: Vec<Mock>,
+    rewrites: Vec<Rewrite>,
+    access: Option<(Arc<AccessPolicy>, Arc<HostCache>)>,
+    // checked on every request, the egress proxy checks the host and port of what isn't paused here
+    url_policy: Option<Arc<UrlPolicy>>,
+    stats: Arc<InterceptStats>,
+}
+
+/// Stops the interception loop when the render is done
+pub(crate) struct InterceptGuard(JoinHandle<()>);
+
+impl Drop for InterceptGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl Interceptor {
+    #[cfg(feature = "content")]
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    pub fn with_proxy_credentials(mut self, username: &str, password: &str) -> Self {
+        self.proxy_credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    #[cfg(feature = "content")]
+    pub fn block_types(mut self, types: Vec<ResourceType>) -> Self {
+        self.block_types = types;
+        self
+    }
+
+    #[cfg(feature = "content")]
+    pub fn block_urls(mut self, patterns: Vec<UrlPattern>) -> Self {
+        self.block_urls = patterns;
+        self
+    }
+
+    #[cfg(feature = "content")]
+    pub fn with_adblock(mut self, filters: Arc<FilterList>, source_host: &str) -> Self {
+        self.adblock = Some((filters, source_host.to_string()));
+        self
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        !self.block_types.is_empty() || !self.block_urls.is_empty() || self.adblock.is_some()
+    }
+
+    #[cfg(feature = "content")]
+    pub fn mocks(mut self, mocks: Vec<Mock>) -> Self {
+        self.mocks = mocks;
+        self
+    }
+
+    #[cfg(feature = "content")]
+    pub fn rewrites(mut self, rewrites: Vec<Rewrite>) -> Self {
+        self.rewrites = rewrites;
+        self
+    }
+
+    /// Check every request, redirects included, against the access policy
+    pub fn with_access(mut self, policy: Arc<AccessPolicy>) -> Self {
+        if !policy.is_open() {
+            self.access = Some((policy, Arc::default()));
+        }
+        self
+    }
+
+    pub fn with_url_policy(mut self, policy: Arc<UrlPolicy>) -> Self {
+        self.url_policy = Some(policy);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.credentials.is_none()
+            && self.proxy_credentials.is_none()
+            && !self.is_blocking()
+            && self.mocks.is_empty()
+            && self.rewrites.is_empty()
+            && self.access.is_none()
+            && self.url_policy.is_none()
+    }
+
+    #[cfg(feature = "content")]
+    pub fn stats(&self) -> Arc<InterceptStats> {
+        self.stats.clone()
+    }
+
+    fn should_block(&self, event: &EventRequestPaused, main_frame: Option<&FrameId>) -> bool {
+        // never block the page itself
+        if event.resource_type == ResourceType::Document && main_frame == Some(&event.frame_id) {
+            return false;
+        }
+        let url = event.request.url.as_str();
+        if self.block_types.contains(&event.resource_type)
+            || self.block_urls.iter().any(|p| p.is_match(url))
+        {
+            self.stats.blocked.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        match &self.adblock {
+            Some((filters, source_host))
+                if filters.check(&adblock::Request {
+                    url,
+                    resource_type: &event.resource_type,
+                    source_host,
+                }) =>
+            {
+                self.stats.blocked.fetch_add(1, Ordering::Relaxed);
+                self.stats.blocked_ads.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Enable Fetch on the page and serve its events in the background
+    pub async fn start(self, page: Page) -> Result<InterceptGuard, Error> {
+        let mut paused = page.event_listener::<EventRequestPaused>().await?;
+        let mut auth_required = page.event_listener::<EventAuthRequired>().await?;
+        let main_frame = page.mainframe().await?;
+
+        page.execute(
+            EnableParams::builder()
+                .handle_auth_requests(
+                    self.credentials.is_some() || self.proxy_credentials.is_some(),
+                )
+                .pattern(RequestPattern::builder().url_pattern("*").build())
+                .build(),
+        )
+        .await?;
+
+        let handle = tokio::spawn(async move {
+            let mut attempted = HashSet::new();
+            loop {
+                select! {
+                    event = paused.next() => match event {
+                        Some(event) => self.on_request_paused(&page, &event, main_frame.as_ref()).await,
+                        None => break,
+                    },
+                    event = auth_required.next() => match event {
+                        Some(event) => self.on_auth_required(&page, &event, &mut attempted).await,
+                        None => break,
+                    },
+                }
+            }
+        });
+        Ok(InterceptGuard(handle))
+    }
+
+    async fn on_request_paused(
+        &self,
+        page: &Page,
+        event: &EventRequestPaused,
+        main_frame: Option<&FrameId>,
+    ) {
+        let url = event.request.url.as_str();
+        let r = if self.should_block(event, main_frame) {
+            page.execute(FailRequestParams::new(
+                event.request_id.clone(),
+                ErrorReason::BlockedByClient,
+            ))
+            .await
+            .map(|_| ())
+        } else if let Some(mock) = self.mocks.iter().find(|m| m.pattern.is_match(url)) {
+            let mut params = FulfillRequestParams::new(event.request_id.clone(), mock.status);
+            params.response_headers = Some(mock.headers.clone());
+            params.body = Some(mock.body.clone().into());
+            page.execute(params).await.map(|_| ())
+        } else {
+            let rewritten = self.rewrites.iter().find_map(|r| r.apply(url));
+            let target = rewritten.as_deref().unwrap_or(url);
+            match self.check_access(target).await {
+                Ok(()) => {
+                    let mut params = ContinueRequestParams::new(event.request_id.clone());
+                    params.url = rewritten;
+                    page.execute(params).await.map(|_| ())
+                }
+                Err(e) => {
+                    log::warn!("access denied {}: {}", url, e);
+                    self.stats.denied.fetch_add(1, Ordering::Relaxed);
+                    if event.resource_type == ResourceType::Document
+                        && main_frame == Some(&event.frame_id)
+                    {
+                        self.stats.denied_document.lock().unwrap().replace(e);
+                    }
+                    page.execute(FailRequestParams::new(
+                        event.request_id.clone(),
+                        ErrorReason::AccessDenied,
+                    ))
+                    .await
+                    .map(|_| ())
+                }
+            }
+        };
+        if let Err(e) = r {
+            log::debug!("intercept request {} error: {}", event.request.url, e);
+        }
+    }
+
+    async fn check_access(&self, url: &str) -> Result<(), String> {
+        let u = match url::Url::parse(url) {
+            Ok(u) => u,
+            Err(_) => return Ok(()),
+        };
+        // data: and blob: urls load nothing from the network
+        if let Some(policy) = self.url_policy.as_ref().filter(|_| u.host().is_some()) {
+            policy.check(&u)?;
+        }
+        match &self.access {
+            Some((policy, cache)) => cache.check(policy, &u).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn on_auth_required(
+        &self,
+        page: &Page,
+        event: &EventAuthRequired,
+        attempted: &mut HashSet<(String, bool)>,
+    ) {
+        let is_proxy = event.auth_challenge.source == Some(AuthChallengeSource::Proxy);
+        let credentials = match is_proxy {
+            true => &self.proxy_credentials,
+            false => &self.credentials,
+        };
+        // answer each challenge once, a second one means the credentials are wrong
+        let response = match credentials {
+            Some((username, password))
+                if attempted.insert((event.request_id.inner().clone(), is_proxy)) =>
+            {
+                let mut response =
+                    AuthChallengeResponse::new(AuthChallengeResponseResponse::ProvideCredentials);
+                response.username = Some(username.clone());
+                response.password = Some(password.clone());
+                response
+            }
+            Some(_) => AuthChallengeResponse::new(AuthChallengeResponseResponse::CancelAuth),
+            None => AuthChallengeResponse::new(AuthChallengeResponseResponse::Default),
+        };
+        if let Err(e) = page
+            .execute(ContinueWithAuthParams::new(
+                event.request_id.clone(),
+                response,
+            ))
+            .await
+        {
+            log::debug!("continue with auth {} error: {}", event.request.url, e);
+        }
+    }
+}
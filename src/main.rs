@@ -1,5 +1,5 @@
 use axum::{
-    http::{Request, Response},
+    http::{Request, Response, StatusCode},
     routing::{get, post},
     Router,
 };
@@ -16,18 +16,44 @@ use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
 use tracing::Span;
 use tracing_appender;
+mod access;
+#[cfg(feature = "content")]
+mod actions;
+mod adblock;
 #[cfg(feature = "content")]
 mod content;
 mod devices;
+#[cfg(feature = "content")]
+mod download;
+#[cfg(feature = "headless")]
+mod egress;
 mod error;
 #[cfg(feature = "headless")]
 mod headless;
+#[cfg(feature = "headless")]
+mod intercept;
+#[cfg(feature = "content")]
+mod overlays;
+#[cfg(feature = "content")]
+mod pdf;
+mod policy;
+#[cfg(feature = "headless")]
+mod pool;
+mod proxy;
+mod queue;
 #[cfg(feature = "remote")]
 mod remote;
 mod session;
+#[cfg(feature = "headless")]
+mod throttle;
+#[cfg(feature = "content")]
+mod upstream;
+mod validate;
+#[cfg(feature = "content")]
+mod wait;
 #[cfg(test)]
 mod tests;
-pub use error::Error;
+pub use error::{Error, ErrorCode};
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -38,6 +64,20 @@ struct Cli {
     #[clap(long, short, default_value = "0")]
     max_sessions: usize,
 
+    #[clap(
+        long,
+        default_value = "0",
+        help = "requests waiting for a session once max_sessions is reached, 0 disables the queue"
+    )]
+    queue_size: usize,
+
+    #[clap(
+        long,
+        default_value = "30000",
+        help = "max time a request waits in the queue in milliseconds"
+    )]
+    queue_timeout: u64,
+
     #[clap(long, default_value = "/tmp/browserlify")]
     data_root: String,
 
@@ -53,13 +93,39 @@ struct Cli {
     #[clap(long, default_value = "false", help = "enable private ip access")]
     enable_private_ip: bool,
 
+    #[clap(long, help = "cidr the browser may reach even if private, can be repeated")]
+    allow_cidr: Vec<String>,
+
+    #[clap(long, help = "cidr the browser may never reach, can be repeated")]
+    deny_cidr: Vec<String>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        help = "enforce the access policy on headless websocket sessions"
+    )]
+    session_access_policy: bool,
+
     #[clap(
         long,
         default_value = "60000",
-        help = "max session timeout in milliseconds"
+        help = "max session timeout in milliseconds, also the cap of a render timeout"
     )]
     max_timeout: u64,
 
+    #[clap(
+        long,
+        help = "max session lifetime in milliseconds, --max-timeout when not given, 0 is unlimited"
+    )]
+    max_lifetime: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "0",
+        help = "close a session without websocket traffic for milliseconds, 0 never does"
+    )]
+    idle_timeout: u64,
+
     #[clap(long, default_value = "false", help = "disable CORS access control")]
     disable_cors: bool,
 
@@ -68,6 +134,49 @@ struct Cli {
 
     #[clap(long, help = "disable background cleanup", default_value = "false")]
     disable_background_cleanup: bool,
+
+    #[clap(long, help = "EasyList format filter list used by block_ads, can be repeated")]
+    adblock_list: Vec<String>,
+
+    #[clap(
+        long,
+        help = "proxy pool file, one proxy per line, used by renders and sessions without a proxy"
+    )]
+    proxy_pool: Option<String>,
+
+    #[clap(
+        long,
+        help = "json file of the allowed hosts, schemes and ports, globally and per api_key"
+    )]
+    url_policy: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "0",
+        help = "browsers the pool keeps launched with a free context, the others are launched on demand up to --pool-max"
+    )]
+    pool_min: usize,
+
+    #[clap(
+        long,
+        default_value = "0",
+        help = "browsers launched by the pool, idle or in use, 0 disables the pool"
+    )]
+    pool_max: usize,
+
+    #[clap(
+        long,
+        default_value = "100",
+        help = "browser contexts created in a pooled browser before it is recycled, 0 is unlimited"
+    )]
+    pool_max_uses: usize,
+
+    #[clap(
+        long,
+        default_value = "4",
+        help = "renders a pooled browser runs at once, each one in its own browser context"
+    )]
+    pool_contexts: usize,
 }
 
 fn open_log_output(log_file_name: Option<String>) -> Box<dyn std::io::Write + Send> {
@@ -126,35 +235,119 @@ fn init_log(level: String, is_test: bool, log_file_name: Option<String>) -> trac
 pub struct AppState {
     sessions: Arc<Mutex<Vec<Session>>>,
     max_sessions: usize,
+    queue: Arc<queue::SessionQueue>,
     data_root: String,
-    enable_private_ip: bool,
+    access: Arc<access::AccessPolicy>,
+    session_access_policy: bool,
+    #[cfg(feature = "content")]
     max_timeout: u64,
+    expiry: session::ExpiryPolicy,
+    #[cfg(feature = "content")]
     author: Option<String>,
+    adblock: Option<Arc<adblock::FilterList>>,
+    proxy_pool: Option<Arc<proxy::ProxyPool>>,
+    url_policy: Option<Arc<policy::PolicyConfig>>,
+    #[cfg(feature = "headless")]
+    pool: Option<Arc<pool::BrowserPool>>,
 }
 
 impl AppState {
     pub fn new(data_root: String, max_sessions: usize) -> Self {
+        let sessions = Arc::new(Mutex::new(Vec::new()));
         AppState {
-            sessions: Arc::new(Mutex::new(Vec::new())),
+            sessions: sessions.clone(),
             max_sessions,
+            queue: Arc::new(queue::SessionQueue::new(
+                sessions,
+                max_sessions,
+                queue::QueueOptions::default(),
+            )),
             data_root,
-            enable_private_ip: false,
+            access: Arc::new(access::AccessPolicy::default()),
+            session_access_policy: false,
+            #[cfg(feature = "content")]
             max_timeout: 60 * 1000, // 60 seconds
+            expiry: session::ExpiryPolicy::default(),
+            #[cfg(feature = "content")]
             author: None,
+            adblock: None,
+            proxy_pool: None,
+            url_policy: None,
+            #[cfg(feature = "headless")]
+            pool: None,
         }
     }
 
     pub fn allow_private_ip(&self) -> Self {
         let mut state = self.clone();
-        state.enable_private_ip = true;
+        state.access = Arc::new(access::AccessPolicy {
+            allow_private: true,
+            ..(*self.access).clone()
+        });
         state
     }
 
+    pub fn with_adblock(&self, filters: adblock::FilterList) -> Self {
+        let mut state = self.clone();
+        state.adblock = Some(Arc::new(filters));
+        state
+    }
+
+    pub fn with_proxy_pool(&self, pool: proxy::ProxyPool) -> Self {
+        let mut state = self.clone();
+        state.proxy_pool = Some(Arc::new(pool));
+        state
+    }
+
+    pub fn with_url_policy(&self, config: policy::PolicyConfig) -> Self {
+        let mut state = self.clone();
+        state.url_policy = Some(Arc::new(config));
+        state
+    }
+
+    pub fn with_expiry(&self, expiry: session::ExpiryPolicy) -> Self {
+        let mut state = self.clone();
+        state.expiry = expiry;
+        state
+    }
+
+    pub fn with_queue(&self, options: queue::QueueOptions) -> Self {
+        let mut state = self.clone();
+        state.queue = Arc::new(queue::SessionQueue::new(
+            self.sessions.clone(),
+            self.max_sessions,
+            options,
+        ));
+        state
+    }
+
+    #[cfg(feature = "headless")]
+    pub fn with_pool(&self, pool: Arc<pool::BrowserPool>) -> Self {
+        let mut state = self.clone();
+        state.pool = Some(pool);
+        state
+    }
+
+    /// The url policy of the api_key, None if the server has no --url-policy
+    pub fn url_policy(
+        &self,
+        api_key: Option<&str>,
+    ) -> Result<Option<Arc<policy::UrlPolicy>>, Error> {
+        match &self.url_policy {
+            Some(config) => config
+                .policy(api_key)
+                .map(Some)
+                .map_err(|e| Error::new(StatusCode::FORBIDDEN, &e)),
+            None => Ok(None),
+        }
+    }
+
     pub fn is_full(&self) -> bool {
         if self.max_sessions <= 0 {
             return false;
         }
-        self.sessions.lock().unwrap().len() >= self.max_sessions
+        let pending = self.queue.pending();
+        self.sessions.lock().unwrap().len() + pending >= self.max_sessions
     }
 }
 type StateRef = Arc<AppState>;
@@ -162,6 +355,7 @@ type StateRef = Arc<AppState>;
 fn create_router(state: StateRef) -> Router {
     let router = Router::new()
         .route("/list", get(session::list_session))
+        .route("/stats", get(session::session_stats))
         .route("/kill/:session_id", post(session::kill_session))
         .route("/screen/:session_id", get(session::screen_session))
         .route("/kill_all", post(session::killall_session))
@@ -189,6 +383,10 @@ fn create_router(state: StateRef) -> Router {
         .route(
             "/html",
             get(content::dump_html_get).post(content::dump_html_post),
+        )
+        .route(
+            "/download",
+            get(content::download_get).post(content::download_post),
         );
 
     #[cfg(feature = "remote")]
@@ -205,25 +403,121 @@ fn create_router(state: StateRef) -> Router {
                 .route("/create", post(remote::create_remote)),
         )
         .with_state(state.clone());
-    router.with_state(state)
+    router
+        .with_state(state)
+        .layer(axum::middleware::from_fn(error::with_request_id))
+}
+
+// sessions still expire with the default flags, after --max-timeout
+fn expiry_policy(args: &Cli) -> session::ExpiryPolicy {
+    let millis = |ms: u64| (ms > 0).then(|| Duration::from_millis(ms));
+    session::ExpiryPolicy {
+        max_lifetime: millis(args.max_lifetime.unwrap_or(args.max_timeout)),
+        idle_timeout: millis(args.idle_timeout),
+    }
 }
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     let args = Cli::parse();
+    let expiry = expiry_policy(&args);
     let addr = args.addr;
     let prefix = args.prefix;
 
     let _log_guard = init_log(args.log_level, false, args.log_file);
 
+    let adblock = match args.adblock_list.is_empty() {
+        true => None,
+        false => {
+            let mut filters = adblock::FilterList::default();
+            for path in &args.adblock_list {
+                filters.extend(adblock::FilterList::load(path)?);
+            }
+            log::info!("adblock loaded {} filters", filters.len());
+            Some(Arc::new(filters))
+        }
+    };
+
+    let proxy_pool = match &args.proxy_pool {
+        Some(path) => {
+            let pool = proxy::ProxyPool::load(path)?;
+            if pool.is_empty() {
+                log::warn!("proxy pool {} is empty", path);
+            }
+            log::info!("proxy pool loaded {} proxies", pool.len());
+            Some(Arc::new(pool))
+        }
+        None => None,
+    };
+
+    let parse_cidrs = |cidrs: &[String]| {
+        cidrs
+            .iter()
+            .map(|c| access::Cidr::try_from(c.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    };
+    let access = access::AccessPolicy {
+        allow_private: args.enable_private_ip,
+        allow: parse_cidrs(&args.allow_cidr)?,
+        deny: parse_cidrs(&args.deny_cidr)?,
+    };
+
+    let url_policy = match &args.url_policy {
+        Some(path) => Some(Arc::new(policy::PolicyConfig::load(path)?)),
+        None => None,
+    };
+
+    let sessions = Arc::new(Mutex::new(Vec::new()));
+    let queue = queue::SessionQueue::new(
+        sessions.clone(),
+        args.max_sessions,
+        queue::QueueOptions {
+            max_len: args.queue_size,
+            wait_timeout: Duration::from_millis(args.queue_timeout),
+        },
+    );
+    if args.max_sessions > 0 {
+        log::info!("session queue {:?}", queue.options());
+    }
     let state = Arc::new(AppState {
         data_root: args.data_root,
         max_sessions: args.max_sessions,
-        sessions: Arc::new(Mutex::new(Vec::new())),
-        enable_private_ip: args.enable_private_ip,
+        sessions,
+        queue: Arc::new(queue),
+        access: Arc::new(access),
+        session_access_policy: args.session_access_policy,
+        #[cfg(feature = "content")]
         max_timeout: args.max_timeout,
+        expiry,
+        #[cfg(feature = "content")]
         author: args.author,
+        adblock,
+        proxy_pool,
+        url_policy,
+        #[cfg(feature = "headless")]
+        pool: None,
     });
 
+    #[cfg(feature = "headless")]
+    let state = match args.pool_max {
+        0 => state,
+        _ => {
+            let pool = Arc::new(pool::BrowserPool::new(
+                pool::PoolOptions {
+                    min: args.pool_min,
+                    max: args.pool_max,
+                    max_uses: args.pool_max_uses,
+                    contexts: args.pool_contexts,
+                },
+                &state.data_root,
+            ));
+            log::info!("browser pool {:?}", pool.options());
+            tokio::spawn(pool.clone().maintain());
+            Arc::new(state.with_pool(pool))
+        }
+    };
+
     let mut router = create_router(state.clone());
 
     if !args.disable_cors {
@@ -0,0 +1,84 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+/// How a consent management platform shows its banner and how to close it
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct BannerRule {
+    pub name: String,
+    // present while the banner is shown
+    pub detect: String,
+    pub accept: Option<String>,
+    pub reject: Option<String>,
+    // removed with the detected element once clicked, the banner may fade out slowly or live in an iframe
+    #[serde(default)]
+    pub hide: Vec<String>,
+}
+
+lazy_static! {
+    pub(crate) static ref COOKIE_BANNERS: Vec<BannerRule> =
+        serde_json::from_str(include_str!("cookie_banners.json"))
+            .expect("invalid cookie_banners.json");
+}
+
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+/// Click the reject (or accept) button of the known banners and remove them,
+/// evaluates to the names of the dismissed banners
+pub(crate) fn dismiss_banners_js(accept: bool) -> String {
+    format!(
+        "(() => {{
+            const rules = {rules};
+            const accept = {accept};
+            const dismissed = [];
+            for (const rule of rules) {{
+                if (!document.querySelector(rule.detect)) continue;
+                const buttons = accept ? [rule.accept, rule.reject] : [rule.reject, rule.accept];
+                const button = buttons.filter(Boolean).map((s) => document.querySelector(s)).find(Boolean);
+                if (button) button.click();
+                [rule.detect, ...rule.hide].forEach((s) => document.querySelectorAll(s).forEach((el) => el.remove()));
+                dismissed.push(rule.name);
+            }}
+            return dismissed;
+        }})()",
+        rules = serde_json::to_string(&*COOKIE_BANNERS).unwrap_or("[]".to_string()),
+        accept = accept,
+    )
+}
+
+/// Remove the elements matching a selector list, evaluates to the count
+pub(crate) fn remove_selectors_js(selectors: &str) -> String {
+    format!(
+        "(() => {{
+            const elements = document.querySelectorAll({});
+            elements.forEach((el) => el.remove());
+            return elements.length;
+        }})()",
+        quote(selectors)
+    )
+}
+
+/// Give the scrolling back once a modal overlay is gone
+pub(crate) const UNLOCK_SCROLL_JS: &str = "(() => {
+    for (const el of [document.documentElement, document.body]) {
+        if (el && getComputedStyle(el).overflow === 'hidden') el.style.setProperty('overflow', 'visible', 'important');
+    }
+})()";
+
+/// Fixed and sticky elements repeat on every viewport of a full page screenshot,
+/// pin them to their place in the document instead
+pub(crate) const UNFIX_POSITION_JS: &str = "(() => {
+    let count = 0;
+    for (const el of document.querySelectorAll('body *')) {
+        const position = getComputedStyle(el).position;
+        if (position === 'fixed') {
+            el.style.setProperty('position', 'absolute', 'important');
+            count++;
+        } else if (position === 'sticky') {
+            el.style.setProperty('position', 'relative', 'important');
+            count++;
+        }
+    }
+    return count;
+})()";
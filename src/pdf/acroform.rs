@@ -0,0 +1,430 @@
+use lopdf::{dictionary, text_string, Dictionary, Document, Object, ObjectId, Stream};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+// css pixels per inch and pdf points per inch
+const CSS_PX_PER_INCH: f64 = 96.0;
+const PT_PER_INCH: f64 = 72.0;
+
+// field flags, PDF 32000-1 12.7.3.1 and 12.7.4
+const FF_READ_ONLY: i64 = 1;
+const FF_REQUIRED: i64 = 1 << 1;
+const FF_MULTILINE: i64 = 1 << 12;
+const FF_PASSWORD: i64 = 1 << 13;
+const FF_NO_TOGGLE_TO_OFF: i64 = 1 << 14;
+const FF_RADIO: i64 = 1 << 15;
+const FF_COMBO: i64 = 1 << 17;
+const FF_MULTI_SELECT: i64 = 1 << 21;
+// annotation flag: print
+const F_PRINT: i64 = 4;
+
+/// Measure every form control where the page lays out, hide the static value
+/// and return the control descriptions. The dom is left as it is, so the
+/// printed layout and the page selectors don't change.
+const COLLECT_FIELDS_JS: &str = r#"(() => {
+    const fields = [];
+    const skip = ['hidden', 'submit', 'button', 'image', 'reset', 'file'];
+    document.querySelectorAll('input, textarea, select').forEach((el) => {
+        const tag = el.tagName.toLowerCase();
+        const type = tag === 'input' ? (el.type || 'text').toLowerCase() : tag;
+        if (skip.includes(type)) {
+            return;
+        }
+        const r = el.getBoundingClientRect();
+        // not rendered, nothing is printed
+        if (r.width === 0 || r.height === 0) {
+            return;
+        }
+        fields.push({
+            kind: type,
+            name: el.name || el.id || '',
+            // an unset value of a checkbox or radio reads as 'on'
+            value: (type === 'checkbox' || type === 'radio') && !el.hasAttribute('value') ? '' : el.value || '',
+            checked: !!el.checked,
+            required: !!el.required,
+            readonly: !!el.readOnly || !!el.disabled,
+            multiple: !!el.multiple,
+            max_length: el.maxLength > 0 ? el.maxLength : null,
+            options: tag === 'select' ? Array.from(el.options).map((o) => [o.value, o.text]) : [],
+            selected: tag === 'select' ? Array.from(el.selectedOptions).map((o) => o.value) : [],
+            rect: [r.left + window.scrollX, r.top + window.scrollY, r.width, r.height],
+        });
+        if (type === 'checkbox' || type === 'radio') {
+            el.checked = false;
+        } else {
+            el.style.color = 'transparent';
+        }
+    });
+    return fields;
+})()"#;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct FormField {
+    kind: String,
+    name: String,
+    value: String,
+    checked: bool,
+    required: bool,
+    readonly: bool,
+    multiple: bool,
+    max_length: Option<i64>,
+    options: Vec<(String, String)>,
+    selected: Vec<String>,
+    // left, top, width and height in css pixels of the document
+    rect: [f64; 4],
+}
+
+pub(crate) fn collect_fields_js() -> &'static str {
+    COLLECT_FIELDS_JS
+}
+
+/// The paper and the margins in inches, the scale and the page ranges of the
+/// print, what places the measured controls on the printed pages
+#[derive(Debug, Clone)]
+pub(crate) struct PrintLayout {
+    pub width: f64,
+    pub height: f64,
+    pub margin_top: f64,
+    pub margin_bottom: f64,
+    pub margin_left: f64,
+    pub margin_right: f64,
+    pub scale: f64,
+    pub page_ranges: Option<String>,
+}
+
+impl PrintLayout {
+    /// The size of the content of one page in css pixels, the page is
+    /// measured with this viewport
+    pub fn viewport(&self) -> (f64, f64) {
+        let px = CSS_PX_PER_INCH / self.scale;
+        (
+            ((self.width - self.margin_left - self.margin_right) * px).max(1.0),
+            ((self.height - self.margin_top - self.margin_bottom) * px).max(1.0),
+        )
+    }
+
+    /// The printed page, counted from 0, and the pdf rectangle of a control
+    fn place(&self, rect: &[f64; 4]) -> Option<(usize, [f32; 4])> {
+        let (_, page_height) = self.viewport();
+        let [x, y, width, height] = *rect;
+        let mut page = (y / page_height).floor().max(0.0);
+        let mut top = y - page * page_height;
+        // a line isn't split, a control across a break starts the next page
+        if top + height > page_height && height <= page_height {
+            page += 1.0;
+            top = 0.0;
+        }
+        let printed = self.printed_index(page as usize + 1)?;
+
+        let pt = PT_PER_INCH / CSS_PX_PER_INCH * self.scale;
+        let left = self.margin_left * PT_PER_INCH + x * pt;
+        let top = (self.height - self.margin_top) * PT_PER_INCH - top * pt;
+        let rect = [left, top - height * pt, left + width * pt, top];
+        Some((printed, rect.map(|v| v as f32)))
+    }
+
+    fn is_printed(&self, page: usize) -> bool {
+        let ranges = match &self.page_ranges {
+            Some(ranges) if !ranges.trim().is_empty() => ranges,
+            _ => return true,
+        };
+        ranges.split(',').any(|range| {
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start.trim(), end.trim()),
+                None => (range.trim(), range.trim()),
+            };
+            let start = start.parse().unwrap_or(1);
+            let end = end.parse().unwrap_or(usize::MAX);
+            (start..=end).contains(&page)
+        })
+    }
+
+    /// Where the page, counted from 1, is in the pdf, None when not printed
+    fn printed_index(&self, page: usize) -> Option<usize> {
+        match self.is_printed(page) {
+            true => Some((1..page).filter(|p| self.is_printed(*p)).count()),
+            false => None,
+        }
+    }
+}
+
+fn name(s: &str) -> Object {
+    // pdf names can't be empty, keep export values usable
+    let s = if s.is_empty() { "Yes" } else { s };
+    Object::Name(s.as_bytes().to_vec())
+}
+
+fn check_appearance(doc: &mut Document, width: f32, height: f32, zadb: ObjectId) -> ObjectId {
+    let size = (width.min(height) * 0.8).max(1.0);
+    let content = format!(
+        "q 0 g BT /ZaDb {:.2} Tf {:.2} {:.2} Td (4) Tj ET Q",
+        size,
+        (width - size * 0.75) / 2.0,
+        (height - size * 0.7) / 2.0
+    );
+    doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "ZaDb" => zadb } },
+        },
+        content.into_bytes(),
+    ))
+}
+
+fn empty_appearance(doc: &mut Document, width: f32, height: f32) -> ObjectId {
+    doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        },
+        Vec::new(),
+    ))
+}
+
+/// The on state of the widget at `position` in its group, radios without a
+/// distinct value are told apart by their position
+fn export_state(group: &[&FormField], position: usize) -> String {
+    let field = group[position];
+    let distinct = group
+        .iter()
+        .enumerate()
+        .all(|(i, f)| i == position || f.value != field.value);
+    match field.kind == "radio" && (field.value.is_empty() || !distinct) {
+        true => position.to_string(),
+        false => field.value.clone(),
+    }
+}
+
+/// Field level entries, shared by every widget of the same name
+fn field_entries(field: &FormField, group: &[&FormField]) -> Dictionary {
+    let mut dict = Dictionary::new();
+    let mut flags = 0;
+    if field.readonly {
+        flags |= FF_READ_ONLY;
+    }
+    if field.required {
+        flags |= FF_REQUIRED;
+    }
+    match field.kind.as_str() {
+        "checkbox" | "radio" => {
+            dict.set("FT", "Btn");
+            if field.kind == "radio" {
+                flags |= FF_RADIO | FF_NO_TOGGLE_TO_OFF;
+            }
+            let on = group.iter().position(|f| f.checked);
+            let state = on.map(|i| export_state(group, i)).unwrap_or_default();
+            dict.set("V", on.map(|_| name(&state)).unwrap_or(name("Off")));
+        }
+        "select" => {
+            dict.set("FT", "Ch");
+            flags |= match field.multiple {
+                true => FF_MULTI_SELECT,
+                false => FF_COMBO,
+            };
+            let options = field
+                .options
+                .iter()
+                .map(|(value, label)| Object::Array(vec![text_string(value), text_string(label)]))
+                .collect::<Vec<_>>();
+            dict.set("Opt", options);
+            match field.selected.as_slice() {
+                [] => {}
+                [one] => dict.set("V", text_string(one)),
+                many => dict.set("V", many.iter().map(|v| text_string(v)).collect::<Vec<_>>()),
+            }
+        }
+        kind => {
+            dict.set("FT", "Tx");
+            if kind == "textarea" {
+                flags |= FF_MULTILINE;
+            }
+            if kind == "password" {
+                flags |= FF_PASSWORD;
+            }
+            if let Some(max_length) = field.max_length {
+                dict.set("MaxLen", max_length);
+            }
+            dict.set("V", text_string(&field.value));
+        }
+    }
+    if flags != 0 {
+        dict.set("Ff", flags);
+    }
+    dict
+}
+
+fn widget_entries(
+    doc: &mut Document,
+    field: &FormField,
+    export: &str,
+    page_id: ObjectId,
+    rect: &[f32; 4],
+    zadb: ObjectId,
+) -> Dictionary {
+    let mut dict = dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "Rect" => rect.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>(),
+        "P" => page_id,
+        "F" => F_PRINT,
+    };
+    if field.kind == "checkbox" || field.kind == "radio" {
+        let (width, height) = (rect[2] - rect[0], rect[3] - rect[1]);
+        let on = check_appearance(doc, width, height, zadb);
+        let off = empty_appearance(doc, width, height);
+        let state = match field.checked {
+            true => name(export),
+            false => name("Off"),
+        };
+        let mut normal = Dictionary::new();
+        normal.set(name(export).as_name().unwrap(), on);
+        normal.set("Off", off);
+        dict.set("AP", dictionary! { "N" => normal });
+        dict.set("AS", state);
+        dict.set("MK", dictionary! { "CA" => text_string("4") });
+    }
+    dict
+}
+
+fn add_annotation(doc: &mut Document, page_id: ObjectId, annot_id: ObjectId) {
+    let annots = match doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Annots"))
+    {
+        Ok(Object::Reference(id)) => *id,
+        _ => {
+            if let Ok(page) = doc.get_dictionary_mut(page_id) {
+                match page.get_mut(b"Annots").and_then(Object::as_array_mut) {
+                    Ok(annots) => annots.push(Object::Reference(annot_id)),
+                    Err(_) => page.set("Annots", vec![Object::Reference(annot_id)]),
+                }
+            }
+            return;
+        }
+    };
+    if let Ok(annots) = doc.get_object_mut(annots).and_then(Object::as_array_mut) {
+        annots.push(Object::Reference(annot_id));
+    }
+}
+
+/// Add an AcroForm widget over every printed form control, returns the field count
+pub(crate) fn build_acroform(
+    doc: &mut Document,
+    fields: &[FormField],
+    layout: &PrintLayout,
+) -> usize {
+    let pages = doc.get_pages();
+    let placed = fields
+        .iter()
+        .enumerate()
+        .filter_map(|(index, field)| {
+            let (page, rect) = layout.place(&field.rect)?;
+            let page_id = *pages.get(&(page as u32 + 1))?;
+            Some((index, page_id, rect))
+        })
+        .collect::<Vec<_>>();
+    if placed.is_empty() {
+        return 0;
+    }
+
+    let helv = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let zadb = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "ZapfDingbats",
+    });
+
+    // a field has one type, a name shared by different kinds of controls
+    // becomes one field per kind
+    let mut kinds: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (index, _, _) in placed.iter() {
+        let field = &fields[*index];
+        kinds.entry(&field.name).or_default().insert(&field.kind);
+    }
+
+    // group widgets by field name, unnamed controls get their own field
+    let mut groups: BTreeMap<String, Vec<(usize, ObjectId, [f32; 4])>> = BTreeMap::new();
+    for (index, page_id, rect) in placed {
+        let field = &fields[index];
+        let key = match field.name.is_empty() {
+            true => format!("field_{}", index),
+            false if kinds[field.name.as_str()].len() > 1 => {
+                format!("{}_{}", field.name, field.kind)
+            }
+            false => field.name.clone(),
+        };
+        groups.entry(key).or_default().push((index, page_id, rect));
+    }
+
+    let mut root_fields = Vec::new();
+    let mut page_widgets: Vec<(ObjectId, ObjectId)> = Vec::new();
+    for (field_name, placed) in groups.iter() {
+        let group = placed
+            .iter()
+            .map(|(i, _, _)| &fields[*i])
+            .collect::<Vec<_>>();
+        let mut field_dict = field_entries(group[0], &group);
+        field_dict.set("T", text_string(field_name));
+        field_dict.set("DA", text_string("/Helv 0 Tf 0 g"));
+
+        let widgets = placed
+            .iter()
+            .enumerate()
+            .map(|(position, (index, page_id, rect))| {
+                let export = export_state(&group, position);
+                let widget = widget_entries(doc, &fields[*index], &export, *page_id, rect, zadb);
+                (*page_id, widget)
+            })
+            .collect::<Vec<_>>();
+
+        if widgets.len() == 1 {
+            let (page_id, widget) = widgets.into_iter().next().unwrap();
+            // merged by hand, Dictionary::extend keeps only the keys it is given
+            for (key, value) in widget.iter() {
+                field_dict.set(key.clone(), value.clone());
+            }
+            let id = doc.add_object(field_dict);
+            root_fields.push(Object::Reference(id));
+            page_widgets.push((page_id, id));
+        } else {
+            let parent_id = doc.new_object_id();
+            let mut kids = Vec::new();
+            for (page_id, mut widget) in widgets {
+                widget.set("Parent", parent_id);
+                let id = doc.add_object(widget);
+                kids.push(Object::Reference(id));
+                page_widgets.push((page_id, id));
+            }
+            field_dict.set("Kids", kids);
+            doc.objects
+                .insert(parent_id, Object::Dictionary(field_dict));
+            root_fields.push(Object::Reference(parent_id));
+        }
+    }
+
+    for (page_id, widget_id) in page_widgets {
+        add_annotation(doc, page_id, widget_id);
+    }
+
+    let count = root_fields.len();
+    let acroform = dictionary! {
+        "Fields" => root_fields,
+        "NeedAppearances" => true,
+        "DA" => text_string("/Helv 0 Tf 0 g"),
+        "DR" => dictionary! {
+            "Font" => dictionary! { "Helv" => helv, "ZaDb" => zadb },
+        },
+    };
+    if let Ok(catalog) = doc.catalog_mut() {
+        catalog.set("AcroForm", acroform);
+    }
+    count
+}
@@ -0,0 +1,157 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lopdf::{dictionary, text_string, Dictionary, Document, Object, Stream};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// A file uploaded with the request, `content` is base64 encoded
+#[derive(Debug, Deserialize)]
+pub struct Attachment {
+    name: String,
+    content: String,
+    mime_type: Option<String>,
+    description: Option<String>,
+    // Source, Data, Alternative, Supplement or Unspecified, used by Factur-X/ZUGFeRD
+    relationship: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct EmbeddedFile {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub mime_type: String,
+    pub description: Option<String>,
+    pub relationship: String,
+}
+
+const RELATIONSHIPS: [&str; 5] = ["Source", "Data", "Alternative", "Supplement", "Unspecified"];
+
+impl EmbeddedFile {
+    pub fn new(name: &str, data: Vec<u8>, mime_type: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            data,
+            mime_type: mime_type.to_string(),
+            description: None,
+            relationship: "Source".to_string(),
+        }
+    }
+}
+
+impl TryFrom<&Attachment> for EmbeddedFile {
+    type Error = String;
+
+    fn try_from(attachment: &Attachment) -> Result<Self, Self::Error> {
+        if attachment.name.is_empty() {
+            return Err("attachment name is empty".to_string());
+        }
+        let data = STANDARD
+            .decode(attachment.content.as_bytes())
+            .map_err(|e| format!("attachment {} is not base64: {}", attachment.name, e))?;
+        let relationship = attachment
+            .relationship
+            .clone()
+            .unwrap_or("Unspecified".to_string());
+        if !RELATIONSHIPS.contains(&relationship.as_str()) {
+            return Err(format!(
+                "attachment {} has unknown relationship {}",
+                attachment.name, relationship
+            ));
+        }
+        Ok(Self {
+            name: attachment.name.clone(),
+            data,
+            mime_type: attachment
+                .mime_type
+                .clone()
+                .unwrap_or("application/octet-stream".to_string()),
+            description: attachment.description.clone(),
+            relationship,
+        })
+    }
+}
+
+/// `name`, or `stem-2.ext`, `stem-3.ext`... when it is taken
+fn unique_name(name: &str, taken: &HashSet<String>) -> String {
+    if !taken.contains(name) {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    (2..)
+        .map(|i| format!("{}-{}{}", stem, i, ext))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or_default()
+}
+
+/// Add the files to the catalog's EmbeddedFiles name tree and /AF array,
+/// a name taken by an earlier file gets a suffix
+pub(crate) fn embed_files(doc: &mut Document, files: &[EmbeddedFile]) -> usize {
+    let now = chrono::Utc::now().format("D:%Y%m%d%H%M%SZ").to_string();
+    let mut names = Vec::new();
+    let mut specs = Vec::new();
+
+    let mut taken = HashSet::new();
+    let mut files = files
+        .iter()
+        .map(|file| {
+            let name = unique_name(&file.name, &taken);
+            taken.insert(name.clone());
+            (name, file)
+        })
+        .collect::<Vec<_>>();
+    // name tree keys must be unique and sorted by their encoded bytes
+    files.sort_by_cached_key(|(name, _)| match text_string(name) {
+        Object::String(bytes, _) => bytes,
+        _ => Vec::new(),
+    });
+
+    for (name, file) in files {
+        let mut stream = Stream::new(
+            dictionary! {
+                "Type" => "EmbeddedFile",
+                "Subtype" => Object::Name(file.mime_type.as_bytes().to_vec()),
+                "Params" => dictionary! {
+                    "Size" => file.data.len() as i64,
+                    "ModDate" => Object::string_literal(now.clone()),
+                },
+            },
+            file.data.clone(),
+        );
+        stream.compress().ok();
+        let stream_id = doc.add_object(stream);
+
+        let mut spec = dictionary! {
+            "Type" => "Filespec",
+            "F" => text_string(&name),
+            "UF" => text_string(&name),
+            "EF" => dictionary! { "F" => stream_id, "UF" => stream_id },
+            "AFRelationship" => Object::Name(file.relationship.as_bytes().to_vec()),
+        };
+        if let Some(description) = &file.description {
+            spec.set("Desc", text_string(description));
+        }
+        let spec_id = doc.add_object(spec);
+        names.push(text_string(&name));
+        names.push(Object::Reference(spec_id));
+        specs.push(Object::Reference(spec_id));
+    }
+
+    let count = specs.len();
+    if count == 0 {
+        return 0;
+    }
+
+    let mut catalog_names = match doc.catalog().and_then(|c| c.get_deref(b"Names", doc)) {
+        Ok(Object::Dictionary(dict)) => dict.clone(),
+        _ => Dictionary::new(),
+    };
+    catalog_names.set("EmbeddedFiles", dictionary! { "Names" => names });
+
+    if let Ok(catalog) = doc.catalog_mut() {
+        catalog.set("Names", catalog_names);
+        catalog.set("AF", specs);
+    }
+    count
+}
@@ -0,0 +1,522 @@
+use super::writer::{is_structural, write_header, write_indirect_object, write_object};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+
+/// Objects reachable from a page, the page object first. Parent links and
+/// other pages (annotation /P, link destinations) are not followed.
+fn page_objects(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let mut seen = HashSet::from([page_id]);
+    let mut order = vec![page_id];
+    let mut queue = VecDeque::from([page_id]);
+
+    while let Some(id) = queue.pop_front() {
+        let mut refs = Vec::new();
+        if let Ok(object) = doc.get_object(id) {
+            collect_refs(object, &mut refs);
+        }
+        for r in refs {
+            if seen.contains(&r) {
+                continue;
+            }
+            let skip = match doc.get_object(r) {
+                Ok(object) => {
+                    is_structural(object)
+                        || matches!(object.type_name(), Ok("Page" | "Pages" | "Catalog"))
+                }
+                Err(_) => true,
+            };
+            seen.insert(r);
+            if !skip {
+                order.push(r);
+                queue.push_back(r);
+            }
+        }
+    }
+    order
+}
+
+fn collect_refs(object: &Object, refs: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => refs.push(*id),
+        Object::Array(array) => array.iter().for_each(|o| collect_refs(o, refs)),
+        Object::Dictionary(dict) => dict
+            .iter()
+            .filter(|(key, _)| key.as_slice() != b"Parent")
+            .for_each(|(_, o)| collect_refs(o, refs)),
+        Object::Stream(stream) => stream.dict.iter().for_each(|(_, o)| collect_refs(o, refs)),
+        _ => {}
+    }
+}
+
+fn renumber(object: &mut Object, map: &HashMap<ObjectId, u32>) {
+    match object {
+        Object::Reference(id) => match map.get(id) {
+            Some(new_id) => *id = (*new_id, 0),
+            None => *object = Object::Null,
+        },
+        Object::Array(array) => array.iter_mut().for_each(|o| renumber(o, map)),
+        Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, o)| renumber(o, map)),
+        Object::Stream(stream) => stream.dict.iter_mut().for_each(|(_, o)| renumber(o, map)),
+        _ => {}
+    }
+}
+
+fn bits_needed(value: u64) -> u32 {
+    64 - value.leading_zeros()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    current: u8,
+    used: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            self.current = (self.current << 1) | ((value >> i) & 1) as u8;
+            self.used += 1;
+            if self.used == 8 {
+                self.data.push(self.current);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    // every hint table item starts on a byte boundary
+    fn align(&mut self) {
+        if self.used > 0 {
+            self.data.push(self.current << (8 - self.used));
+            self.current = 0;
+            self.used = 0;
+        }
+    }
+}
+
+struct PageHint {
+    objects: u64,
+    length: u64,
+    content_offset: u64,
+    content_length: u64,
+    shared_refs: Vec<u64>,
+}
+
+struct SharedHint {
+    first_object: u64,
+    offset: u64,
+    first_page_entries: u64,
+    lengths: Vec<u64>,
+}
+
+/// Page offset hint table (F.3) followed by the shared object hint table
+/// (F.4), returns the stream content and the offset of the shared table.
+fn hint_tables(
+    first_page_offset: u64,
+    pages: &[PageHint],
+    shared: &SharedHint,
+) -> (Vec<u8>, usize) {
+    let min_max = |values: Vec<u64>| {
+        let least = values.iter().copied().min().unwrap_or_default();
+        let greatest = values.iter().copied().max().unwrap_or_default();
+        (least, bits_needed(greatest - least))
+    };
+    let (least_objects, objects_bits) = min_max(pages.iter().map(|p| p.objects).collect());
+    let (least_length, length_bits) = min_max(pages.iter().map(|p| p.length).collect());
+    let (least_content_offset, content_offset_bits) =
+        min_max(pages.iter().map(|p| p.content_offset).collect());
+    let (least_content_length, content_length_bits) =
+        min_max(pages.iter().map(|p| p.content_length).collect());
+    let shared_count_bits = bits_needed(
+        pages
+            .iter()
+            .map(|p| p.shared_refs.len() as u64)
+            .max()
+            .unwrap_or_default(),
+    );
+    let shared_id_bits = bits_needed(
+        pages
+            .iter()
+            .flat_map(|p| p.shared_refs.iter().copied())
+            .max()
+            .unwrap_or_default(),
+    );
+
+    let mut w = BitWriter::default();
+    w.write(least_objects, 32);
+    w.write(first_page_offset, 32);
+    w.write(objects_bits as u64, 16);
+    w.write(least_length, 32);
+    w.write(length_bits as u64, 16);
+    w.write(least_content_offset, 32);
+    w.write(content_offset_bits as u64, 16);
+    w.write(least_content_length, 32);
+    w.write(content_length_bits as u64, 16);
+    w.write(shared_count_bits as u64, 16);
+    w.write(shared_id_bits as u64, 16);
+    // no fractional positions, numerator bits 0 and denominator 1
+    w.write(0, 16);
+    w.write(1, 16);
+
+    pages
+        .iter()
+        .for_each(|p| w.write(p.objects - least_objects, objects_bits));
+    w.align();
+    pages
+        .iter()
+        .for_each(|p| w.write(p.length - least_length, length_bits));
+    w.align();
+    pages
+        .iter()
+        .for_each(|p| w.write(p.shared_refs.len() as u64, shared_count_bits));
+    w.align();
+    pages.iter().for_each(|p| {
+        p.shared_refs
+            .iter()
+            .for_each(|r| w.write(*r, shared_id_bits))
+    });
+    w.align();
+    // numerators take no bits
+    pages
+        .iter()
+        .for_each(|p| w.write(p.content_offset - least_content_offset, content_offset_bits));
+    w.align();
+    pages
+        .iter()
+        .for_each(|p| w.write(p.content_length - least_content_length, content_length_bits));
+    w.align();
+
+    let shared_offset = w.data.len();
+    let (least_group, group_bits) = min_max(shared.lengths.clone());
+    w.write(shared.first_object, 32);
+    w.write(shared.offset, 32);
+    w.write(shared.first_page_entries, 32);
+    w.write(shared.lengths.len() as u64, 32);
+    // every group holds a single object
+    w.write(0, 16);
+    w.write(least_group, 32);
+    w.write(group_bits as u64, 16);
+    shared
+        .lengths
+        .iter()
+        .for_each(|l| w.write(l - least_group, group_bits));
+    w.align();
+    // no md5 signatures
+    shared.lengths.iter().for_each(|_| w.write(0, 1));
+    w.align();
+    (w.data, shared_offset)
+}
+
+fn xref_table(first: u32, offsets: &[usize], with_free_head: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    let count = offsets.len() + with_free_head as usize;
+    write!(out, "xref\n{} {}\n", first, count).ok();
+    if with_free_head {
+        out.extend_from_slice(b"0000000000 65535 f\r\n");
+    }
+    for offset in offsets {
+        write!(out, "{:010} 00000 n\r\n", offset).ok();
+    }
+    out
+}
+
+/// Serialize the document as a linearized ("fast web view") file, Annex F of
+/// PDF 32000-1: first page objects and hint tables up front, classic xref.
+pub(crate) fn save_linearized(doc: &Document) -> Result<Vec<u8>, String> {
+    let pages = doc.get_pages().into_values().collect::<Vec<_>>();
+    let catalog_id = doc
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|e| e.to_string())?;
+    if pages.is_empty() {
+        return Err("document has no pages".to_string());
+    }
+
+    // 1. split objects into sections
+    let first_page = page_objects(doc, pages[0]);
+    let first_set = first_page.iter().copied().collect::<HashSet<_>>();
+    let later_pages = pages[1..]
+        .iter()
+        .map(|id| page_objects(doc, *id))
+        .collect::<Vec<_>>();
+
+    let mut usage: HashMap<ObjectId, usize> = HashMap::new();
+    later_pages
+        .iter()
+        .flatten()
+        .for_each(|id| *usage.entry(*id).or_default() += 1);
+
+    let mut placed = first_set.clone();
+    placed.insert(catalog_id);
+    let mut private = Vec::new();
+    for objects in later_pages.iter() {
+        let own = objects
+            .iter()
+            .copied()
+            .filter(|id| !first_set.contains(id) && usage.get(id) == Some(&1))
+            .collect::<Vec<_>>();
+        placed.extend(own.iter().copied());
+        private.push(own);
+    }
+    let mut shared = Vec::new();
+    for id in later_pages.iter().flatten() {
+        if placed.insert(*id) {
+            shared.push(*id);
+        }
+    }
+    let others = doc
+        .objects
+        .iter()
+        .filter(|(id, object)| !placed.contains(id) && !is_structural(object))
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+
+    // 2. renumber, the main section takes the low numbers
+    let mut map: HashMap<ObjectId, u32> = HashMap::new();
+    let mut next = 1;
+    for id in private
+        .iter()
+        .flatten()
+        .chain(shared.iter())
+        .chain(others.iter())
+    {
+        map.insert(*id, next);
+        next += 1;
+    }
+    let main_count = next;
+    let lin_num = main_count;
+    let catalog_num = main_count + 1;
+    let hint_num = main_count + 2;
+    map.insert(catalog_id, catalog_num);
+    let mut next = main_count + 3;
+    for id in first_page.iter() {
+        map.insert(*id, next);
+        next += 1;
+    }
+    let total = next;
+    let first_page_count = total - main_count;
+
+    let serialize = |id: &ObjectId| {
+        let mut object = doc.get_object(*id).cloned().unwrap_or(Object::Null);
+        renumber(&mut object, &map);
+        let mut out = Vec::new();
+        write_indirect_object(&mut out, (map[id], 0), &object);
+        out
+    };
+    let mut catalog = Vec::new();
+    {
+        let mut object = doc
+            .get_object(catalog_id)
+            .cloned()
+            .map_err(|e| e.to_string())?;
+        renumber(&mut object, &map);
+        write_indirect_object(&mut catalog, (catalog_num, 0), &object);
+    }
+    let first_bodies = first_page.iter().map(serialize).collect::<Vec<_>>();
+    let private_bodies = private
+        .iter()
+        .map(|objects| objects.iter().map(serialize).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let shared_bodies = shared.iter().map(serialize).collect::<Vec<_>>();
+    let other_bodies = others.iter().map(serialize).collect::<Vec<_>>();
+
+    let mut trailer = Dictionary::new();
+    trailer.set("Root", Object::Reference((catalog_num, 0)));
+    if let Ok(info) = doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        if let Some(num) = map.get(&info) {
+            trailer.set("Info", Object::Reference((*num, 0)));
+        }
+    }
+    let id = match doc.trailer.get(b"ID") {
+        Ok(id) => id.clone(),
+        Err(_) => {
+            let id = uuid::Uuid::new_v4().as_bytes().to_vec();
+            Object::Array(vec![
+                Object::String(id.clone(), StringFormat::Hexadecimal),
+                Object::String(id, StringFormat::Hexadecimal),
+            ])
+        }
+    };
+    trailer.set("ID", id);
+
+    // 3. page hints, offsets relative to each page section
+    let content_of = |page: ObjectId, objects: &[ObjectId], bodies: &[Vec<u8>]| {
+        let contents = doc
+            .get_dictionary(page)
+            .and_then(|p| p.get(b"Contents"))
+            .ok()
+            .and_then(|c| match c {
+                Object::Reference(id) => Some(*id),
+                Object::Array(a) => a.first().and_then(|o| o.as_reference().ok()),
+                _ => None,
+            });
+        let mut offset = 0;
+        for (id, body) in objects.iter().zip(bodies) {
+            if Some(*id) == contents {
+                return (offset as u64, body.len() as u64);
+            }
+            offset += body.len();
+        }
+        (0, 0)
+    };
+    let first_entries = first_page
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i as u64))
+        .collect::<HashMap<_, _>>();
+    let shared_entries = shared
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, (first_page.len() + i) as u64))
+        .collect::<HashMap<_, _>>();
+
+    let mut page_hints = Vec::new();
+    let (content_offset, content_length) = content_of(pages[0], &first_page, &first_bodies);
+    page_hints.push(PageHint {
+        objects: first_page.len() as u64,
+        length: first_bodies.iter().map(|b| b.len() as u64).sum(),
+        content_offset,
+        content_length,
+        shared_refs: Vec::new(),
+    });
+    for (i, objects) in later_pages.iter().enumerate() {
+        let (content_offset, content_length) =
+            content_of(pages[i + 1], &private[i], &private_bodies[i]);
+        page_hints.push(PageHint {
+            objects: private[i].len() as u64,
+            length: private_bodies[i].iter().map(|b| b.len() as u64).sum(),
+            content_offset,
+            content_length,
+            shared_refs: objects
+                .iter()
+                .filter_map(|id| first_entries.get(id).or(shared_entries.get(id)).copied())
+                .collect(),
+        });
+    }
+    let mut shared_hint = SharedHint {
+        first_object: shared.first().map(|id| map[id] as u64).unwrap_or_default(),
+        offset: 0,
+        first_page_entries: first_page.len() as u64,
+        lengths: first_bodies
+            .iter()
+            .chain(shared_bodies.iter())
+            .map(|b| b.len() as u64)
+            .collect(),
+    };
+
+    // 4. layout, every offset field is zero padded so sizes are fixed
+    let lin_dict = |length: usize, hint: (usize, usize), end: usize, main_xref: usize| {
+        let mut out = Vec::new();
+        write!(
+            out,
+            "{} 0 obj\n<</Linearized 1/L {:010}/H [{:010} {:010}]/O {}/E {:010}/N {}/T {:010}>>\nendobj\n",
+            lin_num,
+            length,
+            hint.0,
+            hint.1,
+            map[&pages[0]],
+            end,
+            pages.len(),
+            main_xref
+        )
+        .ok();
+        out
+    };
+    let first_xref = |offsets: &[usize], main_xref: usize| {
+        let mut out = xref_table(main_count, offsets, false);
+        let mut trailer = trailer.clone();
+        trailer.set("Size", total as i64);
+        out.extend_from_slice(b"trailer\n");
+        write_object(&mut out, &Object::Dictionary(trailer));
+        // padded /Prev keeps the section size independent of the value
+        out.truncate(out.len() - 2);
+        write!(out, "/Prev {:010}>>\nstartxref\n0\n%%EOF\n", main_xref).ok();
+        out
+    };
+    let hint_stream = |data: Vec<u8>, shared_offset: usize| {
+        let mut dict = Dictionary::new();
+        dict.set("S", shared_offset as i64);
+        let mut out = Vec::new();
+        write_indirect_object(
+            &mut out,
+            (hint_num, 0),
+            &Object::Stream(Stream::new(dict, data)),
+        );
+        out
+    };
+
+    let mut header = Vec::new();
+    write_header(&mut header, &doc.version);
+    let lin_len = lin_dict(0, (0, 0), 0, 0).len();
+    let first_xref_len = first_xref(&vec![0; first_page_count as usize], 0).len();
+    let (data, shared_offset) = hint_tables(0, &page_hints, &shared_hint);
+    let hint_len = hint_stream(data, shared_offset).len();
+
+    let mut offset = header.len() + lin_len;
+    let first_xref_offset = offset;
+    offset += first_xref_len;
+    let catalog_offset = offset;
+    offset += catalog.len();
+    let hint_offset = offset;
+    offset += hint_len;
+    let mut first_offsets = Vec::new();
+    for body in first_bodies.iter() {
+        first_offsets.push(offset);
+        offset += body.len();
+    }
+    let end_of_first_page = offset;
+    let mut main_offsets = Vec::new();
+    for body in private_bodies.iter().flatten() {
+        main_offsets.push(offset);
+        offset += body.len();
+    }
+    shared_hint.offset = offset as u64;
+    for body in shared_bodies.iter().chain(other_bodies.iter()) {
+        main_offsets.push(offset);
+        offset += body.len();
+    }
+    let main_xref_offset = offset;
+    let main_xref = xref_table(0, &main_offsets, true);
+    let main_xref_first_entry = main_xref_offset + format!("xref\n0 {}", main_count).len();
+
+    let mut tail = main_xref;
+    write!(
+        tail,
+        "trailer\n<</Size {}>>\nstartxref\n{}\n%%EOF\n",
+        main_count, first_xref_offset
+    )
+    .ok();
+    let length = main_xref_offset + tail.len();
+
+    // 5. write out with the final offsets
+    let first_page_offset = first_offsets.first().copied().unwrap_or_default();
+    let (data, shared_offset) = hint_tables(first_page_offset as u64, &page_hints, &shared_hint);
+
+    let mut out = header;
+    let lin_offset = out.len();
+    out.extend(lin_dict(
+        length,
+        (hint_offset, hint_len),
+        end_of_first_page,
+        main_xref_first_entry,
+    ));
+    let mut first_section_offsets = vec![lin_offset, catalog_offset, hint_offset];
+    first_section_offsets.extend(first_offsets);
+    out.extend(first_xref(&first_section_offsets, main_xref_offset));
+    out.extend(catalog);
+    out.extend(hint_stream(data, shared_offset));
+    first_bodies.into_iter().for_each(|b| out.extend(b));
+    private_bodies
+        .into_iter()
+        .flatten()
+        .for_each(|b| out.extend(b));
+    shared_bodies.into_iter().for_each(|b| out.extend(b));
+    other_bodies.into_iter().for_each(|b| out.extend(b));
+    out.extend(tail);
+
+    debug_assert_eq!(out.len(), length);
+    Ok(out)
+}
@@ -0,0 +1,44 @@
+use lopdf::{Dictionary, Document, Object, StringFormat};
+
+mod acroform;
+mod attachments;
+mod linearize;
+mod optimize;
+#[cfg(test)]
+mod tests;
+mod writer;
+
+pub(crate) use acroform::{build_acroform, collect_fields_js, FormField, PrintLayout};
+pub(crate) use attachments::{embed_files, Attachment, EmbeddedFile};
+pub(crate) use optimize::{optimize, OptimizeOption};
+
+pub(crate) fn set_author(doc: &mut Document, author: &str) {
+    let mut info = Dictionary::new();
+    info.set(
+        "Author",
+        Object::String(author.into(), StringFormat::Literal),
+    );
+    let value = doc.add_object(Object::Dictionary(info));
+    doc.trailer.set("Info", value);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SaveMode {
+    Plain,
+    // compressed object streams and a cross reference stream
+    ObjectStreams,
+    // first page up front with hint tables, see linearize.rs
+    Linearized,
+}
+
+pub(crate) fn save(doc: &mut Document, mode: SaveMode) -> Result<Vec<u8>, String> {
+    match mode {
+        SaveMode::Plain => {
+            let mut content = Vec::new();
+            doc.save_to(&mut content).map_err(|e| e.to_string())?;
+            Ok(content)
+        }
+        SaveMode::ObjectStreams => Ok(writer::save_with_object_streams(doc)),
+        SaveMode::Linearized => linearize::save_linearized(doc),
+    }
+}
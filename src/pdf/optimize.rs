@@ -0,0 +1,408 @@
+use super::writer::{is_structural, write_object};
+use flate2::read::ZlibDecoder;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat};
+use lopdf::{content::Content, Dictionary, Document, Object, ObjectId};
+use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+
+// nested form xobjects deeper than this are ignored when measuring images
+const MAX_FORM_DEPTH: usize = 8;
+// images are only resampled when they exceed the target dpi by this factor
+const DPI_TOLERANCE: f32 = 1.1;
+
+#[derive(Debug, Clone)]
+pub(crate) struct OptimizeOption {
+    pub image_dpi: u32,
+    pub jpeg_quality: u8,
+}
+
+impl Default for OptimizeOption {
+    fn default() -> Self {
+        Self {
+            image_dpi: 150,
+            jpeg_quality: 80,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct OptimizeReport {
+    pub images_resampled: usize,
+    pub images_reencoded: usize,
+    pub duplicates_removed: usize,
+}
+
+/// Shrink the document in place: resample oversized images, recompress
+/// jpegs, merge duplicate objects and compress every stream left plain.
+/// Packing into object streams happens when the document is saved.
+pub(crate) fn optimize(doc: &mut Document, opt: &OptimizeOption) -> OptimizeReport {
+    let mut report = OptimizeReport::default();
+    let sizes = image_display_sizes(doc);
+
+    for (id, (display_width, display_height)) in sizes {
+        let color_space = match doc.get_object(id).and_then(Object::as_stream) {
+            Ok(stream) => device_color_space(doc, &stream.dict),
+            Err(_) => continue,
+        };
+        // ICC based, indexed, cmyk and the others keep their samples
+        let Some(color_space) = color_space else {
+            continue;
+        };
+        let stream = match doc.get_object_mut(id).and_then(Object::as_stream_mut) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        match resample_image(stream, color_space, display_width, display_height, opt) {
+            Some(true) => report.images_resampled += 1,
+            Some(false) => report.images_reencoded += 1,
+            None => {}
+        }
+    }
+
+    report.duplicates_removed = dedup_objects(doc);
+    doc.prune_objects();
+    doc.compress();
+    report
+}
+
+type Matrix = [f32; 6];
+
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+fn as_matrix(operands: &[Object]) -> Option<Matrix> {
+    let values = operands
+        .iter()
+        .map(|o| o.as_float().ok())
+        .collect::<Option<Vec<_>>>()?;
+    values.try_into().ok()
+}
+
+fn xobjects_of(doc: &Document, resources: &Dictionary) -> HashMap<Vec<u8>, ObjectId> {
+    resources
+        .get_deref(b"XObject", doc)
+        .and_then(Object::as_dict)
+        .map(|dict| {
+            dict.iter()
+                .filter_map(|(name, obj)| obj.as_reference().ok().map(|id| (name.clone(), id)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn page_xobjects(doc: &Document, page_id: ObjectId) -> HashMap<Vec<u8>, ObjectId> {
+    let mut xobjects = HashMap::new();
+    if let Ok((direct, inherited)) = doc.get_page_resources(page_id) {
+        // inherited resources come last so the page's own entries win
+        for id in inherited.iter().rev() {
+            if let Ok(dict) = doc.get_dictionary(*id) {
+                xobjects.extend(xobjects_of(doc, dict));
+            }
+        }
+        if let Some(dict) = direct {
+            xobjects.extend(xobjects_of(doc, dict));
+        }
+    }
+    xobjects
+}
+
+fn collect_sizes(
+    doc: &Document,
+    content: &[u8],
+    xobjects: &HashMap<Vec<u8>, ObjectId>,
+    base: Matrix,
+    depth: usize,
+    sizes: &mut HashMap<ObjectId, (f32, f32)>,
+) {
+    let content = match Content::decode(content) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+    let mut ctm = base;
+    let mut stack = Vec::new();
+
+    for op in content.operations {
+        match op.operator.as_str() {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(base),
+            "cm" => {
+                if let Some(m) = as_matrix(&op.operands) {
+                    ctm = multiply(&m, &ctm);
+                }
+            }
+            "Do" => {
+                let id = match op
+                    .operands
+                    .first()
+                    .and_then(|o| o.as_name().ok())
+                    .and_then(|name| xobjects.get(name))
+                {
+                    Some(id) => *id,
+                    None => continue,
+                };
+                let stream = match doc.get_object(id).and_then(Object::as_stream) {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => {
+                        let width = ctm[0].hypot(ctm[1]);
+                        let height = ctm[2].hypot(ctm[3]);
+                        let mut targets = vec![id];
+                        if let Ok(mask) = stream.dict.get(b"SMask").and_then(Object::as_reference) {
+                            targets.push(mask);
+                        }
+                        for target in targets {
+                            let entry = sizes.entry(target).or_insert((0.0, 0.0));
+                            entry.0 = entry.0.max(width);
+                            entry.1 = entry.1.max(height);
+                        }
+                    }
+                    Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                        let matrix = stream
+                            .dict
+                            .get(b"Matrix")
+                            .and_then(Object::as_array)
+                            .ok()
+                            .and_then(|m| as_matrix(m))
+                            .unwrap_or([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+                        let mut inner = xobjects.clone();
+                        if let Ok(resources) = stream.dict.get_deref(b"Resources", doc) {
+                            if let Ok(resources) = resources.as_dict() {
+                                inner = xobjects_of(doc, resources);
+                            }
+                        }
+                        if let Ok(data) = stream.get_plain_content() {
+                            collect_sizes(
+                                doc,
+                                &data,
+                                &inner,
+                                multiply(&matrix, &ctm),
+                                depth + 1,
+                                sizes,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Largest size, in points, each image xobject is painted at on any page
+fn image_display_sizes(doc: &Document) -> HashMap<ObjectId, (f32, f32)> {
+    let mut sizes = HashMap::new();
+    for page_id in doc.page_iter() {
+        let content = match doc.get_page_content(page_id) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let xobjects = page_xobjects(doc, page_id);
+        collect_sizes(
+            doc,
+            &content,
+            &xobjects,
+            [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            0,
+            &mut sizes,
+        );
+    }
+    sizes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorSpace {
+    Gray,
+    Rgb,
+}
+
+/// The color space of an image when it is DeviceGray or DeviceRGB, resolving a reference
+fn device_color_space(doc: &Document, dict: &Dictionary) -> Option<ColorSpace> {
+    let color_space = match dict.get(b"ColorSpace").ok()? {
+        Object::Reference(id) => doc.get_object(*id).ok()?,
+        color_space => color_space,
+    };
+    match color_space.as_name().ok()? {
+        b"DeviceGray" => Some(ColorSpace::Gray),
+        b"DeviceRGB" => Some(ColorSpace::Rgb),
+        _ => None,
+    }
+}
+
+/// Returns Some(true) when resampled, Some(false) when only re-encoded
+fn resample_image(
+    stream: &mut lopdf::Stream,
+    color_space: ColorSpace,
+    display_width: f32,
+    display_height: f32,
+    opt: &OptimizeOption,
+) -> Option<bool> {
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).ok()? as u32;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).ok()? as u32;
+    if width == 0 || height == 0 || display_width <= 0.0 || display_height <= 0.0 {
+        return None;
+    }
+
+    let dpi = (width as f32 * 72.0 / display_width).max(height as f32 * 72.0 / display_height);
+    let scale = opt.image_dpi as f32 / dpi;
+    let (new_width, new_height) = if scale * DPI_TOLERANCE < 1.0 {
+        (
+            ((width as f32 * scale).ceil() as u32).max(1),
+            ((height as f32 * scale).ceil() as u32).max(1),
+        )
+    } else {
+        (width, height)
+    };
+    let resize = new_width != width || new_height != height;
+
+    let filters = stream.filters().unwrap_or_default();
+    let bits = stream
+        .dict
+        .get(b"BitsPerComponent")
+        .and_then(Object::as_i64)
+        .unwrap_or(8);
+
+    match filters.as_slice() {
+        [f] if f == "DCTDecode" => {
+            let img =
+                image::load_from_memory_with_format(&stream.content, ImageFormat::Jpeg).ok()?;
+            let img = match resize {
+                true => img.resize_exact(new_width, new_height, FilterType::Triangle),
+                false => img,
+            };
+            let img = match color_space {
+                ColorSpace::Gray => DynamicImage::ImageLuma8(img.to_luma8()),
+                ColorSpace::Rgb => DynamicImage::ImageRgb8(img.to_rgb8()),
+            };
+            let mut data = Vec::new();
+            JpegEncoder::new_with_quality(&mut data, opt.jpeg_quality)
+                .encode_image(&img)
+                .ok()?;
+            if !resize && data.len() >= stream.content.len() {
+                return None;
+            }
+            stream.set_content(data);
+        }
+        [f] if f == "FlateDecode" && bits == 8 && resize => {
+            if stream.dict.has(b"DecodeParms") {
+                return None;
+            }
+            let channels = match color_space {
+                ColorSpace::Gray => 1,
+                ColorSpace::Rgb => 3,
+            };
+            let mut raw = Vec::new();
+            ZlibDecoder::new(stream.content.as_slice())
+                .read_to_end(&mut raw)
+                .ok()?;
+            if raw.len() != (width * height * channels) as usize {
+                return None;
+            }
+            let img = match channels {
+                1 => DynamicImage::ImageLuma8(image::GrayImage::from_raw(width, height, raw)?),
+                _ => DynamicImage::ImageRgb8(image::RgbImage::from_raw(width, height, raw)?),
+            };
+            let img = img.resize_exact(new_width, new_height, FilterType::Triangle);
+            stream.dict.remove(b"Filter");
+            stream.set_plain_content(img.into_bytes());
+            stream.compress().ok()?;
+        }
+        _ => return None,
+    }
+
+    stream.dict.set("Width", new_width as i64);
+    stream.dict.set("Height", new_height as i64);
+    Some(resize)
+}
+
+fn fingerprint(object: &Object) -> u64 {
+    let mut data = Vec::new();
+    write_object(&mut data, object);
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn same_object(a: &Object, b: &Object) -> bool {
+    match (a, b) {
+        // ignore where the stream was read from
+        (Object::Stream(a), Object::Stream(b)) => a.dict == b.dict && a.content == b.content,
+        _ => a == b,
+    }
+}
+
+fn replace_references(object: &mut Object, replaced: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(new_id) = replaced.get(id) {
+                *id = *new_id;
+            }
+        }
+        Object::Array(array) => array
+            .iter_mut()
+            .for_each(|item| replace_references(item, replaced)),
+        Object::Dictionary(dict) => dict
+            .iter_mut()
+            .for_each(|(_, item)| replace_references(item, replaced)),
+        Object::Stream(stream) => stream
+            .dict
+            .iter_mut()
+            .for_each(|(_, item)| replace_references(item, replaced)),
+        _ => {}
+    }
+}
+
+/// Merge byte-identical objects (font subsets, images, graphics states)
+/// into one, repeating until no more merges happen
+pub(crate) fn dedup_objects(doc: &mut Document) -> usize {
+    let mut total = 0;
+    loop {
+        let mut seen: HashMap<u64, Vec<ObjectId>> = HashMap::new();
+        let mut replaced = HashMap::new();
+
+        for (id, object) in doc.objects.iter() {
+            if is_structural(object) {
+                continue;
+            }
+            if let Ok("Page" | "Pages" | "Catalog") = object.type_name() {
+                continue;
+            }
+            let candidates = seen.entry(fingerprint(object)).or_default();
+            match candidates.iter().find(|other| {
+                doc.objects
+                    .get(other)
+                    .is_some_and(|o| same_object(o, object))
+            }) {
+                Some(other) => {
+                    replaced.insert(*id, *other);
+                }
+                None => candidates.push(*id),
+            }
+        }
+
+        if replaced.is_empty() {
+            return total;
+        }
+        total += replaced.len();
+        for id in replaced.keys() {
+            doc.objects.remove(id);
+        }
+        for object in doc.objects.values_mut() {
+            replace_references(object, &replaced);
+        }
+        for (_, object) in doc.trailer.iter_mut() {
+            replace_references(object, &replaced);
+        }
+    }
+}
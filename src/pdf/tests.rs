@@ -0,0 +1,471 @@
+use super::{
+    build_acroform, embed_files, optimize, save, set_author, Attachment, EmbeddedFile, FormField,
+    OptimizeOption, PrintLayout, SaveMode,
+};
+use lopdf::{dictionary, Document, Object, Stream};
+
+fn sample_document() -> Document {
+    let mut doc = Document::with_version("1.4");
+    let pages_id = doc.new_object_id();
+
+    let font = dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    };
+    let font_a = doc.add_object(font.clone());
+    let font_b = doc.add_object(font);
+
+    let pixels = vec![128u8; 400 * 400 * 3];
+    let mut image = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => 400,
+            "Height" => 400,
+            "ColorSpace" => "DeviceRGB",
+            "BitsPerComponent" => 8,
+        },
+        pixels,
+    );
+    image.compress().unwrap();
+    let image_id = doc.add_object(image);
+
+    let resources = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_a, "F2" => font_b },
+        "XObject" => dictionary! { "Im1" => image_id },
+    });
+    // paint the 400px image into one inch, 400 dpi
+    let content = b"q 72 0 0 72 0 0 cm /Im1 Do Q BT /F1 12 Tf (hi) Tj ET".to_vec();
+    let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "Contents" => content_id,
+        "Resources" => resources,
+        "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+    });
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc
+}
+
+fn image_width(doc: &Document) -> i64 {
+    doc.objects
+        .values()
+        .filter_map(|o| o.as_stream().ok())
+        .find(|s| s.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image"))
+        .and_then(|s| s.dict.get(b"Width").and_then(Object::as_i64).ok())
+        .expect("image not found")
+}
+
+#[test]
+fn test_save_with_object_streams() {
+    let mut doc = sample_document();
+    set_author(&mut doc, "browserlify_test");
+    let content = save(&mut doc, SaveMode::ObjectStreams).expect("save fail");
+
+    let doc = Document::load_mem(&content).expect("load pdf fail");
+    assert_eq!(doc.get_pages().len(), 1);
+    assert!(doc
+        .objects
+        .values()
+        .any(|o| o.type_name().ok() == Some("ObjStm")));
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .and_then(Object::as_reference)
+        .and_then(|id| doc.get_dictionary(id))
+        .expect("get info fail");
+    assert_eq!(
+        info.get(b"Author").and_then(Object::as_str).unwrap(),
+        b"browserlify_test"
+    );
+}
+
+#[test]
+fn test_optimize() {
+    let mut doc = sample_document();
+    let original = save(&mut doc, SaveMode::Plain).expect("save fail");
+
+    let report = optimize(&mut doc, &OptimizeOption::default());
+    assert_eq!(report.images_resampled, 1);
+    assert_eq!(report.duplicates_removed, 1);
+    assert_eq!(image_width(&doc), 150);
+
+    let content = save(&mut doc, SaveMode::ObjectStreams).expect("save fail");
+    assert!(content.len() < original.len());
+
+    let doc = Document::load_mem(&content).expect("load pdf fail");
+    assert_eq!(image_width(&doc), 150);
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    assert_eq!(doc.get_page_fonts(page_id).unwrap().len(), 2);
+}
+
+fn gray_jpeg_document(color_space: Object) -> Document {
+    let mut doc = sample_document();
+    let mut jpeg = Vec::new();
+    image::DynamicImage::ImageLuma8(image::GrayImage::from_pixel(400, 400, image::Luma([90])))
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+    let color_space = match color_space {
+        Object::Name(name) if name == b"ICCBased" => {
+            let profile = doc.add_object(Stream::new(dictionary! { "N" => 1 }, vec![0u8; 128]));
+            Object::Array(vec![Object::Name(name), profile.into()])
+        }
+        color_space => color_space,
+    };
+    let color_space = doc.add_object(color_space);
+    let image = doc
+        .objects
+        .values_mut()
+        .filter_map(|o| o.as_stream_mut().ok())
+        .find(|s| s.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image"))
+        .unwrap();
+    image.dict.set("Filter", "DCTDecode");
+    image.dict.set("ColorSpace", color_space);
+    image.set_content(jpeg);
+    doc
+}
+
+#[test]
+fn test_optimize_color_spaces() {
+    // a referenced DeviceGray is resampled as gray
+    let mut doc = gray_jpeg_document(Object::Name(b"DeviceGray".to_vec()));
+    assert_eq!(
+        optimize(&mut doc, &OptimizeOption::default()).images_resampled,
+        1
+    );
+    assert_eq!(image_width(&doc), 150);
+
+    // a one component ICC profile or a palette would be rewritten as rgb, they are kept
+    for color_space in [
+        Object::Name(b"ICCBased".to_vec()),
+        Object::Array(vec![
+            Object::Name(b"Indexed".to_vec()),
+            Object::Name(b"DeviceRGB".to_vec()),
+            0.into(),
+            Object::string_literal(vec![0u8; 3]),
+        ]),
+    ] {
+        let mut doc = gray_jpeg_document(color_space);
+        let report = optimize(&mut doc, &OptimizeOption::default());
+        assert_eq!(report.images_resampled, 0);
+        assert_eq!(report.images_reencoded, 0);
+        assert_eq!(image_width(&doc), 400);
+    }
+}
+
+/// A4 with the default margins of chrome
+fn a4_layout() -> PrintLayout {
+    PrintLayout {
+        width: 8.27,
+        height: 11.69,
+        margin_top: 0.4,
+        margin_bottom: 0.4,
+        margin_left: 0.4,
+        margin_right: 0.4,
+        scale: 1.0,
+        page_ranges: None,
+    }
+}
+
+fn widget_rects(doc: &Document) -> Vec<Vec<f32>> {
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    doc.get_page_annotations(page_id)
+        .unwrap()
+        .iter()
+        .map(|w| {
+            w.get(b"Rect")
+                .and_then(Object::as_array)
+                .unwrap()
+                .iter()
+                .map(|v| (v.as_float().unwrap() * 100.0).round() / 100.0)
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_build_acroform() {
+    let mut doc = sample_document();
+    let fields: Vec<FormField> = serde_json::from_str(
+        r#"[
+        {"kind": "text", "name": "email", "value": "a@b.c", "checked": false, "required": true,
+         "readonly": false, "multiple": false, "max_length": null, "options": [], "selected": [],
+         "rect": [0, 0, 100, 20]},
+        {"kind": "radio", "name": "plan", "value": "free", "checked": false, "required": false,
+         "readonly": false, "multiple": false, "max_length": null, "options": [], "selected": [],
+         "rect": [0, 40, 16, 16]},
+        {"kind": "radio", "name": "plan", "value": "pro", "checked": true, "required": false,
+         "readonly": false, "multiple": false, "max_length": null, "options": [], "selected": [],
+         "rect": [0, 60, 16, 16]}
+    ]"#,
+    )
+    .unwrap();
+    assert_eq!(build_acroform(&mut doc, &fields, &a4_layout()), 2);
+
+    let content = save(&mut doc, SaveMode::Plain).expect("save fail");
+    let doc = Document::load_mem(&content).expect("load pdf fail");
+    let acroform = doc
+        .catalog()
+        .and_then(|c| c.get(b"AcroForm"))
+        .and_then(Object::as_dict)
+        .expect("get acroform fail");
+    assert_eq!(
+        acroform
+            .get(b"Fields")
+            .and_then(Object::as_array)
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let widgets = doc.get_page_annotations(page_id).unwrap();
+    assert_eq!(widgets.len(), 3);
+    assert!(widgets
+        .iter()
+        .all(|w| w.get(b"Subtype").and_then(Object::as_name).unwrap() == b"Widget"));
+    // inside the margins, 96 css pixels to the inch
+    assert_eq!(widget_rects(&doc)[0], [28.8, 797.88, 103.8, 812.88]);
+    let plan = widgets[1]
+        .get(b"Parent")
+        .and_then(Object::as_reference)
+        .and_then(|id| doc.get_dictionary(id))
+        .expect("get radio group fail");
+    assert_eq!(plan.get(b"V").and_then(Object::as_name).unwrap(), b"pro");
+}
+
+#[test]
+fn test_acroform_radio_states() {
+    let mut doc = sample_document();
+    // radios without a value attribute
+    let fields: Vec<FormField> = serde_json::from_str(
+        r#"[
+        {"kind": "radio", "name": "size", "value": "", "checked": false, "required": false,
+         "readonly": false, "multiple": false, "max_length": null, "options": [], "selected": [],
+         "rect": [0, 0, 16, 16]},
+        {"kind": "radio", "name": "size", "value": "", "checked": true, "required": false,
+         "readonly": false, "multiple": false, "max_length": null, "options": [], "selected": [],
+         "rect": [20, 0, 16, 16]}
+    ]"#,
+    )
+    .unwrap();
+    assert_eq!(build_acroform(&mut doc, &fields, &a4_layout()), 1);
+
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let widgets = doc.get_page_annotations(page_id).unwrap();
+    let states = widgets
+        .iter()
+        .map(|w| {
+            let normal = w
+                .get(b"AP")
+                .and_then(Object::as_dict)
+                .and_then(|ap| ap.get(b"N"))
+                .and_then(Object::as_dict)
+                .unwrap();
+            let on = normal
+                .iter()
+                .map(|(k, _)| k.clone())
+                .find(|k| k != b"Off")
+                .unwrap();
+            (on, w.get(b"AS").and_then(Object::as_name).unwrap().to_vec())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        states,
+        [
+            (b"0".to_vec(), b"Off".to_vec()),
+            (b"1".to_vec(), b"1".to_vec())
+        ]
+    );
+    let size = widgets[0]
+        .get(b"Parent")
+        .and_then(Object::as_reference)
+        .and_then(|id| doc.get_dictionary(id))
+        .expect("get radio group fail");
+    assert_eq!(size.get(b"V").and_then(Object::as_name).unwrap(), b"1");
+}
+
+#[test]
+fn test_acroform_layout() {
+    let control = |name: &str, kind: &str, top: f64| {
+        serde_json::from_value::<FormField>(serde_json::json!({
+            "kind": kind, "name": name, "value": "", "checked": false, "required": false,
+            "readonly": false, "multiple": false, "max_length": null, "options": [],
+            "selected": [], "rect": [0, top, 100, 20],
+        }))
+        .unwrap()
+    };
+    // a4 content is 1045.44 css pixels high
+    let fields = vec![
+        control("first", "text", 10.0),
+        // across the break, printed at the top of the second page
+        control("second", "text", 1040.0),
+        control("third", "text", 1100.0),
+    ];
+
+    // the second page isn't in the document
+    let mut doc = sample_document();
+    assert_eq!(build_acroform(&mut doc, &fields, &a4_layout()), 1);
+
+    // only the second page is printed, it is the first of the pdf
+    let mut doc = sample_document();
+    let layout = PrintLayout {
+        page_ranges: Some("2".to_string()),
+        ..a4_layout()
+    };
+    assert_eq!(build_acroform(&mut doc, &fields, &layout), 2);
+    assert_eq!(
+        widget_rects(&doc),
+        [[28.8, 797.88, 103.8, 812.88], [28.8, 756.96, 103.8, 771.96]]
+    );
+
+    // a name shared by a text and a checkbox is one field per kind
+    let mut doc = sample_document();
+    let fields = vec![
+        control("contact", "text", 10.0),
+        control("contact", "checkbox", 40.0),
+    ];
+    assert_eq!(build_acroform(&mut doc, &fields, &a4_layout()), 2);
+    let page_id = *doc.get_pages().get(&1).unwrap();
+    let names = doc
+        .get_page_annotations(page_id)
+        .unwrap()
+        .iter()
+        .map(|w| w.get(b"T").and_then(Object::as_str).unwrap().to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [b"contact_checkbox".to_vec(), b"contact_text".to_vec()]
+    );
+}
+
+#[test]
+fn test_save_linearized() {
+    let mut doc = sample_document();
+    // a second page sharing the resources of the first one
+    let pages_id = doc
+        .catalog()
+        .unwrap()
+        .get(b"Pages")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let first_id = *doc.get_pages().get(&1).unwrap();
+    let mut second = doc.get_dictionary(first_id).unwrap().clone();
+    let content_id = doc.add_object(Stream::new(
+        dictionary! {},
+        b"BT /F1 12 Tf (2) Tj ET".to_vec(),
+    ));
+    second.set("Contents", content_id);
+    let second_id = doc.add_object(second);
+    let pages = doc.get_dictionary_mut(pages_id).unwrap();
+    pages.set("Kids", vec![first_id.into(), second_id.into()]);
+    pages.set("Count", 2);
+    set_author(&mut doc, "browserlify_test");
+
+    let content = save(&mut doc, SaveMode::Linearized).expect("save fail");
+    let head = String::from_utf8_lossy(&content[..content.len().min(1024)]).to_string();
+    assert!(head.contains("/Linearized 1"));
+    assert!(head.contains(&format!("/L {:010}", content.len())));
+    assert!(head.contains("/N 2"));
+
+    let doc = Document::load_mem(&content).expect("load pdf fail");
+    assert_eq!(doc.get_pages().len(), 2);
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .and_then(Object::as_reference)
+        .and_then(|id| doc.get_dictionary(id))
+        .expect("get info fail");
+    assert_eq!(
+        info.get(b"Author").and_then(Object::as_str).unwrap(),
+        b"browserlify_test"
+    );
+}
+
+#[test]
+fn test_embed_files() {
+    let mut doc = sample_document();
+    let attachment: Attachment = serde_json::from_str(
+        r#"{"name": "factur-x.xml", "content": "PHhtbC8+", "mime_type": "text/xml", "relationship": "Alternative"}"#,
+    )
+    .unwrap();
+    let bad: Attachment = serde_json::from_str(r#"{"name": "bad.bin", "content": "%%%"}"#).unwrap();
+    assert!(EmbeddedFile::try_from(&bad).is_err());
+
+    let upload: Attachment =
+        serde_json::from_str(r#"{"name": "source.html", "content": "PHAvPg=="}"#).unwrap();
+    let files = vec![
+        EmbeddedFile::try_from(&attachment).unwrap(),
+        EmbeddedFile::try_from(&upload).unwrap(),
+        EmbeddedFile::try_from(&upload).unwrap(),
+        EmbeddedFile::new("source.html", b"<html></html>".to_vec(), "text/html"),
+    ];
+    assert_eq!(embed_files(&mut doc, &files), 4);
+
+    let content = save(&mut doc, SaveMode::ObjectStreams).expect("save fail");
+    let doc = Document::load_mem(&content).expect("load pdf fail");
+    let catalog = doc.catalog().unwrap();
+    assert_eq!(
+        catalog.get(b"AF").and_then(Object::as_array).unwrap().len(),
+        4
+    );
+    let names = catalog
+        .get_deref(b"Names", &doc)
+        .and_then(Object::as_dict)
+        .and_then(|n| n.get(b"EmbeddedFiles"))
+        .and_then(Object::as_dict)
+        .and_then(|e| e.get(b"Names"))
+        .and_then(Object::as_array)
+        .expect("get name tree fail");
+    let keys = names
+        .iter()
+        .step_by(2)
+        .map(|name| name.as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        [
+            &b"factur-x.xml"[..],
+            b"source-2.html",
+            b"source-3.html",
+            b"source.html"
+        ]
+    );
+
+    let spec = doc
+        .get_dictionary(names[1].as_reference().unwrap())
+        .unwrap();
+    assert_eq!(
+        spec.get(b"AFRelationship")
+            .and_then(Object::as_name)
+            .unwrap(),
+        b"Alternative"
+    );
+    let stream = spec
+        .get(b"EF")
+        .and_then(Object::as_dict)
+        .and_then(|ef| ef.get(b"F"))
+        .and_then(Object::as_reference)
+        .and_then(|id| doc.get_object(id))
+        .and_then(Object::as_stream)
+        .unwrap();
+    assert_eq!(stream.get_plain_content().unwrap(), b"<xml/>");
+}
@@ -0,0 +1,226 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::io::Write;
+
+// objects packed into one object stream
+const OBJECTS_PER_STREAM: usize = 100;
+
+pub(crate) fn write_object(out: &mut Vec<u8>, object: &Object) {
+    match object {
+        Object::Null => out.extend_from_slice(b"null"),
+        Object::Boolean(v) => out.extend_from_slice(if *v { b"true" } else { b"false" }),
+        Object::Integer(v) => {
+            write!(out, "{}", v).ok();
+        }
+        Object::Real(v) => {
+            write!(out, "{}", v).ok();
+        }
+        Object::Name(name) => write_name(out, name),
+        Object::String(text, format) => write_string(out, text, format),
+        Object::Array(array) => {
+            out.push(b'[');
+            for (i, item) in array.iter().enumerate() {
+                if i > 0 {
+                    out.push(b' ');
+                }
+                write_object(out, item);
+            }
+            out.push(b']');
+        }
+        Object::Dictionary(dict) => write_dictionary(out, dict),
+        Object::Stream(stream) => write_stream(out, stream),
+        Object::Reference((id, generation)) => {
+            write!(out, "{} {} R", id, generation).ok();
+        }
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &[u8]) {
+    out.push(b'/');
+    for &c in name {
+        if c > b' ' && c <= b'~' && !b"#/()<>[]{}%".contains(&c) {
+            out.push(c);
+        } else {
+            write!(out, "#{:02X}", c).ok();
+        }
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, text: &[u8], format: &StringFormat) {
+    match format {
+        StringFormat::Literal => {
+            out.push(b'(');
+            for &c in text {
+                match c {
+                    b'(' | b')' | b'\\' => {
+                        out.push(b'\\');
+                        out.push(c);
+                    }
+                    b'\r' => out.extend_from_slice(b"\\r"),
+                    _ => out.push(c),
+                }
+            }
+            out.push(b')');
+        }
+        StringFormat::Hexadecimal => {
+            out.push(b'<');
+            for &c in text {
+                write!(out, "{:02X}", c).ok();
+            }
+            out.push(b'>');
+        }
+    }
+}
+
+fn write_dictionary(out: &mut Vec<u8>, dict: &Dictionary) {
+    out.extend_from_slice(b"<<");
+    for (key, value) in dict.iter() {
+        write_name(out, key);
+        out.push(b' ');
+        write_object(out, value);
+    }
+    out.extend_from_slice(b">>");
+}
+
+fn write_stream(out: &mut Vec<u8>, stream: &Stream) {
+    let mut dict = stream.dict.clone();
+    dict.set("Length", stream.content.len() as i64);
+    write_dictionary(out, &dict);
+    out.extend_from_slice(b"\nstream\n");
+    out.extend_from_slice(&stream.content);
+    out.extend_from_slice(b"\nendstream");
+}
+
+pub(crate) fn write_indirect_object(out: &mut Vec<u8>, id: ObjectId, object: &Object) {
+    writeln!(out, "{} {} obj", id.0, id.1).ok();
+    write_object(out, object);
+    out.extend_from_slice(b"\nendobj\n");
+}
+
+/// Objects the writer regenerates itself and must never copy from the source document
+pub(crate) fn is_structural(object: &Object) -> bool {
+    object
+        .type_name()
+        .map(|name| ["ObjStm", "XRef", "Linearized"].contains(&name))
+        .unwrap_or_default()
+}
+
+pub(crate) fn write_header(out: &mut Vec<u8>, version: &str) {
+    writeln!(out, "%PDF-{}", version).ok();
+    out.extend_from_slice(b"%\xE2\xE3\xCF\xD3\n");
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum XrefEntry {
+    Free,
+    Offset(usize, u16),
+    Compressed(u32, u16),
+}
+
+/// Build a cross reference stream covering `entries`, `entries[0]` is object 0
+pub(crate) fn xref_stream(entries: &[XrefEntry], trailer: &Dictionary) -> Stream {
+    let mut content = Vec::with_capacity(entries.len() * 7);
+    for entry in entries {
+        let (kind, field2, field3) = match *entry {
+            XrefEntry::Free => (0u8, 0u32, 0xFFFFu16),
+            XrefEntry::Offset(offset, generation) => (1, offset as u32, generation),
+            XrefEntry::Compressed(stream_id, index) => (2, stream_id, index),
+        };
+        content.push(kind);
+        content.extend_from_slice(&field2.to_be_bytes());
+        content.extend_from_slice(&field3.to_be_bytes());
+    }
+
+    let mut dict = Dictionary::new();
+    dict.set("Type", Object::Name(b"XRef".to_vec()));
+    dict.set("Size", entries.len() as i64);
+    dict.set(
+        "W",
+        Object::Array(vec![
+            Object::Integer(1),
+            Object::Integer(4),
+            Object::Integer(2),
+        ]),
+    );
+    for key in [b"Root".as_slice(), b"Info", b"ID"] {
+        if let Ok(value) = trailer.get(key) {
+            dict.set(key, value.clone());
+        }
+    }
+    let mut stream = Stream::new(dict, content);
+    stream.compress().ok();
+    stream
+}
+
+fn object_stream(objects: &[(ObjectId, &Object)]) -> Stream {
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    for ((id, _), object) in objects {
+        write!(header, "{} {} ", id, body.len()).ok();
+        write_object(&mut body, object);
+        body.push(b'\n');
+    }
+
+    let mut dict = Dictionary::new();
+    dict.set("Type", Object::Name(b"ObjStm".to_vec()));
+    dict.set("N", objects.len() as i64);
+    dict.set("First", header.len() as i64);
+    header.extend(body);
+
+    let mut stream = Stream::new(dict, header);
+    stream.compress().ok();
+    stream
+}
+
+/// Serialize the document packing every non-stream object into compressed
+/// object streams, indexed by a cross reference stream (PDF 1.5+)
+pub(crate) fn save_with_object_streams(doc: &Document) -> Vec<u8> {
+    let mut out = Vec::new();
+    let version = match doc.version.as_str() {
+        "1.0" | "1.1" | "1.2" | "1.3" | "1.4" => "1.5",
+        v => v,
+    };
+    write_header(&mut out, version);
+
+    let mut next_id = doc.max_id + 1;
+    let mut entries = vec![XrefEntry::Free; next_id as usize];
+    let mut packed = Vec::new();
+
+    for (&id, object) in doc.objects.iter() {
+        if is_structural(object) {
+            continue;
+        }
+        match object {
+            Object::Stream(_) => {
+                entries[id.0 as usize] = XrefEntry::Offset(out.len(), id.1);
+                write_indirect_object(&mut out, id, object);
+            }
+            _ if id.1 != 0 => {
+                entries[id.0 as usize] = XrefEntry::Offset(out.len(), id.1);
+                write_indirect_object(&mut out, id, object);
+            }
+            _ => packed.push((id, object)),
+        }
+    }
+
+    for chunk in packed.chunks(OBJECTS_PER_STREAM) {
+        let stream_id = next_id;
+        next_id += 1;
+        for (index, (id, _)) in chunk.iter().enumerate() {
+            entries[id.0 as usize] = XrefEntry::Compressed(stream_id, index as u16);
+        }
+        entries.push(XrefEntry::Offset(out.len(), 0));
+        write_indirect_object(
+            &mut out,
+            (stream_id, 0),
+            &Object::Stream(object_stream(chunk)),
+        );
+    }
+
+    let xref_id = next_id;
+    let xref_start = out.len();
+    entries.push(XrefEntry::Offset(xref_start, 0));
+    let xref = xref_stream(&entries, &doc.trailer);
+    write_indirect_object(&mut out, (xref_id, 0), &Object::Stream(xref));
+    writeln!(out, "startxref\n{}\n%%EOF", xref_start).ok();
+    out
+}
@@ -0,0 +1,103 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Which urls a render or a session may navigate to, empty lists allow anything
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UrlPolicy {
+    // `example.com`, `*.example.com` for its subdomains, or `*`
+    #[serde(default)]
+    pub allow_hosts: Vec<String>,
+    #[serde(default)]
+    pub deny_hosts: Vec<String>,
+    #[serde(default)]
+    pub schemes: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    // the default policy an api_key policy narrows, checked first
+    #[serde(skip)]
+    pub(crate) base: Option<Arc<UrlPolicy>>,
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim().trim_end_matches('.').to_lowercase();
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
+impl UrlPolicy {
+    pub fn check(&self, u: &url::Url) -> Result<(), String> {
+        if let Some(base) = &self.base {
+            base.check(u)?;
+        }
+        if !self.schemes.is_empty() && !self.schemes.iter().any(|s| s == u.scheme()) {
+            return Err(format!("scheme {} is not allowed", u.scheme()));
+        }
+        self.check_host(
+            u.host_str().unwrap_or_default(),
+            u.port_or_known_default().unwrap_or_default(),
+        )
+    }
+
+    /// The host and port part of the policy, all a proxy sees of a tunnel
+    pub fn check_host(&self, host: &str, port: u16) -> Result<(), String> {
+        if let Some(base) = &self.base {
+            base.check_host(host, port)?;
+        }
+        if !self.ports.is_empty() && !self.ports.contains(&port) {
+            return Err(format!("port {} is not allowed", port));
+        }
+
+        let host = host.trim_end_matches('.').to_lowercase();
+        if self.deny_hosts.iter().any(|p| host_matches(p, &host)) {
+            return Err(format!("host {} is denied", host));
+        }
+        if !self.allow_hosts.is_empty() && !self.allow_hosts.iter().any(|p| host_matches(p, &host))
+        {
+            return Err(format!("host {} is not allowed", host));
+        }
+        Ok(())
+    }
+}
+
+/// The `--url-policy` file: a global policy and the policies of each api_key
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyConfig {
+    #[serde(default)]
+    pub default: UrlPolicy,
+    #[serde(default)]
+    pub keys: HashMap<String, UrlPolicy>,
+}
+
+impl PolicyConfig {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        serde_json::from_str(&std::fs::read_to_string(path)?).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, e))
+        })
+    }
+
+    /// The policy of the api_key within the default one, the default one
+    /// without a key. A key can only narrow the default, both deny lists apply
+    pub fn policy(&self, api_key: Option<&str>) -> Result<Arc<UrlPolicy>, String> {
+        let default = Arc::new(self.default.clone());
+        match api_key {
+            Some(key) => match self.keys.get(key) {
+                Some(policy) => Ok(Arc::new(UrlPolicy {
+                    base: Some(default),
+                    ..policy.clone()
+                })),
+                None => Err("unknown api_key".to_string()),
+            },
+            None => Ok(default),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::devices::Device;
+use crate::headless::browser_config;
+use crate::session::SessionOption;
+use crate::Error;
+use chromiumoxide::Browser;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+
+// how often the idle browsers are checked, and how long a check may take
+const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `--pool-min`, `--pool-max`, `--pool-max-uses` and `--pool-contexts`
+#[derive(Debug, Clone, Copy)]
+pub struct PoolOptions {
+    // browsers kept launched with a free context
+    pub min: usize,
+    // browsers launched by the pool, idle or in use
+    pub max: usize,
+    // contexts created in a browser before it is recycled, 0 is unlimited
+    pub max_uses: usize,
+    // renders a browser runs at once, each one in its own browser context
+    pub contexts: usize,
+}
+
+/// How a session uses a pooled browser
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Lease {
+    // the whole browser, killed afterwards
+    Exclusive,
+    // an incognito browser context of a shared browser, disposed afterwards
+    Context,
+}
+
+/// Only sessions with the default flags can use a pre-launched browser,
+/// a context has its own proxy
+pub(crate) fn is_poolable(opt: &SessionOption, device: Option<&Device>, lease: Lease) -> bool {
+    device.is_none()
+        && opt.uuid.is_none()
+        && opt.userdatadir_expire.is_none()
+        && !opt.enable_cache
+        && opt.lang.is_none()
+        && (opt.proxy.is_none() || lease == Lease::Context)
+}
+
+/// A browser launched by the pool, its handler polled by its own task
+pub(crate) struct PooledBrowser {
+    pub browser: Browser,
+    pub id: String,
+    pub data_dir: String,
+    pub endpoint: String,
+    exited: watch::Receiver<bool>,
+    handler: JoinHandle<()>,
+}
+
+impl PooledBrowser {
+    async fn launch(data_root: &str) -> Result<Self, Error> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let data_dir = format!("{}/pool-{}", data_root.trim_end_matches('/'), id);
+        let config = browser_config(&SessionOption::default(), None, &data_dir)?;
+        std::fs::create_dir_all(&data_dir)?;
+        let (browser, mut handler) = match Browser::launch(config).await {
+            Ok(launched) => launched,
+            Err(e) => {
+                std::fs::remove_dir_all(&data_dir).ok();
+                return Err(e.into());
+            }
+        };
+
+        let (exited_tx, exited) = watch::channel(false);
+        let handler = tokio::spawn(async move {
+            while handler.next().await.is_some() {}
+            exited_tx.send(true).ok();
+        });
+        let endpoint = browser.websocket_address().to_string();
+        log::info!("pool launched id: {} -> {}", id, endpoint);
+        Ok(Self {
+            browser,
+            id,
+            data_dir,
+            endpoint,
+            exited,
+            handler,
+        })
+    }
+
+    pub fn exited(&self) -> watch::Receiver<bool> {
+        self.exited.clone()
+    }
+
+    fn is_alive(&self) -> bool {
+        !*self.exited.borrow()
+    }
+
+    async fn is_healthy(&self) -> bool {
+        self.is_alive()
+            && matches!(
+                tokio::time::timeout(HEALTH_TIMEOUT, self.browser.version()).await,
+                Ok(Ok(_))
+            )
+    }
+
+    async fn kill(mut self) {
+        self.browser.kill().await;
+        self.handler.abort();
+        tokio::fs::remove_dir_all(&self.data_dir).await.ok();
+        log::info!("pool killed id: {}", self.id);
+    }
+}
+
+struct PoolEntry {
+    browser: Arc<PooledBrowser>,
+    // contexts in use
+    active: usize,
+    // contexts created
+    uses: usize,
+}
+
+impl PoolEntry {
+    fn is_retired(&self, options: &PoolOptions) -> bool {
+        (options.max_uses > 0 && self.uses >= options.max_uses) || !self.browser.is_alive()
+    }
+
+    fn is_available(&self, options: &PoolOptions) -> bool {
+        self.active < options.contexts && !self.is_retired(options)
+    }
+}
+
+/// Pre-launched browsers, shared by renders through browser contexts
+pub struct BrowserPool {
+    options: PoolOptions,
+    data_root: String,
+    entries: Mutex<Vec<PoolEntry>>,
+    // in the pool and leased exclusively
+    launched: AtomicUsize,
+    changed: Notify,
+}
+
+impl BrowserPool {
+    pub fn new(options: PoolOptions, data_root: &str) -> Self {
+        Self {
+            options: PoolOptions {
+                min: options.min.min(options.max),
+                contexts: options.contexts.max(1),
+                ..options
+            },
+            data_root: data_root.to_string(),
+            entries: Mutex::new(Vec::new()),
+            launched: AtomicUsize::new(0),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn options(&self) -> PoolOptions {
+        self.options
+    }
+
+    /// Browsers that can take another context
+    pub fn available(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.is_available(&self.options))
+            .count()
+    }
+
+    pub fn launched(&self) -> usize {
+        self.launched.load(Ordering::SeqCst)
+    }
+
+    // a place under `max` for one more browser
+    fn reserve(&self) -> bool {
+        self.launched
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |launched| {
+                (launched < self.options.max).then_some(launched + 1)
+            })
+            .is_ok()
+    }
+
+    // a reserved browser, the place is given back when it doesn't start
+    async fn launch_reserved(&self) -> Option<PooledBrowser> {
+        match PooledBrowser::launch(&self.data_root).await {
+            Ok(browser) => Some(browser),
+            Err(e) => {
+                self.launched.fetch_sub(1, Ordering::SeqCst);
+                log::error!("pool launch error: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The least busy browser for a new context, None when every browser is busy
+    pub(crate) fn acquire_shared(&self) -> Option<Arc<PooledBrowser>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .iter_mut()
+            .filter(|e| e.is_available(&self.options))
+            .min_by_key(|e| e.active)?;
+        entry.active += 1;
+        entry.uses += 1;
+        self.changed.notify_one();
+        Some(entry.browser.clone())
+    }
+
+    /// A browser launched for a new context when every pooled one is busy,
+    /// None once `max` browsers are launched
+    pub(crate) async fn launch_shared(&self) -> Option<Arc<PooledBrowser>> {
+        if !self.reserve() {
+            return None;
+        }
+        let browser = Arc::new(self.launch_reserved().await?);
+        self.entries.lock().unwrap().push(PoolEntry {
+            browser: browser.clone(),
+            active: 1,
+            uses: 1,
+        });
+        Some(browser)
+    }
+
+    /// The context of a shared browser is disposed, the browser is recycled once worn out
+    pub(crate) async fn release_shared(&self, browser: Arc<PooledBrowser>) {
+        let retired = {
+            let mut entries = self.entries.lock().unwrap();
+            let Some(index) = entries.iter().position(|e| e.browser.id == browser.id) else {
+                return;
+            };
+            entries[index].active -= 1;
+            match entries[index].active == 0 && entries[index].is_retired(&self.options) {
+                true => Some(entries.remove(index)),
+                false => None,
+            }
+        };
+        drop(browser);
+        if let Some(entry) = retired {
+            self.discard(entry.browser).await;
+        }
+    }
+
+    /// A whole idle browser, it leaves the pool. One the health check holds
+    /// is skipped, the clones are only taken under the lock
+    pub(crate) fn acquire_exclusive(&self) -> Option<PooledBrowser> {
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries.iter().position(|e| {
+            e.active == 0 && !e.is_retired(&self.options) && Arc::strong_count(&e.browser) == 1
+        })?;
+        let entry = entries.remove(index);
+        match Arc::try_unwrap(entry.browser) {
+            Ok(browser) => {
+                self.changed.notify_one();
+                Some(browser)
+            }
+            // still counted in launched, it stays in the pool
+            Err(browser) => {
+                entries.insert(index, PoolEntry { browser, ..entry });
+                None
+            }
+        }
+    }
+
+    /// A whole browser launched when none is idle, None once `max` browsers are launched
+    pub(crate) async fn launch_exclusive(&self) -> Option<PooledBrowser> {
+        match self.reserve() {
+            true => self.launch_reserved().await,
+            false => None,
+        }
+    }
+
+    pub(crate) async fn release_exclusive(&self, browser: PooledBrowser) {
+        self.discard(Arc::new(browser)).await;
+    }
+
+    async fn discard(&self, browser: Arc<PooledBrowser>) {
+        self.launched.fetch_sub(1, Ordering::SeqCst);
+        self.changed.notify_one();
+        match Arc::try_unwrap(browser) {
+            Ok(browser) => browser.kill().await,
+            Err(browser) => log::warn!("pool browser id: {} still in use", browser.id),
+        }
+    }
+
+    // evict the idle browsers that crashed or stopped answering
+    async fn check_idle(&self) {
+        let idle = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.active == 0)
+            .map(|e| e.browser.clone())
+            .collect::<Vec<_>>();
+        for browser in idle {
+            if browser.is_healthy().await {
+                continue;
+            }
+            log::warn!("pool evict unhealthy id: {}", browser.id);
+            let evicted = {
+                let mut entries = self.entries.lock().unwrap();
+                entries
+                    .iter()
+                    .position(|e| e.browser.id == browser.id && e.active == 0)
+                    .map(|index| entries.remove(index))
+            };
+            drop(browser);
+            if let Some(entry) = evicted {
+                self.discard(entry.browser).await;
+            }
+        }
+    }
+
+    /// Keep `min` browsers available and healthy, runs forever. The others
+    /// are launched on demand, up to `max`
+    pub async fn maintain(self: Arc<Self>) {
+        let mut checked = Instant::now();
+        loop {
+            if checked.elapsed() >= HEALTH_INTERVAL {
+                self.check_idle().await;
+                checked = Instant::now();
+            }
+            while self.available() < self.options.min && self.reserve() {
+                match self.launch_reserved().await {
+                    Some(browser) => self.entries.lock().unwrap().push(PoolEntry {
+                        browser: Arc::new(browser),
+                        active: 0,
+                        uses: 0,
+                    }),
+                    None => break,
+                }
+            }
+            tokio::select! {
+                _ = self.changed.notified() => {}
+                _ = tokio::time::sleep(HEALTH_INTERVAL) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::session::Session;
+use crate::{Error, ErrorCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// `--queue-size` and `--queue-timeout`
+#[derive(Debug, Clone, Copy)]
+pub struct QueueOptions {
+    // requests waiting for a session, 0 answers 429 at once
+    pub max_len: usize,
+    pub wait_timeout: Duration,
+}
+
+impl Default for QueueOptions {
+    fn default() -> Self {
+        Self {
+            max_len: 0,
+            wait_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Waiters of a lower class are admitted first, FIFO within a class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Priority {
+    // headless websocket sessions, a client is waiting on the other side
+    Interactive,
+    // content renders and downloads
+    #[cfg(feature = "content")]
+    Batch,
+}
+
+struct Waiter {
+    priority: Priority,
+    seq: u64,
+    enqueued_at: Instant,
+    tx: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct QueueStats {
+    // admitted at once or after waiting
+    pub admitted: u64,
+    pub queued: u64,
+    pub timed_out: u64,
+    // the queue was full
+    pub rejected: u64,
+    pub total_wait_ms: u64,
+    pub max_wait_ms: u64,
+}
+
+#[derive(Default)]
+struct QueueState {
+    waiters: Vec<Waiter>,
+    next_seq: u64,
+    // admitted, not registered as a session yet
+    pending: usize,
+    waited: u64,
+    stats: QueueStats,
+}
+
+/// Admits the new sessions while the server is under max_sessions, the others wait in line
+pub struct SessionQueue {
+    options: QueueOptions,
+    max_sessions: usize,
+    sessions: Arc<Mutex<Vec<Session>>>,
+    state: Mutex<QueueState>,
+}
+
+/// A place under max_sessions, held until the session is registered or given up
+#[derive(Debug)]
+pub(crate) struct Admission {
+    queue: Arc<SessionQueue>,
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.pending -= 1;
+        self.queue.wake_locked(&mut state);
+    }
+}
+
+impl std::fmt::Debug for SessionQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionQueue")
+            .field("options", &self.options)
+            .field("max_sessions", &self.max_sessions)
+            .finish()
+    }
+}
+
+// the wait of a request, given up when the request is dropped
+struct Waiting<'a> {
+    queue: &'a Arc<SessionQueue>,
+    seq: u64,
+    done: bool,
+}
+
+impl Waiting<'_> {
+    /// Leave the line, true if a place was granted meanwhile
+    fn leave(&mut self) -> bool {
+        self.done = true;
+        let mut state = self.queue.state.lock().unwrap();
+        match state.waiters.iter().position(|w| w.seq == self.seq) {
+            Some(index) => {
+                state.waiters.remove(index);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if !self.done && self.leave() {
+            drop(Admission {
+                queue: self.queue.clone(),
+            });
+        }
+    }
+}
+
+impl SessionQueue {
+    pub fn new(
+        sessions: Arc<Mutex<Vec<Session>>>,
+        max_sessions: usize,
+        options: QueueOptions,
+    ) -> Self {
+        Self {
+            options,
+            max_sessions,
+            sessions,
+            state: Mutex::new(QueueState::default()),
+        }
+    }
+
+    pub fn options(&self) -> QueueOptions {
+        self.options
+    }
+
+    /// Admitted requests that are not a session yet
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().pending
+    }
+
+    // the sessions lock is always taken after the queue one
+    fn has_capacity(&self, state: &QueueState) -> bool {
+        self.max_sessions == 0
+            || self.sessions.lock().unwrap().len() + state.pending < self.max_sessions
+    }
+
+    fn wake_locked(&self, state: &mut QueueState) {
+        // the requests dropped while waiting
+        state.waiters.retain(|w| !w.tx.is_closed());
+        while !state.waiters.is_empty() && self.has_capacity(state) {
+            let index = (0..state.waiters.len())
+                .min_by_key(|&i| (state.waiters[i].priority, state.waiters[i].seq))
+                .unwrap_or_default();
+            let waiter = state.waiters.remove(index);
+            state.pending += 1;
+            if waiter.tx.send(()).is_err() {
+                state.pending -= 1;
+                continue;
+            }
+            let wait_ms = waiter.enqueued_at.elapsed().as_millis() as u64;
+            state.waited += 1;
+            state.stats.admitted += 1;
+            state.stats.total_wait_ms += wait_ms;
+            state.stats.max_wait_ms = state.stats.max_wait_ms.max(wait_ms);
+        }
+    }
+
+    /// Admit the waiters a closed session made room for
+    pub fn wake(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.wake_locked(&mut state);
+    }
+
+    /// Wait for a place under max_sessions, 429 when the queue is full or the wait times out
+    pub(crate) async fn admit(self: &Arc<Self>, priority: Priority) -> Result<Admission, Error> {
+        let (seq, mut rx) = {
+            let mut state = self.state.lock().unwrap();
+            state.waiters.retain(|w| !w.tx.is_closed());
+            let ahead = state.waiters.iter().any(|w| w.priority <= priority);
+            if !ahead && self.has_capacity(&state) {
+                state.pending += 1;
+                state.stats.admitted += 1;
+                return Ok(Admission {
+                    queue: self.clone(),
+                });
+            }
+            if state.waiters.len() >= self.options.max_len {
+                state.stats.rejected += 1;
+                let message = match self.options.max_len {
+                    0 => "too many sessions".to_string(),
+                    max_len => format!("too many sessions, {} requests are queued", max_len),
+                };
+                return Err(Error::from_code(ErrorCode::TooManySessions, &message));
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.stats.queued += 1;
+            state.waiters.push(Waiter {
+                priority,
+                seq,
+                enqueued_at: Instant::now(),
+                tx,
+            });
+            (seq, rx)
+        };
+        log::debug!("queued seq: {} priority: {:?}", seq, priority);
+
+        let mut waiting = Waiting {
+            queue: self,
+            seq,
+            done: false,
+        };
+        // the receiver outlives the wait, a closed one is a dropped request
+        tokio::time::timeout(self.options.wait_timeout, &mut rx)
+            .await
+            .ok();
+        // granted, maybe right as the wait timed out
+        if waiting.leave() {
+            return Ok(Admission {
+                queue: self.clone(),
+            });
+        }
+        self.state.lock().unwrap().stats.timed_out += 1;
+        Err(Error::from_code(
+            ErrorCode::TooManySessions,
+            &format!(
+                "too many sessions, waited {}ms in the queue",
+                self.options.wait_timeout.as_millis()
+            ),
+        ))
+    }
+
+    pub fn stats(&self) -> Value {
+        let mut state = self.state.lock().unwrap();
+        state.waiters.retain(|w| !w.tx.is_closed());
+        let waiting = state
+            .waiters
+            .iter()
+            .map(|w| json!({ "priority": w.priority, "wait_ms": w.enqueued_at.elapsed().as_millis() as u64 }))
+            .collect::<Vec<_>>();
+        let avg_wait_ms = match state.waited {
+            0 => 0,
+            waited => state.stats.total_wait_ms / waited,
+        };
+        json!({
+            "length": state.waiters.len(),
+            "max_length": self.options.max_len,
+            "wait_timeout": self.options.wait_timeout.as_millis() as u64,
+            "pending": state.pending,
+            "avg_wait_ms": avg_wait_ms,
+            "stats": state.stats,
+            "waiting": waiting,
+        })
+    }
+}
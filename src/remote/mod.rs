@@ -2,8 +2,8 @@ use std::{fs::remove_file, time::Duration};
 
 use self::x11vnc::{create_x11_session, X11SessionOption};
 use crate::{
-    session::{kill_session, SessionGuard},
-    StateRef,
+    session::{kill_session, ExpiryPolicy, SessionGuard},
+    ErrorCode, StateRef,
 };
 use axum::{
     extract::{Path, Query, State, WebSocketUpgrade},
@@ -161,8 +161,8 @@ pub(crate) async fn edit_remote(
     let data_root = std::path::Path::new(&state.data_root);
     let remote_dir = data_root.join(&remote_id);
     if !remote_dir.exists() {
-        return Err(crate::Error::new(
-            StatusCode::BAD_GATEWAY,
+        return Err(crate::Error::from_code(
+            ErrorCode::NotFound,
             "remote not exists",
         ));
     }
@@ -203,8 +203,8 @@ pub(crate) async fn connect_remote(
     let data_root = std::path::Path::new(&state.data_root);
     let remote_dir = data_root.join(&remote_id);
     if !remote_dir.exists() {
-        return Err(crate::Error::new(
-            StatusCode::BAD_GATEWAY,
+        return Err(crate::Error::from_code(
+            ErrorCode::NotFound,
             "remote not exists",
         ));
     }
@@ -306,8 +306,8 @@ pub(crate) async fn stop_remote(
     let data_root = std::path::Path::new(&state.data_root);
     let remote_dir = data_root.join(&remote_id);
     if !remote_dir.exists() {
-        return Err(crate::Error::new(
-            StatusCode::BAD_GATEWAY,
+        return Err(crate::Error::from_code(
+            ErrorCode::NotFound,
             "remote not exists",
         ));
     }
@@ -323,8 +323,8 @@ pub(crate) async fn start_remote(
     let data_root = std::path::Path::new(&state.data_root);
     let remote_dir = data_root.join(&remote_id);
     if !remote_dir.exists() {
-        return Err(crate::Error::new(
-            StatusCode::BAD_GATEWAY,
+        return Err(crate::Error::from_code(
+            ErrorCode::NotFound,
             "remote not exists",
         ));
     }
@@ -376,7 +376,12 @@ pub(crate) async fn start_remote(
     };
 
     let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
-    let session = create_x11_session(opt, shutdown_tx).await?;
+    let mut session = create_x11_session(opt, shutdown_tx).await?;
+    // a remote browser keeps running without a viewer
+    session.expiry = ExpiryPolicy {
+        idle_timeout: None,
+        ..state.expiry
+    };
 
     tokio::spawn(async move {
         let guard = SessionGuard::new(state, session);
@@ -396,8 +401,8 @@ pub(crate) async fn remove_remote(
     let data_root = std::path::Path::new(&state.data_root);
     let remote_dir = data_root.join(&remote_id);
     if !remote_dir.exists() {
-        return Err(crate::Error::new(
-            StatusCode::BAD_GATEWAY,
+        return Err(crate::Error::from_code(
+            ErrorCode::NotFound,
             "remote not exists",
         ));
     }
@@ -418,7 +423,7 @@ pub(crate) async fn screen_remote_screen(
         .unwrap()
         .iter()
         .find(|s| s.id == session_id)
-        .ok_or_else(|| crate::Error::new(StatusCode::BAD_GATEWAY, "session not found"))?
+        .ok_or_else(|| crate::Error::from_code(ErrorCode::SessionNotFound, "session not found"))?
         .remote_handler
         .as_ref()
         .ok_or_else(|| crate::Error::new(StatusCode::BAD_GATEWAY, "session not start"))?
@@ -256,9 +256,14 @@ pub(super) async fn create_x11_session(
         shutdown_tx: RefCell::new(Some(shutdown_tx)),
         browser: RefCell::new(None),
         headless_handler: RefCell::new(None),
+        expiry: Default::default(),
+        expired: RefCell::new(None),
+        admission: None,
         created_at: SystemTime::now(),
         updated_at: RefCell::new(SystemTime::now()),
         endpoint: format!("vnc://127.0.0.1:{}", x11vnc_port),
+        #[cfg(feature = "headless")]
+        context: None,
         remote_handler: Some(remote_handler),
     };
     Ok(session)
@@ -1,20 +1,30 @@
-use crate::{devices::Device, StateRef};
+use crate::{
+    devices::{get_device, Device},
+    proxy::Proxy,
+    queue::Admission,
+    validate::FieldErrors,
+    ErrorCode, StateRef,
+};
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
     response::Response,
     Json,
 };
-use chromiumoxide::{Browser, Handler};
-use serde::Deserialize;
+use chromiumoxide::{cdp::browser_protocol::browser::BrowserContextId, Browser, Handler};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::{
     cell::RefCell,
     io,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::{fs::read_to_string, sync::oneshot};
 
+// how often the sessions are checked for expiry, and the expired user data dirs removed
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+const CLEANUP_DIR_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone)]
 pub(crate) struct SessionOption {
     pub uuid: Option<String>,
@@ -22,6 +32,12 @@ pub(crate) struct SessionOption {
     pub cleanup: bool,
     pub enable_cache: bool,
     pub userdatadir_expire: Option<u64>,
+    // browser ui and Accept-Language, chrome picks the system one if None
+    pub lang: Option<String>,
+    pub proxy: Option<Proxy>,
+    // milliseconds, shorten the server --max-lifetime and --idle-timeout
+    pub max_lifetime: Option<u64>,
+    pub idle_timeout: Option<u64>,
 }
 
 impl Default for SessionOption {
@@ -32,10 +48,78 @@ impl Default for SessionOption {
             cleanup: true,
             enable_cache: false,
             userdatadir_expire: None,
+            lang: None,
+            proxy: None,
+            max_lifetime: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+/// Why a session was shut down by the background cleanup
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ExpiryReason {
+    // open longer than its max_lifetime
+    MaxLifetime,
+    // no websocket traffic for its idle_timeout
+    IdleTimeout,
+}
+
+impl std::fmt::Display for ExpiryReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExpiryReason::MaxLifetime => write!(f, "max_lifetime"),
+            ExpiryReason::IdleTimeout => write!(f, "idle_timeout"),
         }
     }
 }
 
+/// `--max-lifetime` and `--idle-timeout`, None never expires
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExpiryPolicy {
+    pub max_lifetime: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl ExpiryPolicy {
+    /// The server policy with the session overrides, which can only make it shorter
+    pub(crate) fn with_overrides(&self, opt: &SessionOption) -> Self {
+        let shorter = |server: Option<Duration>, session: Option<u64>| match (
+            server,
+            session.map(Duration::from_millis),
+        ) {
+            (Some(server), Some(session)) => Some(server.min(session)),
+            (server, session) => server.or(session),
+        };
+        Self {
+            max_lifetime: shorter(self.max_lifetime, opt.max_lifetime),
+            idle_timeout: shorter(self.idle_timeout, opt.idle_timeout),
+        }
+    }
+
+    pub(crate) fn expired(
+        &self,
+        created_at: SystemTime,
+        updated_at: SystemTime,
+    ) -> Option<ExpiryReason> {
+        let elapsed = |since: SystemTime| since.elapsed().unwrap_or_default();
+        if self
+            .max_lifetime
+            .is_some_and(|max| elapsed(created_at) > max)
+        {
+            return Some(ExpiryReason::MaxLifetime);
+        }
+        if self
+            .idle_timeout
+            .is_some_and(|idle| elapsed(updated_at) > idle)
+        {
+            return Some(ExpiryReason::IdleTimeout);
+        }
+        None
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum SessionType {
     #[cfg(feature = "headless")]
@@ -55,9 +139,17 @@ pub(crate) struct Session {
     pub(crate) created_at: SystemTime,
     pub(crate) updated_at: RefCell<SystemTime>,
     pub(crate) endpoint: String, // ws:// or vnc://
+    // the browser context of a render in a shared browser, None owns the browser
+    #[cfg(feature = "headless")]
+    pub(crate) context: Option<BrowserContextId>,
     pub(crate) shutdown_tx: RefCell<Option<oneshot::Sender<()>>>,
     pub(crate) browser: RefCell<Option<Browser>>,
     pub(crate) headless_handler: RefCell<Option<Handler>>,
+    pub(crate) expiry: ExpiryPolicy,
+    // set once the session is shut down by the background cleanup
+    pub(crate) expired: RefCell<Option<ExpiryReason>>,
+    // the place under max_sessions until the session is registered
+    pub(crate) admission: Option<Admission>,
     #[cfg(feature = "remote")]
     pub(crate) remote_handler: Option<crate::remote::RemoteHandler>,
 }
@@ -66,17 +158,90 @@ impl Session {
     pub fn touch_updatedat(&self) {
         *self.updated_at.borrow_mut() = SystemTime::now();
     }
+
+    /// Shut the session down once its policy expires it,
+    /// false when it leaves the list without its owner, which was told to stop or can't be
+    pub(crate) fn expire(&self) -> bool {
+        if self.expired.borrow().is_none() {
+            let Some(reason) = self
+                .expiry
+                .expired(self.created_at, *self.updated_at.borrow())
+            else {
+                return true;
+            };
+            log::info!("session id: {} expired: {}", self.id, reason);
+            *self.expired.borrow_mut() = Some(reason);
+        }
+        // the owner of the session stops the browser and removes it
+        self.shutdown_tx.take().is_some()
+    }
 }
 
 #[derive(Deserialize)]
 pub struct CreateSessionParams {
     #[serde(rename = "device")]
     pub(crate) emulating_device: Option<String>,
+    pub(crate) lang: Option<String>,
+
+    // offline, slow-3g, fast-3g, 4g or custom with download_kbps, upload_kbps and latency
+    pub(crate) throttle: Option<String>,
+    pub(crate) download_kbps: Option<f64>,
+    pub(crate) upload_kbps: Option<f64>,
+    pub(crate) latency: Option<f64>,
+    pub(crate) cpu_slowdown: Option<f64>,
+
+    // http(s)://user:password@host:port, socks5://host:port or direct to skip the proxy pool
+    pub(crate) proxy: Option<String>,
+    // the same proxy of the pool for the same proxy_session
+    pub(crate) proxy_session: Option<String>,
+
+    // selects the --url-policy of the key
+    pub(crate) api_key: Option<String>,
+
+    // milliseconds, shorter than the server --max-lifetime and --idle-timeout
+    pub(crate) max_lifetime: Option<u64>,
+    pub(crate) idle_timeout: Option<u64>,
+}
+
+impl CreateSessionParams {
+    /// Reject an unknown device or a malformed lang before a browser is launched
+    pub(crate) fn validate(&self) -> Result<(), crate::Error> {
+        let mut errors = FieldErrors::default();
+        if let Some(device) = &self.emulating_device {
+            errors.check("device", get_device(device).is_some(), "unknown device");
+        }
+        if let Some(lang) = &self.lang {
+            errors.check(
+                "lang",
+                !lang.is_empty()
+                    && lang
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                "must be a language tag, e.g. de-DE",
+            );
+        }
+        errors.check(
+            "max_lifetime",
+            self.max_lifetime != Some(0),
+            "must be positive",
+        );
+        errors.check(
+            "idle_timeout",
+            self.idle_timeout != Some(0),
+            "must be positive",
+        );
+        errors.into_result()
+    }
 }
 
 impl From<&CreateSessionParams> for SessionOption {
-    fn from(_params: &CreateSessionParams) -> Self {
-        Self::default()
+    fn from(params: &CreateSessionParams) -> Self {
+        Self {
+            lang: params.lang.clone(),
+            max_lifetime: params.max_lifetime,
+            idle_timeout: params.idle_timeout,
+            ..Self::default()
+        }
     }
 }
 
@@ -111,9 +276,11 @@ pub(crate) struct SessionGuard {
 }
 
 impl SessionGuard {
-    pub(crate) fn new(state: StateRef, session: Session) -> Self {
+    pub(crate) fn new(state: StateRef, mut session: Session) -> Self {
         let id = session.id.clone();
+        let admission = session.admission.take();
         state.sessions.lock().unwrap().push(session);
+        drop(admission);
         Self { state, id }
     }
 }
@@ -125,6 +292,7 @@ impl Drop for SessionGuard {
             .lock()
             .unwrap()
             .retain(|s| s.id != self.id);
+        self.state.queue.wake();
     }
 }
 
@@ -142,12 +310,27 @@ pub(crate) async fn list_session(State(state): State<StateRef>) -> Json<Value> {
                 "enable_cache": s.enable_cache,
                 "created_at": s.created_at,
                 "updated_at": s.updated_at,
+                "max_lifetime": s.expiry.max_lifetime.map(|d| d.as_millis() as u64),
+                "idle_timeout": s.expiry.idle_timeout.map(|d| d.as_millis() as u64),
+                "expired": *s.expired.borrow(),
             })
         })
         .collect::<Vec<_>>();
     Json(json!(data))
 }
 
+/// Session counts and the queue of the requests waiting for a session
+pub(crate) async fn session_stats(State(state): State<StateRef>) -> Json<Value> {
+    let queue = state.queue.stats();
+    let sessions = state.sessions.lock().unwrap().len();
+    Json(json!({
+        "sessions": sessions,
+        "max_sessions": state.max_sessions,
+        "full": state.is_full(),
+        "queue": queue,
+    }))
+}
+
 pub(crate) async fn kill_session(Path(session_id): Path<String>, State(state): State<StateRef>) {
     state
         .sessions
@@ -166,6 +349,7 @@ pub(crate) async fn killall_session(State(state): State<StateRef>) {
 
 #[derive(Deserialize)]
 pub(crate) struct ScreenSessionParams {
+    #[cfg(feature = "remote")]
     percentage: Option<i32>,
 }
 
@@ -181,7 +365,7 @@ pub(crate) async fn screen_session(
         .unwrap()
         .iter()
         .find(|s| s.id == session_id)
-        .ok_or_else(|| crate::Error::new(StatusCode::BAD_GATEWAY, "session not found"))?
+        .ok_or_else(|| crate::Error::from_code(ErrorCode::SessionNotFound, "session not found"))?
         .r#type
         .clone();
 
@@ -212,28 +396,17 @@ pub(crate) async fn handle_index_page() -> Response {
 
 pub(crate) async fn background_cleanup_task(state: StateRef) -> Result<(), io::Error> {
     log::info!("background_cleanup_task started");
+    let mut cleaned_at = Instant::now();
     loop {
-        state
-            .sessions
-            .lock()
-            .unwrap()
-            .retain(|s| match s.created_at.elapsed() {
-                Ok(elapsed) => {
-                    if elapsed.as_secs() > state.max_timeout {
-                        log::info!("session id: {} has timed out, elapsed: {:?}", s.id, elapsed);
-                        false
-                    } else {
-                        true
-                    }
-                }
-                Err(e) => {
-                    log::error!("elapsed error: {:?}", e);
-                    true
-                }
-            });
+        // an expired session already told to stop is dropped at the next round
+        state.sessions.lock().unwrap().retain(|s| s.expire());
+        state.queue.wake();
         // cleanup expired userdatadir
-        clean_expired_dir(std::path::Path::new(&state.data_root)).await?;
-        tokio::time::sleep(Duration::from_secs(60)).await;
+        if cleaned_at.elapsed() >= CLEANUP_DIR_INTERVAL {
+            clean_expired_dir(std::path::Path::new(&state.data_root)).await?;
+            cleaned_at = Instant::now();
+        }
+        tokio::time::sleep(EXPIRY_INTERVAL).await;
     }
 }
 
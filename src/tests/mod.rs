@@ -2,9 +2,24 @@ use crate::{create_router, AppState};
 use axum::Router;
 use std::sync::Arc;
 use tokio::{spawn, sync::oneshot};
+mod test_access;
+mod test_adblock;
 mod test_browser;
 mod test_client;
 mod test_content;
+mod test_download;
+mod test_egress;
+mod test_error;
+mod test_intercept;
+mod test_overlays;
+mod test_policy;
+mod test_pool;
+mod test_proxy;
+mod test_queue;
+mod test_session;
+mod test_throttle;
+mod test_upstream;
+mod test_validate;
 use tower_http::services::ServeDir;
 
 #[allow(unused)]
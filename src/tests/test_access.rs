@@ -0,0 +1,165 @@
+use crate::access::{is_internal, AccessPolicy, Cidr};
+use crate::{create_router, AppState};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_cidr() {
+    let cidr = Cidr::try_from("10.0.0.0/8").unwrap();
+    assert!(cidr.contains(&ip("10.1.2.3")));
+    assert!(cidr.contains(&ip("::ffff:10.1.2.3")));
+    assert!(!cidr.contains(&ip("11.0.0.1")));
+
+    let cidr = Cidr::try_from("fd00::/8").unwrap();
+    assert!(cidr.contains(&ip("fd00:ec2::254")));
+    assert!(!cidr.contains(&ip("fe80::1")));
+
+    assert!(Cidr::try_from("192.168.1.1")
+        .unwrap()
+        .contains(&ip("192.168.1.1")));
+    assert!(Cidr::try_from("0.0.0.0/0")
+        .unwrap()
+        .contains(&ip("8.8.8.8")));
+    assert!(Cidr::try_from("10.0.0.0/33").is_err());
+    assert!(Cidr::try_from("example.com/8").is_err());
+}
+
+#[test]
+fn test_internal_addresses() {
+    for addr in [
+        "127.0.0.1",
+        "10.0.0.1",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "::1",
+        "::",
+        "fd00:ec2::254",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+    ] {
+        assert!(is_internal(&ip(addr)), "{} is internal", addr);
+    }
+    for addr in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111"] {
+        assert!(!is_internal(&ip(addr)), "{} is public", addr);
+    }
+}
+
+#[test]
+fn test_embedded_ipv4() {
+    // nat64 64:ff9b::/96
+    assert!(is_internal(&ip("64:ff9b::a9fe:a9fe")));
+    assert!(is_internal(&ip("64:ff9b::10.0.0.1")));
+    assert!(!is_internal(&ip("64:ff9b::8.8.8.8")));
+    // 6to4 2002::/16
+    assert!(is_internal(&ip("2002:7f00:1::1")));
+    assert!(is_internal(&ip("2002:c0a8:101::")));
+    assert!(!is_internal(&ip("2002:808:808::1")));
+    // ipv4 compatible ::a.b.c.d
+    assert!(is_internal(&ip("::127.0.0.1")));
+    assert!(is_internal(&ip("::169.254.169.254")));
+    assert!(!is_internal(&ip("::8.8.8.8")));
+    // multicast
+    assert!(is_internal(&ip("ff02::1")));
+    assert!(is_internal(&ip("ff0e::1")));
+    assert!(is_internal(&ip("224.0.0.1")));
+    assert!(is_internal(&ip("64:ff9b::239.255.255.250")));
+
+    // the deny list applies to the embedded address too
+    let policy = AccessPolicy {
+        allow_private: true,
+        deny: vec![Cidr::try_from("169.254.169.254/32").unwrap()],
+        ..Default::default()
+    };
+    assert!(policy.check_ip(&ip("64:ff9b::a9fe:a9fe")).is_err());
+    assert!(policy.check_ip(&ip("2002:a9fe:a9fe::1")).is_err());
+    assert!(policy.check_ip(&ip("::169.254.169.254")).is_err());
+    assert!(policy.check_ip(&ip("64:ff9b::10.0.0.1")).is_ok());
+}
+
+#[tokio::test]
+async fn test_access_policy() {
+    let check = |policy: &AccessPolicy, url: &str| {
+        let policy = policy.clone();
+        let u = url::Url::parse(url).unwrap();
+        async move { policy.check_url(&u).await }
+    };
+
+    let policy = AccessPolicy::default();
+    assert!(check(&policy, "http://169.254.169.254/latest/meta-data/")
+        .await
+        .is_err());
+    assert!(check(&policy, "http://[::ffff:7f00:1]:8080/")
+        .await
+        .is_err());
+    assert!(check(&policy, "http://[fd00::1]/").await.is_err());
+    assert!(check(&policy, "http://api.localhost/").await.is_err());
+    assert!(check(&policy, "http://localhost/").await.is_err());
+    assert!(check(&policy, "http://8.8.8.8/").await.is_ok());
+    assert!(check(&policy, "data:text/plain,hello").await.is_ok());
+
+    let policy = AccessPolicy {
+        allow: vec![Cidr::try_from("10.1.0.0/16").unwrap()],
+        deny: vec![Cidr::try_from("8.8.8.8").unwrap()],
+        ..Default::default()
+    };
+    assert!(check(&policy, "http://10.1.2.3/").await.is_ok());
+    assert!(check(&policy, "http://10.2.0.1/").await.is_err());
+    assert!(check(&policy, "http://8.8.8.8/").await.is_err());
+
+    let policy = AccessPolicy {
+        allow_private: true,
+        deny: vec![Cidr::try_from("169.254.0.0/16").unwrap()],
+        ..Default::default()
+    };
+    assert!(!policy.is_open());
+    assert!(check(&policy, "http://127.0.0.1/").await.is_ok());
+    assert!(check(&policy, "http://169.254.169.254/").await.is_err());
+}
+
+#[tokio::test]
+async fn test_deny_internal_render() {
+    let addr = "127.0.0.1:9020";
+    let state = Arc::new(AppState::new("/tmp/browserlify_unittest".to_string(), 0));
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(state)).await });
+
+    let client = reqwest::Client::new();
+    for url in [
+        "http://169.254.169.254/latest/meta-data/",
+        "http://localhost:9020/list",
+        "http://[::1]:9020/list",
+    ] {
+        let resp = client
+            .get(format!("http://{}/text", addr))
+            .query(&[("url", url)])
+            .send()
+            .await
+            .expect("get api/text fail");
+        assert_eq!(resp.status(), 403, "{}", url);
+        let body: serde_json::Value =
+            serde_json::from_str(&resp.text().await.expect("get error fail")).unwrap();
+        assert_eq!(body["code"], "ACCESS_DENIED", "{}", url);
+    }
+
+    // a public page through a proxy of the client on an internal host
+    let resp = client
+        .get(format!("http://{}/text", addr))
+        .query(&[
+            ("url", "http://93.184.216.34/"),
+            ("proxy", "http://127.0.0.1:6379"),
+        ])
+        .send()
+        .await
+        .expect("get api/text fail");
+    assert_eq!(resp.status(), 403);
+    let body: serde_json::Value =
+        serde_json::from_str(&resp.text().await.expect("get error fail")).unwrap();
+    assert_eq!(body["code"], "ACCESS_DENIED");
+}
@@ -0,0 +1,152 @@
+use crate::adblock::{FilterList, Request};
+use chromiumoxide::cdp::browser_protocol::network::ResourceType;
+
+const FILTERS: &str = r#"[Adblock Plus 2.0]
+! Title: test list
+||ads.example.com^
+||tracker.net^$third-party
+/banner/*/img^
+|https://cdn.example.org/ad.js|
+/\/pixel\d+\.gif/
+@@||ads.example.com/allowed^
+||fonts.example.net^$font
+||video.example.net^$~media
+||popup.example.com^$popup
+example.org##.ad-banner
+"#;
+
+fn check(filters: &FilterList, url: &str, resource_type: ResourceType, source_host: &str) -> bool {
+    filters.check(&Request {
+        url,
+        resource_type: &resource_type,
+        source_host,
+    })
+}
+
+#[test]
+fn test_parse() {
+    let filters = FilterList::parse(FILTERS);
+    // the popup and element hiding rules are skipped
+    assert_eq!(filters.len(), 8);
+}
+
+#[test]
+fn test_check() {
+    let filters = FilterList::parse(FILTERS);
+    let host = "www.example.org";
+
+    assert!(check(
+        &filters,
+        "https://ads.example.com/x.js",
+        ResourceType::Script,
+        host
+    ));
+    assert!(check(
+        &filters,
+        "https://sub.ads.example.com/x.js",
+        ResourceType::Script,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://notads.example.com/x.js",
+        ResourceType::Script,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://ads.example.com.evil.org/",
+        ResourceType::Script,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://ads.example.com/allowed/1.js",
+        ResourceType::Script,
+        host
+    ));
+
+    assert!(check(
+        &filters,
+        "https://tracker.net/t.js",
+        ResourceType::Script,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://tracker.net/t.js",
+        ResourceType::Script,
+        "www.tracker.net"
+    ));
+
+    assert!(check(
+        &filters,
+        "https://a.com/banner/123/img?x=1",
+        ResourceType::Image,
+        host
+    ));
+    assert!(check(
+        &filters,
+        "https://a.com/banner/1/img",
+        ResourceType::Image,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://a.com/banner/1/imgs",
+        ResourceType::Image,
+        host
+    ));
+
+    assert!(check(
+        &filters,
+        "https://cdn.example.org/ad.js",
+        ResourceType::Script,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://cdn.example.org/ad.js?v=1",
+        ResourceType::Script,
+        host
+    ));
+
+    assert!(check(
+        &filters,
+        "https://a.com/PIXEL12.gif",
+        ResourceType::Image,
+        host
+    ));
+
+    assert!(check(
+        &filters,
+        "https://fonts.example.net/a.woff",
+        ResourceType::Font,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://fonts.example.net/a.css",
+        ResourceType::Stylesheet,
+        host
+    ));
+    assert!(check(
+        &filters,
+        "https://video.example.net/a.js",
+        ResourceType::Script,
+        host
+    ));
+    assert!(!check(
+        &filters,
+        "https://video.example.net/a.mp4",
+        ResourceType::Media,
+        host
+    ));
+
+    assert!(!check(
+        &filters,
+        "https://popup.example.com/",
+        ResourceType::Document,
+        host
+    ));
+}
@@ -1,4 +1,7 @@
+use crate::pool::{BrowserPool, PoolOptions};
 use crate::tests::{serve_test_http_server, serve_test_server};
+use crate::{create_router, AppState};
+use std::sync::Arc;
 
 #[tokio::test]
 async fn test_render_pdf() {
@@ -161,3 +164,723 @@ async fn test_wait_load() {
     drop(shutdown_tx);
     drop(http_shutdown_tx);
 }
+
+#[tokio::test]
+async fn test_request_headers() {
+    use axum::{http::HeaderMap, routing::get, Router};
+
+    let addr = "127.0.0.1:9009";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    // echo the request headers back as the page body
+    let http_addr = crate::tests::open_port();
+    let listener = tokio::net::TcpListener::bind(&http_addr).await.unwrap();
+    tokio::spawn(async move {
+        let app = Router::new().route(
+            "/",
+            get(|headers: HeaderMap| async move {
+                let value = |name: &str| {
+                    headers
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                axum::response::Html(format!(
+                    "<pre>x-token={} cookie={} ua={}</pre>",
+                    value("x-token"),
+                    value("cookie"),
+                    value("user-agent")
+                ))
+            }),
+        );
+        axum::serve(listener, app).await.unwrap();
+    });
+    let target = format!("http://{http_addr}/");
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": target,
+                "basic_auth": "missing-colon",
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": target,
+                "headers": {"X-Token": "secret"},
+                "cookies": [{"name": "session", "value": "abc"}],
+                "user_agent": "browserlify_test",
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("x-token=secret"));
+    assert!(body.contains("cookie=session=abc"));
+    assert!(body.contains("ua=browserlify_test"));
+
+    drop(shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_inject_script() {
+    let addr = "127.0.0.1:9010";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let (http_shutdown_tx, http_shutdown_rx) = tokio::sync::oneshot::channel();
+    let http_addr = serve_test_http_server(http_shutdown_rx)
+        .await
+        .expect("serve http fail");
+    let target = format!("http://{http_addr}/?from=unittest");
+
+    let script_before = urlencoding::encode("window.__flag = 'before';");
+    let script_after = urlencoding::encode(
+        "new Promise(r => setTimeout(() => { document.body.innerHTML = '<p>' + window.__flag + '</p><p id=\"hidden\">after</p>'; r(); }, 100))",
+    );
+    let style = urlencoding::encode("#hidden { display: none }");
+    let url = format!(
+        "http://{}/text?url={}&script_before={script_before}&script_after={script_after}&style={style}",
+        addr,
+        urlencoding::encode(&target)
+    );
+
+    let resp = reqwest::get(&url).await.expect("get api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("before"));
+    assert!(!body.contains("after"));
+
+    drop(shutdown_tx);
+    drop(http_shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_actions() {
+    let addr = "127.0.0.1:9011";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let (http_shutdown_tx, http_shutdown_rx) = tokio::sync::oneshot::channel();
+    let http_addr = serve_test_http_server(http_shutdown_rx)
+        .await
+        .expect("serve http fail");
+    let target = format!("http://{http_addr}/?from=unittest");
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": target,
+                "actions": [
+                    {"type": "evaluate", "script": "document.body.innerHTML = '<input id=\"name\"><button id=\"go\" onclick=\"document.body.innerHTML = `<p id=done>hello ${name.value}</p>`\">go</button>'"},
+                    {"type": "type", "selector": "#name", "text": "browserlify"},
+                    {"type": "click", "selector": "#go"},
+                    {"type": "wait_for_selector", "selector": "#done", "timeout": 1000},
+                    {"type": "click", "selector": "#missing", "timeout": 200, "optional": true},
+                ],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("hello browserlify"));
+
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": target,
+                "actions": [{"type": "wait_for_selector", "selector": "#missing", "timeout": 200}],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(!resp.status().is_success());
+    let body = resp.text().await.expect("get error fail");
+    assert!(body.contains("action #0 wait_for_selector failed"));
+
+    drop(shutdown_tx);
+    drop(http_shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_block_resources() {
+    let addr = "127.0.0.1:9012";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let (http_shutdown_tx, http_shutdown_rx) = tokio::sync::oneshot::channel();
+    let http_addr = serve_test_http_server(http_shutdown_rx)
+        .await
+        .expect("serve http fail");
+    let target = format!("http://{http_addr}/?from=unittest");
+
+    let url = format!(
+        "http://{}/html?block_resources=image,font&url={}",
+        addr,
+        urlencoding::encode(&target)
+    );
+    let resp = reqwest::get(&url).await.expect("get api/html fail");
+    assert!(resp.status().is_success());
+    assert!(resp.headers().contains_key("x-blocked-requests"));
+
+    let url = format!(
+        "http://{}/html?block_resources=unknown&url={}",
+        addr,
+        urlencoding::encode(&target)
+    );
+    let resp = reqwest::get(&url).await.expect("get api/html fail");
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/html", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": target,
+                "block_urls": ["*/bootstrap.min.css", "/custom\\.css$/"],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/html fail");
+    assert!(resp.status().is_success());
+    assert_eq!(resp.headers()["x-blocked-requests"], "2");
+
+    drop(shutdown_tx);
+    drop(http_shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_mocks_and_rewrite() {
+    let addr = "127.0.0.1:9013";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let (http_shutdown_tx, http_shutdown_rx) = tokio::sync::oneshot::channel();
+    let http_addr = serve_test_http_server(http_shutdown_rx)
+        .await
+        .expect("serve http fail");
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://mocked.invalid/",
+                "mocks": [{
+                    "url": "http://mocked.invalid/",
+                    "headers": {"Content-Type": "text/html"},
+                    "body": "<p>mocked page</p>",
+                }],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("mocked page"));
+
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://prod.invalid/?from=unittest",
+                "rewrite": [{"from": "http://prod.invalid", "to": format!("http://{http_addr}")}],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("MADE WITH CARE IN HANGZHOU"));
+
+    drop(shutdown_tx);
+    drop(http_shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_emulate_media() {
+    let addr = "127.0.0.1:9014";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let url = format!(
+        "http://{}/text?color_scheme=blue&url={}",
+        addr,
+        urlencoding::encode("http://mocked.invalid/")
+    );
+    let resp = reqwest::get(&url).await.expect("get api/text fail");
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://mocked.invalid/",
+                "color_scheme": "dark",
+                "media": "print",
+                "mocks": [{
+                    "url": "http://mocked.invalid/",
+                    "headers": {"Content-Type": "text/html"},
+                    "body": "<script>document.write([matchMedia('(prefers-color-scheme: dark)').matches, matchMedia('print').matches].join(','))</script>",
+                }],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("true,true"));
+
+    drop(shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_emulate_locale() {
+    let addr = "127.0.0.1:9015";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let url = format!(
+        "http://{}/text?geolocation=100,0&url={}",
+        addr,
+        urlencoding::encode("http://mocked.invalid/")
+    );
+    let resp = reqwest::get(&url).await.expect("get api/text fail");
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://mocked.invalid/",
+                "timezone": "Asia/Tokyo",
+                "locale": "de-DE",
+                "geolocation": "35.68,139.69,10",
+                "accept_language": "de-DE,de;q=0.9",
+                "script_after": "new Promise(r => navigator.geolocation.getCurrentPosition(p => { document.body.innerText += ` lat=${p.coords.latitude}`; r(); }, () => r()))",
+                "mocks": [{
+                    "url": "http://mocked.invalid/",
+                    "headers": {"Content-Type": "text/html"},
+                    "body": "<body><script>document.write([Intl.DateTimeFormat().resolvedOptions().timeZone, navigator.language, (1234.5).toLocaleString()].join(' '))</script></body>",
+                }],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("Asia/Tokyo"));
+    assert!(body.contains("de-DE"));
+    assert!(body.contains("1.234,5"));
+    assert!(body.contains("lat=35.68"));
+
+    drop(shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_wait_conditions() {
+    let addr = "127.0.0.1:9016";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let page = r#"<body><div id="spinner">loading</div><div id="result" style="display:none">ready</div>
+<script>setTimeout(() => {
+    document.getElementById('spinner').remove();
+    document.getElementById('result').style.display = 'block';
+    history.pushState({}, '', '/done');
+    window.appReady = Promise.resolve(true);
+    window.dispatchEvent(new Event('app:rendered'));
+}, 300)</script></body>"#;
+
+    let client = reqwest::Client::new();
+    let st = std::time::Instant::now();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://mocked.invalid/",
+                "wait_load": 5000,
+                "wait_visible": "#result",
+                "wait_hidden": "#spinner",
+                "wait_text": "ready",
+                "wait_url": "/done$",
+                "wait_event": "app:rendered",
+                "wait_function": "window.appReady",
+                "mocks": [{
+                    "url": "http://mocked.invalid/",
+                    "headers": {"Content-Type": "text/html"},
+                    "body": page,
+                }],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("ready"));
+    assert!(!body.contains("loading"));
+    assert!(st.elapsed().as_millis() < 5000);
+
+    drop(shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_dismiss_overlays() {
+    let addr = "127.0.0.1:9017";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let page = r#"<body style="overflow:hidden"><p>article</p>
+<div id="onetrust-banner-sdk">we use cookies <button id="onetrust-reject-all-handler">reject</button></div>
+<div class="newsletter-modal">subscribe</div>
+<header style="position:fixed;top:0">menu</header></body>"#;
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://mocked.invalid/",
+                "dismiss_cookie_banners": true,
+                "remove_selectors": ".newsletter-modal",
+                "unfix_position": true,
+                "mocks": [{
+                    "url": "http://mocked.invalid/",
+                    "headers": {"Content-Type": "text/html"},
+                    "body": page,
+                }],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    assert_eq!(
+        resp.headers()
+            .get("X-Dismissed-Banners")
+            .and_then(|v| v.to_str().ok()),
+        Some("OneTrust")
+    );
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("article"));
+    assert!(!body.contains("cookies"));
+    assert!(!body.contains("subscribe"));
+
+    drop(shutdown_tx);
+}
+
+// answers every request itself, after a 407 challenge
+async fn serve_auth_proxy(addr: &str, credentials: &'static str) {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let expected = format!(
+        "proxy-authorization: basic {}",
+        STANDARD.encode(credentials)
+    );
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let expected = expected.to_lowercase();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 8192];
+                let n = stream.read(&mut buf).await.unwrap_or_default();
+                let head = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let resp = match head.lines().any(|l| l.trim() == expected) {
+                    true => "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 16\r\nConnection: close\r\n\r\n<p>via proxy</p>",
+                    false => "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"test\"\r\nContent-Length: 0\r\n\r\n",
+                };
+                stream.write_all(resp.as_bytes()).await.ok();
+            });
+        }
+    });
+}
+
+#[tokio::test]
+async fn test_proxy() {
+    let addr = "127.0.0.1:9018";
+    let proxy_addr = "127.0.0.1:9019";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+    serve_auth_proxy(proxy_addr, "user:secret").await;
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .get(format!("http://{}/text", addr))
+        .query(&[
+            ("url", "http://proxied.invalid/"),
+            ("proxy", "ftp://nowhere"),
+        ])
+        .send()
+        .await
+        .expect("get api/text fail");
+    assert_eq!(resp.status(), 400);
+
+    let resp = client
+        .get(format!("http://{}/text", addr))
+        .query(&[
+            ("url", "http://proxied.invalid/".to_string()),
+            ("proxy", format!("http://user:secret@{}", proxy_addr)),
+        ])
+        .send()
+        .await
+        .expect("get api/text fail");
+    assert!(resp.status().is_success());
+    let body = resp.text().await.expect("get text fail");
+    assert!(body.contains("via proxy"));
+
+    drop(shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_download() {
+    let addr = "127.0.0.1:9022";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/download", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://mocked.invalid/",
+                "actions": [{"type": "click", "selector": "#export"}],
+                "mocks": [{
+                    "url": "http://mocked.invalid/",
+                    "headers": {"Content-Type": "text/html"},
+                    "body": "<a id=\"export\" href=\"/export\">export</a>",
+                }, {
+                    "url": "http://mocked.invalid/export",
+                    "headers": {
+                        "Content-Type": "application/x-report",
+                        "Content-Disposition": "attachment; filename=\"report.csv\"",
+                    },
+                    "body": "id,name\n1,browserlify\n",
+                }],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/download fail");
+    assert!(resp.status().is_success());
+    assert_eq!(
+        resp.headers()
+            .get("Content-Type")
+            .and_then(|v| v.to_str().ok()),
+        Some("application/x-report")
+    );
+    assert!(resp
+        .headers()
+        .get("Content-Disposition")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("report.csv")));
+    let body = resp.text().await.expect("get download fail");
+    assert_eq!(body, "id,name\n1,browserlify\n");
+
+    // a page that downloads nothing
+    let resp = client
+        .post(format!("http://{}/download", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://mocked.invalid/",
+                "mocks": [{
+                    "url": "http://mocked.invalid/",
+                    "headers": {"Content-Type": "text/html"},
+                    "body": "<p>nothing to export</p>",
+                }],
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/download fail");
+    assert_eq!(resp.status(), 422);
+    let body: serde_json::Value =
+        serde_json::from_str(&resp.text().await.expect("get error fail")).unwrap();
+    assert_eq!(body["code"], "NO_DOWNLOAD");
+
+    drop(shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_upstream_status() {
+    let addr = "127.0.0.1:9023";
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    serve_test_server(shutdown_rx, addr.to_string()).await;
+
+    let render = |fail_on_status: bool| {
+        serde_json::json!({
+            "url": "http://mocked.invalid/missing",
+            "fail_on_status": fail_on_status,
+            "upstream_headers": "ETag",
+            "mocks": [{
+                "url": "http://mocked.invalid/missing",
+                "status": 404,
+                "headers": {"Content-Type": "text/html", "ETag": "v1"},
+                "body": "<p>not found</p>",
+            }],
+        })
+        .to_string()
+    };
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(render(false))
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert!(resp.status().is_success());
+    let header = |name: &str| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    assert_eq!(header("X-Upstream-Status").as_deref(), Some("404"));
+    assert_eq!(
+        header("X-Final-Url").as_deref(),
+        Some("http://mocked.invalid/missing")
+    );
+    assert_eq!(header("X-Upstream-ETag").as_deref(), Some("v1"));
+
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(render(true))
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert_eq!(resp.status(), 502);
+
+    let resp = client
+        .get(format!("http://{}/text", addr))
+        .query(&[("url", "http://unresolvable.invalid/")])
+        .send()
+        .await
+        .expect("get api/text fail");
+    assert_eq!(resp.status(), 502);
+    let body: serde_json::Value =
+        serde_json::from_str(&resp.text().await.expect("get error fail")).unwrap();
+    assert_eq!(body["code"], "DNS_ERROR");
+    assert!(body["session_id"].is_string());
+
+    drop(shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_browser_pool() {
+    let addr = "127.0.0.1:9026";
+    let pool = Arc::new(BrowserPool::new(
+        PoolOptions {
+            min: 1,
+            max: 1,
+            max_uses: 2,
+            contexts: 2,
+        },
+        "/tmp/browserlify_unittest",
+    ));
+    tokio::spawn(pool.clone().maintain());
+    let state =
+        Arc::new(AppState::new("/tmp/browserlify_unittest".to_string(), 0).with_pool(pool.clone()));
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(state)).await });
+
+    for _ in 0..50 {
+        if pool.available() == 1 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(pool.available(), 1);
+
+    let client = reqwest::Client::new();
+    let render = |text: &str| {
+        serde_json::json!({
+            "url": "http://mocked.invalid/",
+            "mocks": [{
+                "url": "http://mocked.invalid/",
+                "headers": {"Content-Type": "text/html"},
+                "body": format!("<p>{}</p>", text),
+            }],
+        })
+        .to_string()
+    };
+    // both renders share the browser, each one in its own context
+    let renders = ["first", "second"].map(|text| {
+        client
+            .post(format!("http://{}/text", addr))
+            .header("Content-Type", "application/json")
+            .body(render(text))
+            .send()
+    });
+    let resps = futures::future::join_all(renders).await;
+    for (resp, text) in resps.into_iter().zip(["first", "second"]) {
+        let resp = resp.expect("post api/text fail");
+        assert!(resp.status().is_success());
+        assert!(resp.text().await.expect("get text fail").contains(text));
+    }
+
+    // recycled after max_uses, a fresh browser takes its place
+    for _ in 0..50 {
+        if pool.available() == 1 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(pool.available(), 1);
+    assert_eq!(pool.launched(), 1);
+}
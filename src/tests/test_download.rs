@@ -0,0 +1,36 @@
+use crate::download::Download;
+
+#[test]
+fn test_download_headers() {
+    let download = Download {
+        path: "/tmp/browserlify/id/downloads/guid".into(),
+        url: "http://example.com/export".to_string(),
+        suggested_filename: "q3 \"report\" é.csv".to_string(),
+        mime_type: None,
+        dir: tempfile::tempdir().unwrap(),
+    };
+    assert_eq!(download.content_type(), "text/csv");
+    assert_eq!(
+        download.content_disposition(),
+        "attachment; filename=\"q3 _report_ _.csv\"; filename*=UTF-8''q3%20%22report%22%20%C3%A9.csv"
+    );
+
+    let download = Download {
+        suggested_filename: "export".to_string(),
+        ..download
+    };
+    assert_eq!(download.content_type(), "application/octet-stream");
+
+    // the type chrome saw wins over the name, unless it says nothing more
+    let download = Download {
+        mime_type: Some("application/vnd.ms-excel".to_string()),
+        ..download
+    };
+    assert_eq!(download.content_type(), "application/vnd.ms-excel");
+    let download = Download {
+        suggested_filename: "export.csv".to_string(),
+        mime_type: Some("application/octet-stream".to_string()),
+        ..download
+    };
+    assert_eq!(download.content_type(), "text/csv");
+}
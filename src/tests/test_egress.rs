@@ -0,0 +1,119 @@
+use crate::access::{AccessPolicy, Cidr};
+use crate::egress::{EgressPolicy, EgressProxy};
+use crate::policy::UrlPolicy;
+use axum::{routing::get, Router};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+async fn serve_upstream() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let app = Router::new().route("/", get(|| async { "upstream ok" }));
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    addr
+}
+
+async fn get_through(egress: &EgressProxy, url: &str) -> (u16, String) {
+    let proxy = reqwest::Proxy::http(egress.proxy().server()).unwrap();
+    let client = reqwest::Client::builder().proxy(proxy).build().unwrap();
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .expect("get through egress fail");
+    (
+        resp.status().as_u16(),
+        resp.text().await.unwrap_or_default(),
+    )
+}
+
+// the status line of the CONNECT answer, then a request through the tunnel
+async fn connect_through(egress: &EgressProxy, target: &str) -> (String, String) {
+    let server = egress.proxy().server();
+    let mut stream = tokio::net::TcpStream::connect(server.trim_start_matches("http://"))
+        .await
+        .unwrap();
+    let head = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target);
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let mut buf = vec![0u8; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    let answer = String::from_utf8_lossy(&buf[..n]).to_string();
+    let status = answer.lines().next().unwrap_or_default().to_string();
+    if !status.contains(" 200 ") {
+        return (status, answer);
+    }
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        target
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut body = String::new();
+    stream.read_to_string(&mut body).await.unwrap();
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_egress_access() {
+    let upstream = serve_upstream().await;
+    let url = format!("http://{}/", upstream);
+
+    let denied = EgressProxy::start(EgressPolicy {
+        access: Some(Arc::new(AccessPolicy::default())),
+        url_policy: None,
+    })
+    .await
+    .unwrap();
+    let (status, body) = get_through(&denied, &url).await;
+    assert_eq!(status, 403);
+    assert!(body.contains("127.0.0.1"), "{}", body);
+    let (status, _) = connect_through(&denied, &upstream).await;
+    assert!(status.contains(" 403 "), "{}", status);
+    let port = upstream.rsplit_once(':').unwrap().1;
+    let (status, _) = connect_through(&denied, &format!("localhost:{}", port)).await;
+    assert!(status.contains(" 403 "), "{}", status);
+    assert_eq!(denied.denied(), 3);
+
+    let allowed = EgressProxy::start(EgressPolicy {
+        access: Some(Arc::new(AccessPolicy {
+            allow: vec![Cidr::try_from("127.0.0.1").unwrap()],
+            ..Default::default()
+        })),
+        url_policy: None,
+    })
+    .await
+    .unwrap();
+    let (status, body) = get_through(&allowed, &url).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, "upstream ok");
+    let (status, body) = connect_through(&allowed, &upstream).await;
+    assert!(status.contains(" 200 "), "{}", status);
+    assert!(body.ends_with("upstream ok"), "{}", body);
+    assert_eq!(allowed.denied(), 0);
+}
+
+#[tokio::test]
+async fn test_egress_url_policy() {
+    let upstream = serve_upstream().await;
+    let port = upstream.rsplit_once(':').unwrap().1.parse::<u16>().unwrap();
+
+    let egress = EgressProxy::start(EgressPolicy {
+        access: None,
+        url_policy: Some(Arc::new(UrlPolicy {
+            allow_hosts: vec!["127.0.0.1".to_string()],
+            ports: vec![port],
+            ..Default::default()
+        })),
+    })
+    .await
+    .unwrap();
+    let (status, _) = get_through(&egress, &format!("http://{}/", upstream)).await;
+    assert_eq!(status, 200);
+    // the port and the host of a tunnel are all the proxy sees
+    let (status, _) = connect_through(&egress, "127.0.0.1:1").await;
+    assert!(status.contains(" 403 "), "{}", status);
+    let (status, _) = connect_through(&egress, &format!("localhost:{}", port)).await;
+    assert!(status.contains(" 403 "), "{}", status);
+    let (status, _) = connect_through(&egress, &upstream).await;
+    assert!(status.contains(" 200 "), "{}", status);
+    assert_eq!(egress.denied(), 2);
+}
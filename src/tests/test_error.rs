@@ -0,0 +1,87 @@
+use crate::{create_router, AppState, Error, ErrorCode};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use std::sync::Arc;
+
+#[test]
+fn test_error_code() {
+    assert_eq!(
+        ErrorCode::NavigationTimeout.to_string(),
+        "NAVIGATION_TIMEOUT"
+    );
+    assert_eq!(
+        ErrorCode::NavigationTimeout.status(),
+        StatusCode::GATEWAY_TIMEOUT
+    );
+    assert_eq!(ErrorCode::DnsError.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        ErrorCode::TooManySessions.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // errors created by status keep a code
+    assert_eq!(
+        Error::new(StatusCode::BAD_REQUEST, "invalid lang").code(),
+        ErrorCode::InvalidParams
+    );
+    assert_eq!(
+        Error::new(StatusCode::IM_A_TEAPOT, "").code(),
+        ErrorCode::InternalError
+    );
+}
+
+#[tokio::test]
+async fn test_error_response() {
+    let resp = Error::from_code(ErrorCode::TooManySessions, "too many sessions")
+        .with_session("s1")
+        .into_response();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers()["Retry-After"], "5");
+    assert_eq!(resp.headers()["Content-Type"], "application/json");
+
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "TOO_MANY_SESSIONS");
+    assert_eq!(body["message"], "too many sessions");
+    assert_eq!(body["session_id"], "s1");
+    assert!(body["request_id"].is_null());
+    assert!(body.get("details").is_none());
+}
+
+#[tokio::test]
+async fn test_error_request_id() {
+    let addr = "127.0.0.1:9024";
+    let state = Arc::new(AppState::new("/tmp/browserlify_unittest".to_string(), 0));
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(state)).await });
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .get(format!("http://{}/screen/missing", addr))
+        .header("X-Request-Id", "req-1")
+        .send()
+        .await
+        .expect("get api/screen fail");
+    assert_eq!(resp.status(), 404);
+    assert_eq!(resp.headers()["x-request-id"], "req-1");
+    let body: serde_json::Value =
+        serde_json::from_str(&resp.text().await.expect("get error fail")).unwrap();
+    assert_eq!(body["code"], "SESSION_NOT_FOUND");
+    assert_eq!(body["request_id"], "req-1");
+
+    // a request id is generated without one
+    let resp = client
+        .get(format!("http://{}/text", addr))
+        .query(&[("url", "ftp://example.com/")])
+        .send()
+        .await
+        .expect("get api/text fail");
+    assert_eq!(resp.status(), 400);
+    let request_id = resp.headers()["x-request-id"].to_str().unwrap().to_string();
+    let body: serde_json::Value =
+        serde_json::from_str(&resp.text().await.expect("get error fail")).unwrap();
+    assert_eq!(body["code"], "INVALID_PARAMS");
+    assert_eq!(body["request_id"], request_id.as_str());
+}
@@ -0,0 +1,50 @@
+use crate::intercept::{Mock, MockResponse, Rewrite, RewriteRule, UrlPattern};
+
+#[test]
+fn test_url_pattern() {
+    let glob = UrlPattern::try_from("https://*.example.com/api/*").unwrap();
+    assert!(glob.is_match("https://www.example.com/api/users?id=1"));
+    assert!(!glob.is_match("https://www.example.com/static/app.js"));
+    assert!(!glob.is_match("http://www.example.com/api/users"));
+
+    let regex = UrlPattern::try_from(r"/\.(png|jpe?g)$/").unwrap();
+    assert!(regex.is_match("https://example.com/a.jpeg"));
+    assert!(!regex.is_match("https://example.com/a.jpeg?x=1"));
+
+    assert!(UrlPattern::try_from("/(unclosed/").is_err());
+}
+
+#[test]
+fn test_mock() {
+    let mock: MockResponse = serde_json::from_str(
+        r#"{"url": "*/api/*", "status": 201, "headers": {"Content-Type": "application/json"}, "body": "{}"}"#,
+    )
+    .unwrap();
+    assert!(Mock::try_from(&mock).is_ok());
+
+    let mock: MockResponse =
+        serde_json::from_str(r#"{"url": "*/logo.png", "body_base64": "%%%"}"#).unwrap();
+    assert!(Mock::try_from(&mock).is_err());
+
+    let mock: MockResponse = serde_json::from_str(r#"{"url": "*", "status": 1000}"#).unwrap();
+    assert!(Mock::try_from(&mock).is_err());
+}
+
+#[test]
+fn test_rewrite() {
+    let rule: RewriteRule = serde_json::from_str(
+        r#"{"from": "https://api.example.com", "to": "https://staging-api.example.com:8443/"}"#,
+    )
+    .unwrap();
+    let rewrite = Rewrite::try_from(&rule).unwrap();
+    assert_eq!(
+        rewrite.apply("https://api.example.com/v1/users?id=1"),
+        Some("https://staging-api.example.com:8443/v1/users?id=1".to_string())
+    );
+    assert_eq!(rewrite.apply("https://api.example.com.evil.org/"), None);
+    assert_eq!(rewrite.apply("http://api.example.com/"), None);
+
+    let rule: RewriteRule =
+        serde_json::from_str(r#"{"from": "data:text/plain,x", "to": "https://a.com"}"#).unwrap();
+    assert!(Rewrite::try_from(&rule).is_err());
+}
@@ -0,0 +1,20 @@
+use crate::overlays::{dismiss_banners_js, remove_selectors_js, COOKIE_BANNERS};
+use std::collections::HashSet;
+
+#[test]
+fn test_cookie_banner_rules() {
+    assert!(!COOKIE_BANNERS.is_empty());
+    let names: HashSet<_> = COOKIE_BANNERS.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names.len(), COOKIE_BANNERS.len());
+    for rule in COOKIE_BANNERS.iter() {
+        assert!(!rule.detect.is_empty(), "{} without detect", rule.name);
+        assert!(
+            rule.accept.is_some() || rule.reject.is_some() || !rule.hide.is_empty(),
+            "{} can't be dismissed",
+            rule.name
+        );
+    }
+
+    assert!(dismiss_banners_js(false).contains("#onetrust-banner-sdk"));
+    assert!(remove_selectors_js(".modal, \"#x\"").contains(r##"".modal, \"#x\"""##));
+}
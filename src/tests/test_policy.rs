@@ -0,0 +1,103 @@
+use crate::policy::{PolicyConfig, UrlPolicy};
+use crate::{create_router, AppState};
+use std::sync::Arc;
+
+fn check(policy: &UrlPolicy, url: &str) -> Result<(), String> {
+    policy.check(&url::Url::parse(url).unwrap())
+}
+
+#[test]
+fn test_url_policy() {
+    let policy = UrlPolicy {
+        allow_hosts: vec!["example.com".to_string(), "*.example.org".to_string()],
+        deny_hosts: vec!["admin.example.org".to_string()],
+        schemes: vec!["https".to_string()],
+        ports: vec![443],
+        ..Default::default()
+    };
+    assert!(check(&policy, "https://example.com/").is_ok());
+    assert!(check(&policy, "https://EXAMPLE.com./path").is_ok());
+    assert!(check(&policy, "https://www.example.org/").is_ok());
+    assert!(check(&policy, "https://a.b.example.org/").is_ok());
+    assert!(check(&policy, "https://example.org/").is_err());
+    assert!(check(&policy, "https://badexample.org/").is_err());
+    assert!(check(&policy, "https://www.example.com/").is_err());
+    assert!(check(&policy, "https://admin.example.org/").is_err());
+    assert!(check(&policy, "http://example.com/").is_err());
+    assert!(check(&policy, "https://example.com:8443/").is_err());
+
+    let policy = UrlPolicy {
+        deny_hosts: vec!["*.internal.example".to_string()],
+        ..Default::default()
+    };
+    assert!(check(&policy, "http://anything.test:8080/").is_ok());
+    assert!(check(&policy, "http://db.internal.example/").is_err());
+}
+
+#[test]
+fn test_policy_config() {
+    let config: PolicyConfig = serde_json::from_str(
+        r#"{
+            "default": {"allow_hosts": ["*.example.com"], "deny_hosts": ["admin.example.com"], "schemes": ["https"]},
+            "keys": {
+                "team-a": {"allow_hosts": ["*"], "schemes": ["http", "https"], "ports": [443]},
+                "team-b": {"deny_hosts": ["www.example.com"]}
+            }
+        }"#,
+    )
+    .unwrap();
+    let default = config.policy(None).unwrap();
+    assert!(check(&default, "https://www.example.com/").is_ok());
+    assert!(check(&default, "https://example.org/").is_err());
+    assert!(check(&default, "https://admin.example.com/").is_err());
+
+    // a key narrows the default, it can't widen it
+    let team = config.policy(Some("team-a")).unwrap();
+    assert!(check(&team, "https://www.example.com/").is_ok());
+    assert!(check(&team, "https://example.org/").is_err());
+    assert!(check(&team, "https://admin.example.com/").is_err());
+    assert!(check(&team, "http://www.example.com/").is_err());
+    assert!(check(&team, "https://www.example.com:8443/").is_err());
+    assert!(team.check_host("admin.example.com", 443).is_err());
+
+    // both deny lists apply
+    let team = config.policy(Some("team-b")).unwrap();
+    assert!(check(&team, "https://docs.example.com/").is_ok());
+    assert!(check(&team, "https://www.example.com/").is_err());
+    assert!(check(&team, "https://admin.example.com/").is_err());
+    assert!(config.policy(Some("team-c")).is_err());
+
+    assert!(serde_json::from_str::<PolicyConfig>(r#"{"default": {"allow": []}}"#).is_err());
+}
+
+#[tokio::test]
+async fn test_url_policy_render() {
+    let addr = "127.0.0.1:9021";
+    let config: PolicyConfig =
+        serde_json::from_str(r#"{"default": {"allow_hosts": ["example.com"]}, "keys": {"k": {}}}"#)
+            .unwrap();
+    let state = Arc::new(
+        AppState::new("/tmp/browserlify_unittest".to_string(), 0)
+            .allow_private_ip()
+            .with_url_policy(config),
+    );
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(state)).await });
+
+    let client = reqwest::Client::new();
+    for (url, api_key, status) in [
+        ("http://example.org/", None, 403),
+        ("http://example.org/", Some("unknown"), 403),
+        ("ftp://example.com/", None, 400),
+    ] {
+        let mut query = vec![("url", url)];
+        query.extend(api_key.map(|key| ("api_key", key)));
+        let resp = client
+            .get(format!("http://{}/text", addr))
+            .query(&query)
+            .send()
+            .await
+            .expect("get api/text fail");
+        assert_eq!(resp.status(), status, "{} {:?}", url, api_key);
+    }
+}
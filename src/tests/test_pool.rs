@@ -0,0 +1,76 @@
+use crate::devices::get_device;
+use crate::pool::{is_poolable, BrowserPool, Lease, PoolOptions};
+use crate::session::SessionOption;
+
+#[test]
+fn test_poolable() {
+    let opt = SessionOption::default();
+    assert!(is_poolable(&opt, None, Lease::Context));
+    assert!(is_poolable(&opt, None, Lease::Exclusive));
+    assert!(!is_poolable(
+        &opt,
+        get_device("ipad").as_ref(),
+        Lease::Context
+    ));
+
+    for opt in [
+        SessionOption {
+            lang: Some("de-DE".to_string()),
+            ..SessionOption::default()
+        },
+        SessionOption {
+            uuid: Some("profile".to_string()),
+            enable_cache: true,
+            cleanup: false,
+            userdatadir_expire: Some(3600),
+            ..SessionOption::default()
+        },
+    ] {
+        assert!(!is_poolable(&opt, None, Lease::Context), "{:?}", opt);
+    }
+
+    // a browser context has its own proxy
+    let opt = SessionOption {
+        proxy: Some("http://10.0.0.1:3128".try_into().unwrap()),
+        ..SessionOption::default()
+    };
+    assert!(is_poolable(&opt, None, Lease::Context));
+    assert!(!is_poolable(&opt, None, Lease::Exclusive));
+}
+
+#[tokio::test]
+async fn test_pool_options() {
+    let pool = BrowserPool::new(
+        PoolOptions {
+            min: 4,
+            max: 2,
+            max_uses: 10,
+            contexts: 0,
+        },
+        "/tmp/browserlify_unittest",
+    );
+    assert_eq!(pool.options().min, 2);
+    assert_eq!(pool.options().contexts, 1);
+    assert_eq!(pool.available(), 0);
+    assert_eq!(pool.launched(), 0);
+    // nothing launched yet, the session launches its own browser
+    assert!(pool.acquire_shared().is_none());
+    assert!(pool.acquire_exclusive().is_none());
+}
+
+#[tokio::test]
+async fn test_pool_launch_limit() {
+    let pool = BrowserPool::new(
+        PoolOptions {
+            min: 0,
+            max: 0,
+            max_uses: 0,
+            contexts: 1,
+        },
+        "/tmp/browserlify_unittest",
+    );
+    // no place left under max, the session launches its own browser
+    assert!(pool.launch_shared().await.is_none());
+    assert!(pool.launch_exclusive().await.is_none());
+    assert_eq!(pool.launched(), 0);
+}
@@ -0,0 +1,111 @@
+use crate::queue::{Priority, QueueOptions};
+use crate::{create_router, AppState, ErrorCode};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn queued_state(max_len: usize, wait_timeout: Duration) -> Arc<AppState> {
+    Arc::new(
+        AppState::new("/tmp/browserlify_unittest".to_string(), 1).with_queue(QueueOptions {
+            max_len,
+            wait_timeout,
+        }),
+    )
+}
+
+#[tokio::test]
+async fn test_queue_admit() {
+    let state = queued_state(0, Duration::from_secs(1));
+    let first = state.queue.admit(Priority::Batch).await.unwrap();
+    assert!(state.is_full());
+
+    // no queue, answered at once
+    let e = state.queue.admit(Priority::Batch).await.unwrap_err();
+    assert_eq!(e.code(), ErrorCode::TooManySessions);
+    drop(first);
+    assert!(!state.is_full());
+
+    let state = queued_state(1, Duration::from_millis(100));
+    let _first = state.queue.admit(Priority::Batch).await.unwrap();
+    let e = state.queue.admit(Priority::Batch).await.unwrap_err();
+    assert!(e.to_string().contains("waited 100ms"), "{}", e);
+    let stats = state.queue.stats();
+    assert_eq!(stats["stats"]["timed_out"], 1);
+    assert_eq!(stats["length"], 0);
+}
+
+#[tokio::test]
+async fn test_queue_priority() {
+    let state = queued_state(2, Duration::from_secs(5));
+    let first = state.queue.admit(Priority::Batch).await.unwrap();
+
+    let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut waiters = Vec::new();
+    for priority in [Priority::Batch, Priority::Interactive] {
+        let (state, order_tx) = (state.clone(), order_tx.clone());
+        waiters.push(tokio::spawn(async move {
+            let admission = state.queue.admit(priority).await.unwrap();
+            order_tx.send(priority).unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(admission);
+        }));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(state.queue.stats()["length"], 2);
+
+    // full queue, Retry-After is set by the error
+    let e = state.queue.admit(Priority::Interactive).await.unwrap_err();
+    assert_eq!(e.code(), ErrorCode::TooManySessions);
+
+    drop(first);
+    for waiter in waiters {
+        waiter.await.unwrap();
+    }
+    // the interactive waiter went first though it came later
+    assert_eq!(order_rx.recv().await, Some(Priority::Interactive));
+    assert_eq!(order_rx.recv().await, Some(Priority::Batch));
+    let stats = state.queue.stats();
+    assert_eq!(stats["stats"]["admitted"], 3);
+    assert_eq!(stats["stats"]["rejected"], 1);
+    assert_eq!(stats["pending"], 0);
+}
+
+#[tokio::test]
+async fn test_queue_canceled() {
+    let state = queued_state(1, Duration::from_secs(5));
+    let first = state.queue.admit(Priority::Batch).await.unwrap();
+
+    // the client went away while waiting
+    let waiting = tokio::spawn({
+        let state = state.clone();
+        async move { state.queue.admit(Priority::Batch).await.map(|_| ()) }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    waiting.abort();
+    waiting.await.ok();
+    assert_eq!(state.queue.stats()["length"], 0);
+
+    drop(first);
+    assert_eq!(state.queue.pending(), 0);
+    let _next = state.queue.admit(Priority::Batch).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_session_stats() {
+    let addr = "127.0.0.1:9027";
+    let state = queued_state(4, Duration::from_secs(1));
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    let router = create_router(state.clone());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let _first = state.queue.admit(Priority::Batch).await.unwrap();
+    let resp = reqwest::get(format!("http://{}/stats", addr))
+        .await
+        .expect("get api/stats fail");
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value =
+        serde_json::from_str(&resp.text().await.expect("get stats fail")).unwrap();
+    assert_eq!(body["max_sessions"], 1);
+    assert_eq!(body["full"], true);
+    assert_eq!(body["queue"]["max_length"], 4);
+    assert_eq!(body["queue"]["pending"], 1);
+}
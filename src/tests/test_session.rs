@@ -0,0 +1,236 @@
+use crate::policy::PolicyConfig;
+use crate::session::{
+    background_cleanup_task, ExpiryPolicy, ExpiryReason, Session, SessionOption, SessionType,
+};
+use crate::tests::serve_test_http_server;
+use crate::{create_router, expiry_policy, AppState, Cli};
+use chromiumoxide::Browser;
+use clap::Parser;
+use futures::StreamExt;
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn test_expiry_policy() {
+    let server = ExpiryPolicy {
+        max_lifetime: Some(Duration::from_secs(600)),
+        idle_timeout: None,
+    };
+    let opt = SessionOption {
+        max_lifetime: Some(3_600_000),
+        idle_timeout: Some(30_000),
+        ..SessionOption::default()
+    };
+    // a session can't outlive the server policy
+    let policy = server.with_overrides(&opt);
+    assert_eq!(policy.max_lifetime, Some(Duration::from_secs(600)));
+    assert_eq!(policy.idle_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(server.with_overrides(&SessionOption::default()), server);
+
+    let now = SystemTime::now();
+    let minutes_ago = |m: u64| now - Duration::from_secs(m * 60);
+    assert_eq!(policy.expired(minutes_ago(5), now), None);
+    assert_eq!(
+        policy.expired(minutes_ago(5), minutes_ago(1)),
+        Some(ExpiryReason::IdleTimeout)
+    );
+    assert_eq!(
+        policy.expired(minutes_ago(11), now),
+        Some(ExpiryReason::MaxLifetime)
+    );
+    assert_eq!(
+        ExpiryPolicy::default().expired(minutes_ago(60 * 24), minutes_ago(60)),
+        None
+    );
+}
+
+#[test]
+fn test_default_expiry() {
+    let now = SystemTime::now();
+    let seconds_ago = |s: u64| now - Duration::from_secs(s);
+
+    // with the default flags a session lives up to --max-timeout
+    let policy = expiry_policy(&Cli::parse_from(["browserlify"]));
+    assert_eq!(policy.max_lifetime, Some(Duration::from_secs(60)));
+    assert_eq!(policy.expired(seconds_ago(30), now), None);
+    assert_eq!(
+        policy.expired(seconds_ago(61), now),
+        Some(ExpiryReason::MaxLifetime)
+    );
+
+    let policy = expiry_policy(&Cli::parse_from(["browserlify", "--max-timeout", "5000"]));
+    assert_eq!(policy.max_lifetime, Some(Duration::from_secs(5)));
+    let policy = expiry_policy(&Cli::parse_from(["browserlify", "--max-lifetime", "0"]));
+    assert_eq!(policy.expired(seconds_ago(3600), seconds_ago(3600)), None);
+}
+
+fn idle_session(id: &str, idle_timeout: Duration) -> (Session, tokio::sync::oneshot::Receiver<()>) {
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    let session = Session {
+        id: id.to_string(),
+        r#type: SessionType::Headless,
+        data_dir: format!("/tmp/browserlify_unittest/{}", id),
+        device: None,
+        cleanup: false,
+        enable_cache: false,
+        shutdown_tx: RefCell::new(Some(shutdown_tx)),
+        browser: RefCell::new(None),
+        headless_handler: RefCell::new(None),
+        expiry: ExpiryPolicy {
+            max_lifetime: None,
+            idle_timeout: Some(idle_timeout),
+        },
+        expired: RefCell::new(None),
+        admission: None,
+        created_at: SystemTime::now(),
+        updated_at: RefCell::new(SystemTime::now()),
+        endpoint: String::new(),
+        context: None,
+        remote_handler: None,
+    };
+    (session, shutdown_rx)
+}
+
+#[tokio::test]
+async fn test_session_expire() {
+    let state = Arc::new(AppState::new("/tmp/browserlify_unittest".to_string(), 0));
+    let (idle, idle_rx) = idle_session("idle", Duration::from_millis(500));
+    let (busy, mut busy_rx) = idle_session("busy", Duration::from_millis(500));
+    state.sessions.lock().unwrap().extend([idle, busy]);
+    tokio::spawn(background_cleanup_task(state.clone()));
+
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        state
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.id == "busy")
+            .for_each(|s| s.touch_updatedat());
+    }
+    // the owner of the idle session is told to stop, it stays listed with the reason
+    assert!(idle_rx.await.is_err());
+    assert!(busy_rx
+        .try_recv()
+        .is_err_and(|e| e == tokio::sync::oneshot::error::TryRecvError::Empty));
+    {
+        let sessions = state.sessions.lock().unwrap();
+        let idle = sessions.iter().find(|s| s.id == "idle").unwrap();
+        assert_eq!(*idle.expired.borrow(), Some(ExpiryReason::IdleTimeout));
+    }
+
+    // and dropped from the list if its owner doesn't remove it
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let ids: Vec<_> = state
+        .sessions
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|s| s.id.clone())
+        .collect();
+    assert!(!ids.contains(&"idle".to_string()));
+}
+
+#[tokio::test]
+async fn test_session_url_policy_new_target() {
+    let addr = "127.0.0.1:9028";
+    let (http_shutdown_tx, http_shutdown_rx) = tokio::sync::oneshot::channel();
+    let http_addr = serve_test_http_server(http_shutdown_rx)
+        .await
+        .expect("serve http fail");
+    let config: PolicyConfig =
+        serde_json::from_str(r#"{"default": {"allow_hosts": ["127.0.0.1"]}}"#).unwrap();
+    let state = Arc::new(
+        AppState::new("/tmp/browserlify_unittest".to_string(), 0)
+            .allow_private_ip()
+            .with_url_policy(config),
+    );
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(state)).await });
+
+    let (mut browser, mut handler) = Browser::connect(format!("ws://{}/", addr))
+        .await
+        .expect("connect fail");
+    tokio::spawn(async move { while handler.next().await.is_some() {} });
+
+    let page = browser
+        .new_page(format!("http://{}/", http_addr))
+        .await
+        .expect("new page fail");
+    let content = page.content().await.expect("content fail");
+    assert!(content.contains("MADE WITH CARE IN HANGZHOU"));
+
+    // the same server under a host the policy doesn't allow
+    let port = http_addr.rsplit_once(':').unwrap().1;
+    let denied_url = format!("http://localhost:{}/", port);
+    let fetched: String = page
+        .evaluate(format!(
+            "fetch('{}', {{ mode: 'no-cors' }}).then(() => 'loaded', () => 'failed')",
+            denied_url
+        ))
+        .await
+        .expect("evaluate fail")
+        .into_value()
+        .unwrap();
+    assert_eq!(fetched, "failed");
+
+    // a target opened after the session started, attached late by the interceptor
+    if let Ok(target) = browser.new_page(denied_url.as_str()).await {
+        let content = target.content().await.unwrap_or_default();
+        assert!(!content.contains("MADE WITH CARE IN HANGZHOU"));
+    }
+
+    browser.close().await.ok();
+    drop(http_shutdown_tx);
+}
+
+#[tokio::test]
+async fn test_session_screen() {
+    let addr = "127.0.0.1:9029";
+    let (http_shutdown_tx, http_shutdown_rx) = tokio::sync::oneshot::channel();
+    let http_addr = serve_test_http_server(http_shutdown_rx)
+        .await
+        .expect("serve http fail");
+    let state =
+        Arc::new(AppState::new("/tmp/browserlify_unittest".to_string(), 0).allow_private_ip());
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn({
+        let state = state.clone();
+        async move { axum::serve(listener, create_router(state)).await }
+    });
+
+    let (mut browser, mut handler) = Browser::connect(format!("ws://{}/", addr))
+        .await
+        .expect("connect fail");
+    tokio::spawn(async move { while handler.next().await.is_some() {} });
+    let page = browser
+        .new_page(format!("http://{}/", http_addr))
+        .await
+        .expect("new page fail");
+    let session_id = state.sessions.lock().unwrap()[0].id.clone();
+    let screen = |id: String| async move {
+        reqwest::get(format!("http://{}/screen/{}", addr, id))
+            .await
+            .expect("get api/screen fail")
+    };
+
+    let resp = screen(session_id.clone()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "image/png");
+    // the screenshot only disconnects, the session goes on
+    let content = page.content().await.expect("content after screen fail");
+    assert!(content.contains("MADE WITH CARE IN HANGZHOU"));
+
+    for page in browser.pages().await.expect("pages fail") {
+        page.close().await.ok();
+    }
+    let resp = screen(session_id).await;
+    assert_eq!(resp.status(), 404);
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(body["code"], "NOT_FOUND");
+
+    browser.close().await.ok();
+    drop(http_shutdown_tx);
+}
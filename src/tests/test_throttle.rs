@@ -0,0 +1,49 @@
+use crate::throttle::ThrottleOptions;
+
+#[test]
+fn test_throttle_options() {
+    assert!(ThrottleOptions::default().build().unwrap().is_none());
+
+    for profile in ["offline", "slow-3g", "fast-3g", "4g"] {
+        let opt = ThrottleOptions {
+            profile: Some(profile),
+            ..Default::default()
+        };
+        assert!(opt.build().unwrap().is_some());
+    }
+
+    let opt = ThrottleOptions {
+        profile: Some("5g"),
+        ..Default::default()
+    };
+    assert!(opt.build().is_err());
+
+    let opt = ThrottleOptions {
+        profile: Some("custom"),
+        ..Default::default()
+    };
+    assert!(opt.build().is_err());
+
+    let opt = ThrottleOptions {
+        profile: Some("custom"),
+        download_kbps: Some(1000.0),
+        latency: Some(-1.0),
+        ..Default::default()
+    };
+    assert!(opt.build().is_err());
+
+    let opt = ThrottleOptions {
+        profile: Some("custom"),
+        download_kbps: Some(1000.0),
+        latency: Some(100.0),
+        cpu_slowdown: Some(4.0),
+        ..Default::default()
+    };
+    assert!(opt.build().unwrap().is_some());
+
+    let opt = ThrottleOptions {
+        cpu_slowdown: Some(0.5),
+        ..Default::default()
+    };
+    assert!(opt.build().is_err());
+}
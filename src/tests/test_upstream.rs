@@ -0,0 +1,66 @@
+use crate::upstream::{NavigationFailure, UpstreamResponse};
+use crate::ErrorCode;
+use axum::http::StatusCode;
+
+#[test]
+fn test_navigation_failure() {
+    for (text, failure) in [
+        ("net::ERR_NAME_NOT_RESOLVED", NavigationFailure::Dns),
+        ("net::ERR_DNS_TIMED_OUT", NavigationFailure::Dns),
+        ("net::ERR_CERT_AUTHORITY_INVALID", NavigationFailure::Tls),
+        ("net::ERR_SSL_PROTOCOL_ERROR", NavigationFailure::Tls),
+        ("net::ERR_CONNECTION_REFUSED", NavigationFailure::Connection),
+        ("net::ERR_CONNECTION_TIMED_OUT", NavigationFailure::Timeout),
+        ("net::ERR_ACCESS_DENIED", NavigationFailure::Blocked),
+        ("net::ERR_ABORTED", NavigationFailure::Other),
+    ] {
+        assert_eq!(
+            NavigationFailure::from_error_text(text),
+            failure,
+            "{}",
+            text
+        );
+    }
+    assert_eq!(NavigationFailure::Dns.code(), ErrorCode::DnsError);
+    assert_eq!(
+        NavigationFailure::Timeout.code().status(),
+        StatusCode::GATEWAY_TIMEOUT
+    );
+    assert_eq!(NavigationFailure::Tls.code().to_string(), "TLS_ERROR");
+}
+
+#[test]
+fn test_upstream_response() {
+    let response = UpstreamResponse {
+        status: 404,
+        url: "https://example.com/missing".to_string(),
+        headers: serde_json::json!({"Content-Type": "text/html", "ETag": "\"v1\""}),
+    };
+    assert!(response.is_error());
+    assert_eq!(response.header("etag").as_deref(), Some("\"v1\""));
+    assert_eq!(response.header("x-missing"), None);
+}
+
+#[test]
+fn test_repeated_header() {
+    let response = UpstreamResponse {
+        status: 200,
+        url: "https://example.com/".to_string(),
+        headers: serde_json::json!({
+            "Set-Cookie": "a=1; Path=/\nb=2; HttpOnly",
+            "Link": "</style.css>; rel=preload\n\n</app.js>; rel=preload",
+            "X-Broken": "ok\nbad\u{7f}value",
+        }),
+    };
+    assert_eq!(
+        response.header_values("set-cookie"),
+        vec!["a=1; Path=/", "b=2; HttpOnly"]
+    );
+    assert_eq!(
+        response.header_values("Link"),
+        vec!["</style.css>; rel=preload", "</app.js>; rel=preload"]
+    );
+    // the values a response can't carry are dropped, not the render
+    assert_eq!(response.header_values("X-Broken"), vec!["ok"]);
+    assert!(response.header_values("ETag").is_empty());
+}
@@ -0,0 +1,129 @@
+use crate::validate::FieldErrors;
+use crate::{create_router, AppState, ErrorCode};
+use std::sync::Arc;
+
+#[test]
+fn test_field_errors() {
+    let mut errors = FieldErrors::default();
+    errors.range("quality", Some(80), 0, 100);
+    errors.one_of("format", Some("png"), &["png", "jpeg"]);
+    errors.conflicts("clip", false, "full_page", true);
+    assert!(errors.is_empty());
+    assert!(errors.into_result().is_ok());
+
+    let mut errors = FieldErrors::default();
+    errors.range("scale", Some(3.0), 0.1, 2.0);
+    errors.one_of("format", Some("gif"), &["png", "jpeg"]);
+    errors.conflicts("clip", true, "full_page", true);
+    let e = errors.into_result().unwrap_err();
+    assert_eq!(e.code(), ErrorCode::InvalidParams);
+    assert!(e.to_string().contains("scale must be between 0.1 and 2"));
+    assert!(e.to_string().contains("clip conflicts with full_page"));
+}
+
+async fn error_body(resp: reqwest::Response) -> serde_json::Value {
+    assert_eq!(resp.status(), 400);
+    serde_json::from_str(&resp.text().await.expect("get error fail")).unwrap()
+}
+
+#[tokio::test]
+async fn test_validate_render_params() {
+    let addr = "127.0.0.1:9025";
+    let state = Arc::new(AppState::new("/tmp/browserlify_unittest".to_string(), 0));
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(state)).await });
+
+    let client = reqwest::Client::new();
+    // used to panic the handler
+    let resp = client
+        .get(format!("http://{}/screenshot", addr))
+        .query(&[("url", "http://example.org/"), ("clip", "a,b")])
+        .send()
+        .await
+        .expect("get api/screenshot fail");
+    let body = error_body(resp).await;
+    assert_eq!(body["code"], "INVALID_PARAMS");
+    assert_eq!(body["details"]["errors"][0]["field"], "clip");
+
+    let resp = client
+        .post(format!("http://{}/screenshot", addr))
+        .header("Content-Type", "application/json")
+        .body(
+            serde_json::json!({
+                "url": "http://example.org/",
+                "device": "nokia-3310",
+                "format": "gif",
+                "quality": 101,
+                "clip": "0,0,100,100",
+                "full_page": true,
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .expect("post api/screenshot fail");
+    let body = error_body(resp).await;
+    let fields: Vec<_> = body["details"]["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(fields, ["device", "format", "quality", "clip"]);
+
+    // quality only conflicts with a png asked for, not with the default format,
+    // a valid render of localhost is refused by the access policy instead
+    for (format, status) in [(Some("png"), 400), (None, 403), (Some("jpeg"), 403)] {
+        let mut query = vec![("url", "http://localhost/"), ("quality", "80")];
+        query.extend(format.map(|format| ("format", format)));
+        let resp = client
+            .get(format!("http://{}/screenshot", addr))
+            .query(&query)
+            .send()
+            .await
+            .expect("get api/screenshot fail");
+        assert_eq!(resp.status(), status, "{:?}", format);
+    }
+
+    let resp = client
+        .get(format!("http://{}/pdf", addr))
+        .query(&[
+            ("url", "http://example.org/"),
+            ("paper", "b4"),
+            ("scale", "5"),
+        ])
+        .send()
+        .await
+        .expect("get api/pdf fail");
+    let body = error_body(resp).await;
+    assert_eq!(body["details"]["errors"].as_array().unwrap().len(), 2);
+
+    // rejected while deserializing
+    for timeout in ["-1", "soon"] {
+        let resp = client
+            .get(format!("http://{}/text", addr))
+            .query(&[("url", "http://example.org/"), ("timeout", timeout)])
+            .send()
+            .await
+            .expect("get api/text fail");
+        assert_eq!(error_body(resp).await["code"], "INVALID_PARAMS");
+    }
+    let resp = client
+        .post(format!("http://{}/text", addr))
+        .header("Content-Type", "application/json")
+        .body(r#"{"url": "http://example.org/", "timeout": -1}"#)
+        .send()
+        .await
+        .expect("post api/text fail");
+    assert_eq!(error_body(resp).await["code"], "INVALID_PARAMS");
+
+    for query in ["device=nokia-3310", "idle_timeout=0"] {
+        let resp = tokio_tungstenite::connect_async(format!("ws://{}/?{}", addr, query)).await;
+        match resp {
+            Err(tokio_tungstenite::tungstenite::Error::Http(resp)) => {
+                assert_eq!(resp.status(), 400)
+            }
+            r => panic!("unexpected {:?}", r.map(|_| ())),
+        }
+    }
+}
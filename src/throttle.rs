@@ -0,0 +1,103 @@
+use chromiumoxide::cdp::browser_protocol::emulation::SetCpuThrottlingRateParams;
+use chromiumoxide::cdp::browser_protocol::network::{EmulateNetworkConditionsParams, EnableParams};
+use chromiumoxide::error::CdpError;
+use chromiumoxide::Page;
+
+/// The throttling options of a render or a headless session
+#[derive(Debug, Default)]
+pub(crate) struct ThrottleOptions<'a> {
+    // offline, slow-3g, fast-3g, 4g or custom
+    pub profile: Option<&'a str>,
+    // custom profile, in kilobits per second and milliseconds
+    pub download_kbps: Option<f64>,
+    pub upload_kbps: Option<f64>,
+    pub latency: Option<f64>,
+    // 1 is no slowdown, 4 is 4x slower
+    pub cpu_slowdown: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Throttle {
+    network: Option<EmulateNetworkConditionsParams>,
+    cpu_slowdown: Option<f64>,
+}
+
+// bytes per second, with the same 0.8/0.9 factors as the devtools presets
+fn kbps(kbps: f64, factor: f64) -> f64 {
+    kbps * 1000.0 / 8.0 * factor
+}
+
+impl ThrottleOptions<'_> {
+    pub fn build(&self) -> Result<Option<Throttle>, String> {
+        let network = match self.profile {
+            None => None,
+            Some("offline") => Some(EmulateNetworkConditionsParams::new(true, 0, -1, -1)),
+            Some("slow-3g") => Some(EmulateNetworkConditionsParams::new(
+                false,
+                2000,
+                kbps(500.0, 0.8),
+                kbps(500.0, 0.8),
+            )),
+            Some("fast-3g") => Some(EmulateNetworkConditionsParams::new(
+                false,
+                562.5,
+                kbps(1600.0, 0.9),
+                kbps(750.0, 0.9),
+            )),
+            Some("4g") => Some(EmulateNetworkConditionsParams::new(
+                false,
+                165,
+                kbps(9000.0, 0.9),
+                kbps(1500.0, 0.9),
+            )),
+            Some("custom") => {
+                if self.download_kbps.is_none()
+                    && self.upload_kbps.is_none()
+                    && self.latency.is_none()
+                {
+                    return Err(
+                        "custom throttle needs download_kbps, upload_kbps or latency".to_string(),
+                    );
+                }
+                let values = [self.download_kbps, self.upload_kbps, self.latency];
+                if values.iter().flatten().any(|v| !v.is_finite() || *v < 0.0) {
+                    return Err("throttle values must be positive".to_string());
+                }
+                Some(EmulateNetworkConditionsParams::new(
+                    false,
+                    self.latency.unwrap_or_default(),
+                    self.download_kbps.map(|v| kbps(v, 1.0)).unwrap_or(-1.0),
+                    self.upload_kbps.map(|v| kbps(v, 1.0)).unwrap_or(-1.0),
+                ))
+            }
+            Some(profile) => return Err(format!("unknown throttle profile {}", profile)),
+        };
+
+        if let Some(rate) = self.cpu_slowdown {
+            if !(1.0..=100.0).contains(&rate) {
+                return Err("cpu_slowdown must be between 1 and 100".to_string());
+            }
+        }
+
+        match (network, self.cpu_slowdown) {
+            (None, None) => Ok(None),
+            (network, cpu_slowdown) => Ok(Some(Throttle {
+                network,
+                cpu_slowdown,
+            })),
+        }
+    }
+}
+
+impl Throttle {
+    pub async fn apply(&self, page: &Page) -> Result<(), CdpError> {
+        if let Some(network) = &self.network {
+            page.execute(EnableParams::default()).await?;
+            page.execute(network.clone()).await?;
+        }
+        if let Some(rate) = self.cpu_slowdown {
+            page.execute(SetCpuThrottlingRateParams::new(rate)).await?;
+        }
+        Ok(())
+    }
+}
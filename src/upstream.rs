@@ -0,0 +1,129 @@
+use crate::error::ErrorCode;
+use axum::http::HeaderValue;
+use chromiumoxide::cdp::browser_protocol::network::{EventResponseReceived, ResourceType};
+use chromiumoxide::error::CdpError;
+use chromiumoxide::Page;
+use futures::StreamExt;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+/// The response of the main document, the last one after redirects and navigations
+#[derive(Debug, Clone)]
+pub(crate) struct UpstreamResponse {
+    pub status: i64,
+    pub url: String,
+    pub headers: serde_json::Value,
+}
+
+impl UpstreamResponse {
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers.as_object()?.iter().find_map(|(k, v)| {
+            k.eq_ignore_ascii_case(name)
+                .then(|| v.as_str().unwrap_or_default().to_string())
+        })
+    }
+
+    /// Each value of a header, chrome joins the repeated ones (Set-Cookie, Link) with a newline,
+    /// values that can't be sent back in a response are skipped
+    pub fn header_values(&self, name: &str) -> Vec<String> {
+        self.header(name)
+            .unwrap_or_default()
+            .split('\n')
+            .filter(|value| !value.is_empty() && HeaderValue::from_str(value).is_ok())
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.status >= 400
+    }
+}
+
+/// Records the main document responses of a page until dropped
+pub(crate) struct ResponseRecorder {
+    response: Arc<Mutex<Option<UpstreamResponse>>>,
+    handle: JoinHandle<()>,
+}
+
+impl ResponseRecorder {
+    pub async fn start(page: &Page) -> Result<Self, CdpError> {
+        let mut events = page.event_listener::<EventResponseReceived>().await?;
+        let main_frame = page.mainframe().await?;
+        let response = Arc::new(Mutex::new(None));
+        let recorded = response.clone();
+        let handle = tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if event.r#type != ResourceType::Document || event.frame_id != main_frame {
+                    continue;
+                }
+                recorded.lock().unwrap().replace(UpstreamResponse {
+                    status: event.response.status,
+                    url: event.response.url.clone(),
+                    headers: event.response.headers.inner().clone(),
+                });
+            }
+        });
+        Ok(Self { response, handle })
+    }
+
+    pub fn response(&self) -> Option<UpstreamResponse> {
+        self.response.lock().unwrap().clone()
+    }
+}
+
+impl Drop for ResponseRecorder {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Why the browser couldn't load the page, from the net error of the navigation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NavigationFailure {
+    Dns,
+    Tls,
+    Connection,
+    Timeout,
+    Blocked,
+    Other,
+}
+
+impl NavigationFailure {
+    /// `net::ERR_NAME_NOT_RESOLVED` and friends, see chrome's net_error_list.h
+    pub fn from_error_text(text: &str) -> Self {
+        let name = text.trim().trim_start_matches("net::");
+        match name {
+            "ERR_NAME_NOT_RESOLVED" | "ERR_NAME_RESOLUTION_FAILED" => Self::Dns,
+            _ if name.starts_with("ERR_DNS_") => Self::Dns,
+            _ if name.starts_with("ERR_CERT_")
+                || name.starts_with("ERR_SSL_")
+                || name == "ERR_BAD_SSL_CLIENT_AUTH_CERT" =>
+            {
+                Self::Tls
+            }
+            "ERR_TIMED_OUT" | "ERR_CONNECTION_TIMED_OUT" => Self::Timeout,
+            "ERR_CONNECTION_REFUSED"
+            | "ERR_CONNECTION_RESET"
+            | "ERR_CONNECTION_CLOSED"
+            | "ERR_CONNECTION_FAILED"
+            | "ERR_ADDRESS_UNREACHABLE"
+            | "ERR_EMPTY_RESPONSE"
+            | "ERR_INTERNET_DISCONNECTED"
+            | "ERR_PROXY_CONNECTION_FAILED"
+            | "ERR_TUNNEL_CONNECTION_FAILED" => Self::Connection,
+            "ERR_BLOCKED_BY_CLIENT" | "ERR_ACCESS_DENIED" => Self::Blocked,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Dns => ErrorCode::DnsError,
+            Self::Tls => ErrorCode::TlsError,
+            Self::Connection => ErrorCode::ConnectionError,
+            Self::Timeout => ErrorCode::NavigationTimeout,
+            Self::Blocked => ErrorCode::NavigationBlocked,
+            Self::Other => ErrorCode::NavigationError,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{Error, ErrorCode};
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use serde_json::json;
+
+/// Collects the invalid fields of a request, answered together as one 400
+#[derive(Debug, Default)]
+pub(crate) struct FieldErrors {
+    errors: Vec<(&'static str, String)>,
+}
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &'static str, message: impl ToString) {
+        self.errors.push((field, message.to_string()));
+    }
+
+    pub fn check(&mut self, field: &'static str, valid: bool, message: &str) {
+        if !valid {
+            self.add(field, message);
+        }
+    }
+
+    #[cfg(feature = "content")]
+    pub fn range<T: PartialOrd + std::fmt::Display>(
+        &mut self,
+        field: &'static str,
+        value: Option<T>,
+        min: T,
+        max: T,
+    ) {
+        if let Some(value) = value {
+            if value < min || value > max {
+                self.add(field, format!("must be between {} and {}", min, max));
+            }
+        }
+    }
+
+    #[cfg(feature = "content")]
+    pub fn one_of(&mut self, field: &'static str, value: Option<&str>, allowed: &[&str]) {
+        if let Some(value) = value {
+            if !allowed.contains(&value) {
+                self.add(field, format!("must be one of {}", allowed.join(", ")));
+            }
+        }
+    }
+
+    #[cfg(feature = "content")]
+    pub fn conflicts(&mut self, field: &'static str, set: bool, other: &str, other_set: bool) {
+        if set && other_set {
+            self.add(field, format!("conflicts with {}", other));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let message = self
+            .errors
+            .iter()
+            .map(|(field, message)| format!("{} {}", field, message))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let errors = self
+            .errors
+            .iter()
+            .map(|(field, message)| json!({ "field": field, "message": message }))
+            .collect::<Vec<_>>();
+        Err(Error::from_code(ErrorCode::InvalidParams, &message)
+            .with_details(json!({ "errors": errors })))
+    }
+}
+
+impl From<QueryRejection> for Error {
+    fn from(rejection: QueryRejection) -> Self {
+        Error::from_code(ErrorCode::InvalidParams, &rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(rejection: JsonRejection) -> Self {
+        Error::from_code(ErrorCode::InvalidParams, &rejection.body_text())
+    }
+}
@@ -0,0 +1,124 @@
+use chromiumoxide::Page;
+use std::time::{Duration, SystemTime};
+use tokio::time;
+
+const RETRY_INTERVAL: u64 = 100;
+
+/// A condition checked inside the page until it holds
+#[derive(Debug)]
+pub(crate) enum WaitCondition<'a> {
+    Selector(&'a str),
+    Visible(&'a str),
+    Hidden(&'a str),
+    Text(&'a str),
+    // a javascript regex matched against location.href
+    Url(&'a str),
+    // a custom event dispatched on window, see `event_recorder_js`
+    Event(&'a str),
+    // a javascript expression, truthy or resolving to truthy
+    Function(&'a str),
+}
+
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+const IS_VISIBLE_JS: &str = "(el) => !!el && el.getClientRects().length > 0
+    && (!el.checkVisibility || el.checkVisibility({ opacityProperty: true, visibilityProperty: true }))";
+
+/// Record the events dispatched on window, installed before the page scripts run
+pub(crate) fn event_recorder_js(name: &str) -> String {
+    format!(
+        "(() => {{
+            const events = window.__browserlifyEvents = window.__browserlifyEvents || new Set();
+            window.addEventListener({name}, () => events.add({name}));
+        }})()",
+        name = quote(name)
+    )
+}
+
+impl WaitCondition<'_> {
+    pub fn describe(&self) -> String {
+        match self {
+            WaitCondition::Selector(s) => format!("selector {}", s),
+            WaitCondition::Visible(s) => format!("visible {}", s),
+            WaitCondition::Hidden(s) => format!("hidden {}", s),
+            WaitCondition::Text(s) => format!("text {}", s),
+            WaitCondition::Url(s) => format!("url {}", s),
+            WaitCondition::Event(s) => format!("event {}", s),
+            WaitCondition::Function(s) => format!("function {}", s),
+        }
+    }
+
+    // an expression true once the condition holds, may be a promise
+    fn check_js(&self) -> String {
+        match self {
+            WaitCondition::Selector(s) => format!("document.querySelector({}) !== null", quote(s)),
+            WaitCondition::Visible(s) => {
+                format!("({})(document.querySelector({}))", IS_VISIBLE_JS, quote(s))
+            }
+            WaitCondition::Hidden(s) => {
+                format!("!({})(document.querySelector({}))", IS_VISIBLE_JS, quote(s))
+            }
+            WaitCondition::Text(s) => format!(
+                "!!document.body && document.body.innerText.includes({})",
+                quote(s)
+            ),
+            WaitCondition::Url(s) => format!("new RegExp({}).test(location.href)", quote(s)),
+            WaitCondition::Event(s) => format!(
+                "!!window.__browserlifyEvents && window.__browserlifyEvents.has({})",
+                quote(s)
+            ),
+            WaitCondition::Function(s) => format!("({})", s.trim().trim_end_matches(';')),
+        }
+    }
+
+    // checks on dom changes and once per frame, without a cdp call per check
+    fn poll_js(&self) -> String {
+        format!(
+            "new Promise((resolve) => {{
+                const check = async () => {{ try {{ return !!(await ({check})); }} catch (e) {{ return false; }} }};
+                let done = false;
+                const finish = () => {{ done = true; observer.disconnect(); resolve(true); }};
+                const test = () => {{ if (!done) check().then((ok) => ok && !done && finish()); }};
+                const observer = new MutationObserver(test);
+                observer.observe(document, {{ childList: true, subtree: true, attributes: true, characterData: true }});
+                const frame = () => {{
+                    if (done) return;
+                    test();
+                    document.hidden ? setTimeout(frame, 50) : requestAnimationFrame(frame);
+                }};
+                frame();
+            }})",
+            check = self.check_js()
+        )
+    }
+}
+
+/// Wait until the condition holds, the caller bounds it with a timeout
+pub(crate) async fn wait_condition(
+    cmd: &str,
+    url: &str,
+    page: &Page,
+    condition: &WaitCondition<'_>,
+) {
+    let st = SystemTime::now();
+    let poll = condition.poll_js();
+    loop {
+        match page.evaluate(poll.as_str()).await {
+            Ok(_) => break,
+            // the document was replaced by a navigation, poll the new one
+            Err(e) => {
+                log::debug!("{} {} wait {} error: {}", cmd, url, condition.describe(), e);
+                time::sleep(Duration::from_millis(RETRY_INTERVAL)).await;
+            }
+        }
+    }
+    log::info!(
+        "{} {} wait {} done usage: {:?}",
+        cmd,
+        url,
+        condition.describe(),
+        st.elapsed().unwrap_or_default()
+    );
+}